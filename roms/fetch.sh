@@ -0,0 +1,34 @@
+#!/bin/sh
+# Downloads the conformance suites the harness tests run against into roms/. They aren't
+# checked in: they're big, and they aren't ours to redistribute. The tests that run them are
+# ignored by default, run them with `cargo test -- --ignored` once this has fetched them.
+set -eu
+cd "$(dirname "$0")"
+
+fetch() {
+    mkdir -p "$(dirname "$2")"
+    [ -s "$2" ] || curl -fsSL -o "$2" "$1"
+}
+
+# Klaus Dormann's 6502 functional and interrupt tests, the default builds
+klaus=https://github.com/Klaus2m5/6502_65C02_functional_tests/raw/master/bin_files
+fetch "$klaus/6502_functional_test.bin" klaus/6502_functional_test.bin
+fetch "$klaus/6502_interrupt_test.bin" klaus/6502_interrupt_test.bin
+
+# nestest and Nintendulator's golden log of it
+fetch https://www.qmtpro.com/~nes/misc/nestest.nes nestest.nes
+fetch https://www.qmtpro.com/~nes/misc/nestest.log nestest.log
+
+# blargg's instruction tests, the NROM singles: all_instrs.nes and official_only.nes are MMC1
+blargg=https://github.com/christopherpow/nes-test-roms/raw/master/instr_test-v5/rom_singles
+for rom in 01-basics 02-implied 03-immediate 04-zero_page 05-zp_xy 06-absolute 07-abs_xy \
+    08-ind_x 09-ind_y 10-branches 11-stack 12-jmp_jsr 13-rts 14-rti 15-brk 16-special; do
+    fetch "$blargg/$rom.nes" "blargg/$rom.nes"
+done
+
+# SingleStepTests, ten thousand cases for each NMOS 6502 opcode
+single_step=https://github.com/SingleStepTests/65x02/raw/main/6502/v1
+for opcode in $(seq 0 255); do
+    name=$(printf '%02x' "$opcode")
+    fetch "$single_step/$name.json" "single-step/$name.json"
+done
@@ -0,0 +1,658 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::assembler::expression::Evaluator;
+use crate::cpu::cpu::CpuVariant;
+use crate::cpu::opcodes::{self, AddressingMode, Mnemonic};
+use crate::memory::bus::Bus;
+use crate::symbols::symbols::SymbolTable;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// 1-based source line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+/// A run of bytes assembled to consecutive addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// The output of the assembler, one segment per `.org`
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable,
+}
+
+impl Program {
+    /// where execution starts, the first byte assembled
+    pub fn entry(&self) -> u16 {
+        self.segments
+            .first()
+            .map_or(Assembler::DEFAULT_ORIGIN, |segment| segment.origin)
+    }
+    /// writes every segment to the bus
+    pub fn load_into<B: Bus>(&self, bus: &mut B) {
+        for segment in &self.segments {
+            for (offset, byte) in segment.bytes.iter().enumerate() {
+                bus.write(segment.origin.wrapping_add(offset as u16), *byte);
+            }
+        }
+    }
+    /// Patches an image, a ROM bank for example, that is mapped at `image_origin`.
+    /// Fails with the first address that falls outside of it, leaving the image untouched
+    pub fn patch(&self, image: &mut [u8], image_origin: u16) -> Result<(), u16> {
+        let start = image_origin as usize;
+        for segment in &self.segments {
+            let origin = segment.origin as usize;
+            if origin < start {
+                return Err(segment.origin);
+            }
+            if origin + segment.bytes.len() > start + image.len() {
+                return Err((start + image.len()).max(origin) as u16);
+            }
+        }
+        for segment in &self.segments {
+            let offset = segment.origin as usize - start;
+            image[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Assembles `source`, panicking with the assembler's error message when it doesn't assemble.
+/// Meant for tests: `Cpu::with_program(asm!("LDA #$42\n STA $0200"))`, or
+/// `asm!(CpuVariant::Cmos65C02, "STZ $10")` for another CPU's instruction set
+#[macro_export]
+macro_rules! asm {
+    ($source:expr) => {
+        $crate::assembler::assembler::assemble($source).unwrap_or_else(|error| panic!("{}", error))
+    };
+    ($variant:expr, $source:expr) => {
+        $crate::assembler::assembler::assemble_for($variant, $source)
+            .unwrap_or_else(|error| panic!("{}", error))
+    };
+}
+
+/// assembles for the NES's 2A03
+pub fn assemble(source: &str) -> Result<Program, AssemblyError> {
+    assemble_for(CpuVariant::Ricoh2A03, source)
+}
+
+/// assembles with the instruction set of `variant`
+pub fn assemble_for(variant: CpuVariant, source: &str) -> Result<Program, AssemblyError> {
+    let mut assembler = Assembler::new(variant);
+    assembler.pass(source, Pass::Layout)?;
+    assembler.pass(source, Pass::Emit)?;
+    let mut symbols = SymbolTable::new();
+    for (name, address) in &assembler.labels {
+        symbols.insert(*address, name);
+    }
+    Ok(Program {
+        segments: assembler.segments,
+        symbols,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    /// works out where everything goes, forward references aren't known yet
+    Layout,
+    /// everything is known, emits the bytes
+    Emit,
+}
+
+/// A two pass assembler for the full instruction set of any `CpuVariant`, the NMOS parts'
+/// undocumented opcodes included.
+///
+/// Lines look like `label: MNEMONIC operand ; comment`. Labels starting with `@` are local to
+/// the last global label. Supported directives are `.org`, `.byte`/`.db` (which also takes
+/// strings) and `.word`/`.dw`, and `NAME = expression` defines a constant. Operands follow
+/// the usual syntax, zero page is picked automatically when the address is known to fit
+struct Assembler {
+    variant: CpuVariant,
+    pass: Pass,
+    program_counter: u32,
+    symbols: HashMap<String, i64>,
+    /// names defined so far in this pass, to catch duplicates
+    defined: HashSet<String>,
+    labels: Vec<(String, u16)>,
+    scope: String,
+    /// the mode picked for each instruction during layout, so emitting agrees on sizes
+    modes: Vec<AddressingMode>,
+    instruction: usize,
+    segments: Vec<Segment>,
+}
+
+impl Assembler {
+    /// where code goes until the first `.org`
+    pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+    fn new(variant: CpuVariant) -> Self {
+        Self {
+            variant,
+            pass: Pass::Layout,
+            program_counter: Self::DEFAULT_ORIGIN as u32,
+            symbols: HashMap::new(),
+            defined: HashSet::new(),
+            labels: Vec::new(),
+            scope: String::new(),
+            modes: Vec::new(),
+            instruction: 0,
+            segments: Vec::new(),
+        }
+    }
+
+    fn pass(&mut self, source: &str, pass: Pass) -> Result<(), AssemblyError> {
+        self.pass = pass;
+        self.program_counter = Self::DEFAULT_ORIGIN as u32;
+        self.scope.clear();
+        self.defined.clear();
+        self.instruction = 0;
+        for (index, line) in source.lines().enumerate() {
+            self.line(line).map_err(|message| AssemblyError {
+                line: index + 1,
+                message,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let mut line = strip_comment(line).trim();
+        if let Some((label, rest)) = split_label(line) {
+            self.define_label(label)?;
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some((name, expression)) = split_assignment(line) {
+            let value = self.evaluate(expression)?;
+            let name = self.qualify(name);
+            self.define(&name, value)?;
+            return Ok(());
+        }
+        let (keyword, operand) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+        if keyword.starts_with('.') {
+            self.directive(&keyword.to_lowercase(), operand)
+        } else {
+            self.instruction(keyword, operand)
+        }
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        self.evaluator().qualify(name)
+    }
+    fn evaluator(&self) -> Evaluator<'_> {
+        Evaluator {
+            symbols: &self.symbols,
+            scope: &self.scope,
+            program_counter: self.program_counter as i64,
+        }
+    }
+    /// evaluates an expression, undefined symbols are only an error once layout is done
+    fn evaluate(&self, text: &str) -> Result<Option<i64>, String> {
+        match self.evaluator().evaluate(text)? {
+            None if self.pass == Pass::Emit => Err(format!("undefined symbol in `{}`", text)),
+            value => Ok(value),
+        }
+    }
+    /// a constant defined in terms of a forward reference stays undefined until emitting
+    fn define(&mut self, name: &str, value: Option<i64>) -> Result<(), String> {
+        if !self.defined.insert(name.to_string()) {
+            return Err(format!("`{}` is defined twice", name));
+        }
+        if let Some(value) = value {
+            self.symbols.insert(name.to_string(), value);
+        }
+        Ok(())
+    }
+    fn define_label(&mut self, label: &str) -> Result<(), String> {
+        if !label.starts_with('@') {
+            self.scope = label.to_string();
+        }
+        let name = self.qualify(label);
+        self.define(&name, Some(self.program_counter as i64))?;
+        if self.pass == Pass::Layout {
+            self.labels.push((name, self.program_counter as u16));
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str, operand: &str) -> Result<(), String> {
+        match directive {
+            ".org" => {
+                let origin = self
+                    .evaluate(operand)?
+                    .ok_or("`.org` can't use a forward reference")?;
+                if !(0..=0xFFFF).contains(&origin) {
+                    return Err(format!("origin ${:X} is out of range", origin));
+                }
+                self.program_counter = origin as u32;
+                Ok(())
+            }
+            ".byte" | ".db" => {
+                for argument in split_arguments(operand) {
+                    if let Some(text) = argument.strip_prefix('"') {
+                        let text = text.strip_suffix('"').ok_or("unterminated string")?;
+                        for byte in text.bytes() {
+                            self.emit(byte)?;
+                        }
+                    } else {
+                        let value = self.evaluate(argument)?;
+                        self.emit(to_byte(value)?)?;
+                    }
+                }
+                Ok(())
+            }
+            ".word" | ".dw" => {
+                for argument in split_arguments(operand) {
+                    let [lo, hi] = to_word(self.evaluate(argument)?)?.to_le_bytes();
+                    self.emit(lo)?;
+                    self.emit(hi)?;
+                }
+                Ok(())
+            }
+            _ => Err(format!("unknown directive `{}`", directive)),
+        }
+    }
+
+    fn instruction(&mut self, mnemonic: &str, operand: &str) -> Result<(), String> {
+        let mnemonic = parse_mnemonic(self.variant, mnemonic)?;
+        let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+        let upper = operand.to_uppercase();
+        let (candidates, expression): (&[AddressingMode], &str) = if operand.is_empty() {
+            (&[AddressingMode::Implied, AddressingMode::Accumulator], "")
+        } else if upper == "A" {
+            (&[AddressingMode::Accumulator], "")
+        } else if let Some(expression) = operand.strip_prefix('#') {
+            (&[AddressingMode::Immediate], expression)
+        } else if upper.starts_with('(') && upper.ends_with(",X)") {
+            (
+                &[AddressingMode::IndirectX, AddressingMode::AbsoluteIndirectX],
+                &operand[1..operand.len() - 3],
+            )
+        } else if upper.starts_with('(') && upper.ends_with("),Y") {
+            (&[AddressingMode::IndirectY], &operand[1..operand.len() - 3])
+        } else if upper.starts_with('(') && closing_paren(&operand) == Some(operand.len() - 1) {
+            (
+                &[AddressingMode::ZeroPageIndirect, AddressingMode::Indirect],
+                &operand[1..operand.len() - 1],
+            )
+        } else if upper.ends_with(",X") {
+            (
+                &[AddressingMode::ZeroPageX, AddressingMode::AbsoluteX],
+                &operand[..operand.len() - 2],
+            )
+        } else if upper.ends_with(",Y") {
+            (
+                &[AddressingMode::ZeroPageY, AddressingMode::AbsoluteY],
+                &operand[..operand.len() - 2],
+            )
+        } else if split_arguments(&operand).len() == 2 {
+            // BBR and BBS take `zero page,target`, both evaluated when emitting
+            (&[AddressingMode::ZeroPageRelative], "")
+        } else {
+            (
+                &[
+                    AddressingMode::Relative,
+                    AddressingMode::ZeroPage,
+                    AddressingMode::Absolute,
+                ],
+                &operand[..],
+            )
+        };
+        let value = if expression.is_empty() {
+            None
+        } else {
+            self.evaluate(expression)?
+        };
+
+        let mode = match self.pass {
+            Pass::Layout => {
+                let supported: Vec<AddressingMode> = candidates
+                    .iter()
+                    .copied()
+                    .filter(|mode| encode(self.variant, mnemonic, *mode).is_some())
+                    .collect();
+                let fits_zero_page = matches!(value, Some(0..=0xFF));
+                let mode = match supported[..] {
+                    [] => return Err(format!("{} doesn't support that addressing mode", mnemonic)),
+                    [mode] => mode,
+                    // zero page when it's known to fit, absolute otherwise
+                    [zero_page, absolute, ..] => {
+                        if fits_zero_page {
+                            zero_page
+                        } else {
+                            absolute
+                        }
+                    }
+                };
+                self.modes.push(mode);
+                mode
+            }
+            Pass::Emit => self.modes[self.instruction],
+        };
+        self.instruction += 1;
+
+        let byte = encode(self.variant, mnemonic, mode).expect("mode was checked during layout");
+        self.emit(byte)?;
+        match mode.operand_len() {
+            0 => {}
+            1 if mode == AddressingMode::Relative => self.branch(value)?,
+            1 => self.emit(to_byte(value)?)?,
+            _ if mode == AddressingMode::ZeroPageRelative => {
+                let arguments = split_arguments(&operand);
+                let zero_page = self.evaluate(arguments[0])?;
+                let target = self.evaluate(arguments[1])?;
+                self.emit(to_byte(zero_page)?)?;
+                self.branch(target)?;
+            }
+            _ => {
+                let [lo, hi] = to_word(value)?.to_le_bytes();
+                self.emit(lo)?;
+                self.emit(hi)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// emits the offset from the end of the instruction to `target`
+    fn branch(&mut self, target: Option<i64>) -> Result<(), String> {
+        let offset = target.map(|target| target - (self.program_counter as i64 + 1));
+        if let Some(offset @ (..=-129 | 128..)) = offset {
+            return Err(format!("branch is out of range by {} bytes", offset));
+        }
+        self.emit(offset.unwrap_or(0) as u8)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.program_counter > 0xFFFF {
+            return Err("assembled past $FFFF".to_string());
+        }
+        if self.pass == Pass::Emit {
+            let address = self.program_counter as u16;
+            match self.segments.last_mut() {
+                Some(segment)
+                    if segment.origin as usize + segment.bytes.len() == address as usize =>
+                {
+                    segment.bytes.push(byte)
+                }
+                _ => self.segments.push(Segment {
+                    origin: address,
+                    bytes: vec![byte],
+                }),
+            }
+        }
+        self.program_counter += 1;
+        Ok(())
+    }
+}
+
+/// the opcode for `mnemonic` in `mode` on `variant`, preferring the documented encoding when
+/// there are several
+fn encode(variant: CpuVariant, mnemonic: Mnemonic, mode: AddressingMode) -> Option<u8> {
+    let matching = |official: bool| {
+        (0..=0xFF).find(|&byte| {
+            let opcode = opcodes::decode_for(variant, byte);
+            opcode.mnemonic == mnemonic && opcode.mode == mode && opcode.official == official
+        })
+    };
+    matching(true).or_else(|| matching(false))
+}
+
+fn parse_mnemonic(variant: CpuVariant, text: &str) -> Result<Mnemonic, String> {
+    let upper = text.to_uppercase();
+    (0..=0xFF)
+        .map(|byte| opcodes::decode_for(variant, byte).mnemonic)
+        .find(|mnemonic| mnemonic.to_string() == upper)
+        .ok_or_else(|| format!("unknown instruction `{}`", text))
+}
+
+/// bytes accept -128..=255 so both signed and unsigned values can be written
+fn to_byte(value: Option<i64>) -> Result<u8, String> {
+    match value.unwrap_or(0) {
+        value @ -0x80..=0xFF => Ok(value as u8),
+        value => Err(format!("${:X} doesn't fit in a byte", value)),
+    }
+}
+fn to_word(value: Option<i64>) -> Result<u16, String> {
+    match value.unwrap_or(0) {
+        value @ -0x8000..=0xFFFF => Ok(value as u16),
+        value => Err(format!("${:X} doesn't fit in a word", value)),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = None;
+    for (index, c) in line.char_indices() {
+        match (c, quoted) {
+            ('"' | '\'', None) => quoted = Some(c),
+            (c, Some(quote)) if c == quote => quoted = None,
+            (';', None) => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// splits `label: rest` into its parts
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    is_identifier(label.trim()).then(|| (label.trim(), rest))
+}
+
+/// splits `NAME = expression` into its parts
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let (name, expression) = line.split_once('=')?;
+    is_identifier(name.trim()).then(|| (name.trim(), expression))
+}
+
+/// splits directive arguments on the commas that aren't inside strings or parentheses
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                arguments.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(text[start..].trim());
+    arguments.retain(|argument| !argument.is_empty());
+    arguments
+}
+
+/// index of the parenthesis closing the one that opens `text`
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        let program = assemble(source).unwrap();
+        assert_eq!(program.segments.len(), 1);
+        program.segments[0].bytes.clone()
+    }
+
+    #[test]
+    fn addressing_modes() {
+        let source = "
+            LDA #$42
+            LDA $10
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $1234,X
+            LDA $1234,Y
+            JMP ($FFFC)
+            LDA ($20,X)
+            LDA ( $20 ), y
+            ASL
+            ROL A
+            NOP
+            LAX $10
+        ";
+        assert_eq!(
+            bytes(source),
+            [
+                0xA9, 0x42, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12,
+                0xB9, 0x34, 0x12, 0x6C, 0xFC, 0xFF, 0xA1, 0x20, 0xB1, 0x20, 0x0A, 0x2A, 0xEA, 0xA7,
+                0x10,
+            ]
+        );
+    }
+
+    #[test]
+    fn cmos_instructions() {
+        let source = "
+            STZ $11
+            SMB0 $11
+            BBS0 $11,skip
+            STP
+    skip:   LDA ($12)
+            BRA skip
+            JMP ($0020,X)
+            JMP ($0020)
+            INC
+            PHX
+        ";
+        let program = assemble_for(CpuVariant::Cmos65C02, source).unwrap();
+        assert_eq!(
+            program.segments[0].bytes,
+            [
+                0x64, 0x11, 0x87, 0x11, 0x8F, 0x11, 0x01, 0xDB, 0xB2, 0x12, 0x80, 0xFC, 0x7C, 0x20,
+                0x00, 0x6C, 0x20, 0x00, 0x1A, 0xDA,
+            ]
+        );
+        // the NMOS parts have none of them
+        assert_eq!(
+            assemble("STZ $11").unwrap_err().message,
+            "unknown instruction `STZ`"
+        );
+        assert_eq!(
+            assemble("LDA ($12)").unwrap_err().message,
+            "LDA doesn't support that addressing mode"
+        );
+    }
+
+    #[test]
+    fn labels_and_expressions() {
+        let source = "
+            SCREEN = $2000
+            Start:  LDX #<SCREEN + 3   ; low byte
+            @loop:  DEX
+                    BNE @loop
+                    JSR Later          ; forward reference stays absolute
+                    LDA Data,X
+            Later:  STA zero           ; forward constant
+                    RTS
+            @loop:  BEQ @loop
+            Data:   .byte 1, -1, \"hi\"
+                    .word Start, * + 2
+            zero = $10
+        ";
+        assert_eq!(
+            bytes(source),
+            [
+                0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x20, 0x0B, 0x06, 0xBD, 0x11, 0x06, 0x8D, 0x10, 0x00,
+                0x60, 0xF0, 0xFE, 0x01, 0xFF, 0x68, 0x69, 0x00, 0x06, 0x19, 0x06,
+            ]
+        );
+    }
+
+    #[test]
+    fn org_starts_new_segments() {
+        let program = assemble(".org $C000\nReset: JMP Reset\n.org $FFFC\n.word Reset").unwrap();
+        assert_eq!(
+            program.segments,
+            [
+                Segment {
+                    origin: 0xC000,
+                    bytes: vec![0x4C, 0x00, 0xC0]
+                },
+                Segment {
+                    origin: 0xFFFC,
+                    bytes: vec![0x00, 0xC0]
+                },
+            ]
+        );
+        assert_eq!(program.entry(), 0xC000);
+        assert_eq!(program.symbols.address("Reset"), Some(0xC000));
+
+        let mut bank = vec![0xFF; 0x4000];
+        assert_eq!(program.patch(&mut bank, 0xC000), Ok(()));
+        assert_eq!(bank[..3], [0x4C, 0x00, 0xC0]);
+        assert_eq!(bank[0x3FFC..], [0x00, 0xC0, 0xFF, 0xFF]);
+        assert_eq!(program.patch(&mut bank, 0xC001), Err(0xC000));
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        let cases = [
+            ("NOP\nFOO #1", 2, "unknown instruction `FOO`"),
+            ("STA #1", 1, "STA doesn't support that addressing mode"),
+            ("x: NOP\nx: NOP", 2, "`x` is defined twice"),
+            ("LDA missing", 1, "undefined symbol in `missing`"),
+            ("LDA #$100", 1, "$100 doesn't fit in a byte"),
+        ];
+        for (source, line, message) in cases {
+            assert_eq!(
+                assemble(source).unwrap_err(),
+                AssemblyError {
+                    line,
+                    message: message.to_string()
+                }
+            );
+        }
+        let far = "BNE far\n.org $0700\nfar: NOP";
+        assert_eq!(assemble(far).unwrap_err().line, 1);
+    }
+}
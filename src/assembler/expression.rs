@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+/// Evaluates assembler expressions: `$hex`, `%binary`, decimal and `'c'` literals, symbols,
+/// `*` for the current address, the unary operators `- ~ ! < >` (negate, not, logical not, low and
+/// high byte) and the binary operators `* / + - << >> & ^ | == != < > <= >= && ||`, loosest binding
+/// last. Comparisons and logical operators evaluate to 1 or 0.
+/// Evaluates to `Ok(None)` when a symbol isn't defined yet
+pub struct Evaluator<'a> {
+    pub symbols: &'a HashMap<String, i64>,
+    /// prefix for `@local` labels, the name of the last global label
+    pub scope: &'a str,
+    pub program_counter: i64,
+}
+
+type Value = Result<Option<i64>, String>;
+
+impl Evaluator<'_> {
+    pub fn evaluate(&self, text: &str) -> Value {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            evaluator: self,
+            tokens: &tokens,
+            position: 0,
+        };
+        let value = parser.binary(0)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected `{}` in `{}`", token, text.trim())),
+        }
+    }
+    /// the full name of a symbol, locals are stored as `Global@local`
+    pub fn qualify(&self, name: &str) -> String {
+        if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Symbol(name) => write!(f, "{}", name),
+            Token::Operator(operator) => write!(f, "{}", operator),
+        }
+    }
+}
+
+/// longer operators come first so `<<` isn't read as two `<`
+const OPERATORS: [&str; 21] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "&", "|", "^", "~", "!",
+    "<", ">", "(", ")",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let rest: String = chars[index..].iter().collect();
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == '$' || c == '%' || c.is_ascii_digit() {
+            let (radix, start) = match c {
+                '$' => (16, index + 1),
+                '%' => (2, index + 1),
+                _ => (10, index),
+            };
+            let mut end = start;
+            while end < chars.len() && chars[end].is_ascii_alphanumeric() {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("bad number `{}`", &rest[..end - index]))?;
+            tokens.push(Token::Number(value));
+            index = end;
+        } else if c == '\'' {
+            match chars.get(index + 1..index + 3) {
+                Some([value, '\'']) => tokens.push(Token::Number(*value as i64)),
+                _ => return Err(format!("bad character literal `{}`", rest)),
+            }
+            index += 3;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' {
+            let mut end = index + 1;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            tokens.push(Token::Symbol(chars[index..end].iter().collect()));
+            index = end;
+        } else if let Some(operator) = OPERATORS
+            .iter()
+            .find(|operator| rest.starts_with(**operator))
+        {
+            tokens.push(Token::Operator(operator));
+            index += operator.len();
+        } else {
+            return Err(format!("unexpected `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 9] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", ">", "<=", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+struct Parser<'a> {
+    evaluator: &'a Evaluator<'a>,
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn binary(&mut self, level: usize) -> Value {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            if !PRECEDENCE[level].contains(operator) {
+                break;
+            }
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = match (lhs, rhs) {
+                (Some(lhs), Some(rhs)) => Some(match *operator {
+                    "||" => (lhs != 0 || rhs != 0) as i64,
+                    "&&" => (lhs != 0 && rhs != 0) as i64,
+                    "==" => (lhs == rhs) as i64,
+                    "!=" => (lhs != rhs) as i64,
+                    "<" => (lhs < rhs) as i64,
+                    ">" => (lhs > rhs) as i64,
+                    "<=" => (lhs <= rhs) as i64,
+                    ">=" => (lhs >= rhs) as i64,
+                    "|" => lhs | rhs,
+                    "^" => lhs ^ rhs,
+                    "&" => lhs & rhs,
+                    "<<" => lhs << rhs,
+                    ">>" => lhs >> rhs,
+                    "+" => lhs + rhs,
+                    "-" => lhs - rhs,
+                    "*" => lhs * rhs,
+                    _ if rhs == 0 => return Err("division by zero".to_string()),
+                    _ => lhs / rhs,
+                }),
+                _ => None,
+            };
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Value {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Some(value)),
+            Some(Token::Symbol(name)) => {
+                let name = self.evaluator.qualify(&name);
+                Ok(self.evaluator.symbols.get(&name).copied())
+            }
+            Some(Token::Operator("*")) => Ok(Some(self.evaluator.program_counter)),
+            Some(Token::Operator("(")) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Operator(")")) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            Some(Token::Operator(operator @ ("-" | "~" | "!" | "<" | ">"))) => {
+                let value = self.unary()?;
+                Ok(value.map(|value| match operator {
+                    "-" => -value,
+                    "~" => !value,
+                    "!" => (value == 0) as i64,
+                    "<" => value & 0xFF,
+                    _ => (value >> 8) & 0xFF,
+                }))
+            }
+            Some(token) => Err(format!("unexpected `{}`", token)),
+            None => Err("expected a value".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates() {
+        let mut symbols = HashMap::new();
+        symbols.insert("Main".to_string(), 0xC000);
+        symbols.insert("Main@loop".to_string(), 0xC005);
+        let evaluator = Evaluator {
+            symbols: &symbols,
+            scope: "Main",
+            program_counter: 0x0600,
+        };
+        let cases = [
+            ("$10 + %101 * 2", 0x1A),
+            ("(1 + 2) * 3", 9),
+            (">Main", 0xC0),
+            ("<@loop + 1", 0x06),
+            ("* - 2", 0x05FE),
+            ("'A' | 1 << 7", 0xC1),
+            ("-1 & $FF", 0xFF),
+            ("Main == $C000 && <Main < 1", 1),
+            ("!(1 + 1 >= 3) || 0", 1),
+            ("$80 & $C0 != 0", 1),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(evaluator.evaluate(text), Ok(Some(expected)), "{}", text);
+        }
+        assert_eq!(evaluator.evaluate("Later + 1"), Ok(None));
+        assert!(evaluator.evaluate("1 +").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::cartridge::cartridge::{Cartridge, Mirroring};
+use crate::cartridge::eeprom::{Eeprom, EepromChip};
+use crate::cartridge::mapper::Mapper;
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+/// Mappers 16 and 159: Bandai's FCG boards, as the LZ93D50 ASIC with a serial EEPROM for saves.
+/// Sixteen registers mirrored across $6000-$FFFF, a 16K PRG bank at $8000 with the last bank
+/// fixed at $C000, eight 1K CHR banks and a 16 bit IRQ counter clocked by the CPU
+pub struct BandaiFcg {
+    prg_rom: Vec<u8>,
+    /// CHR-ROM, or 8K of CHR-RAM when the cartridge has none
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq: bool,
+    /// bit 7 of the EEPROM control register lets the chip drive the data bus
+    eeprom_readable: bool,
+    pub eeprom: Eeprom,
+}
+
+impl BandaiFcg {
+    pub fn new(cartridge: Cartridge, chip: EepromChip) -> Self {
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; Cartridge::CHR_BANK_LEN]
+        } else {
+            cartridge.chr_rom
+        };
+        Self {
+            prg_rom: cartridge.prg_rom,
+            chr,
+            chr_is_ram,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: cartridge.mirroring,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq: false,
+            eeprom_readable: false,
+            eeprom: Eeprom::new(chip),
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_banks[address as usize / 0x400] as usize;
+        (bank * 0x400 + address as usize % 0x400) % self.chr.len()
+    }
+}
+
+impl Mapper for BandaiFcg {
+    fn peek_prg(&self, address: u16) -> u8 {
+        match address {
+            // the EEPROM's data line shows up on bit 4, the rest is open bus
+            0x6000..=0x7FFF => ((self.eeprom_readable && self.eeprom.read()) as u8) << 4,
+            _ => self
+                .prg_offset(address)
+                .map_or(0, |offset| self.prg_rom[offset]),
+        }
+    }
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        match address & 0x0F {
+            register @ 0x0..=0x7 => self.chr_banks[register as usize] = value,
+            0x8 => self.prg_bank = value & 0x0F,
+            0x9 => {
+                self.mirroring = match value & 3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0xA => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_counter = self.irq_latch;
+                self.irq = false;
+            }
+            0xB => self.irq_latch = self.irq_latch & 0xFF00 | value as u16,
+            0xC => self.irq_latch = self.irq_latch & 0x00FF | (value as u16) << 8,
+            0xD => {
+                self.eeprom_readable = value & 0x80 != 0;
+                self.eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+            }
+            _ => {}
+        }
+    }
+    fn read_chr(&self, address: u16) -> u8 {
+        if self.chr_is_ram {
+            return self.chr[address as usize % self.chr.len()];
+        }
+        self.chr[self.chr_index(address)]
+    }
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[address as usize % len] = value;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn tick(&mut self, cycles: u32) {
+        if !self.irq_enabled {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.irq_counter == 0 {
+                self.irq = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+    fn chr_rom(&self) -> &[u8] {
+        if self.chr_is_ram {
+            &[]
+        } else {
+            &self.chr
+        }
+    }
+    fn prg_offset(&self, address: u16) -> Option<usize> {
+        let bank = match address {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            0xC000..=0xFFFF => self.prg_rom.len() / Cartridge::PRG_BANK_LEN - 1,
+            _ => return None,
+        };
+        let index = bank * Cartridge::PRG_BANK_LEN + address as usize % Cartridge::PRG_BANK_LEN;
+        Some(index % self.prg_rom.len())
+    }
+    fn chr_offset(&self, address: u16) -> Option<usize> {
+        (!self.chr_is_ram).then(|| self.chr_index(address))
+    }
+    fn battery(&self) -> Option<&[u8]> {
+        Some(&self.eeprom.data)
+    }
+    fn battery_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.eeprom.data)
+    }
+    fn save_state(&self, state: &mut StateWriter) {
+        if self.chr_is_ram {
+            state.block(&self.chr);
+        }
+        state.block(&self.chr_banks);
+        state.u8(self.prg_bank);
+        state.u8(match self.mirroring {
+            Mirroring::Vertical => 0,
+            Mirroring::Horizontal => 1,
+            Mirroring::SingleScreenLower => 2,
+            _ => 3,
+        });
+        state.bool(self.irq_enabled);
+        state.u16(self.irq_counter);
+        state.u16(self.irq_latch);
+        state.bool(self.irq);
+        state.bool(self.eeprom_readable);
+        self.eeprom.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.chr_is_ram {
+            state.block_into(&mut self.chr, "CHR-RAM")?;
+        }
+        state.block_into(&mut self.chr_banks, "CHR banks")?;
+        self.prg_bank = state.u8()?;
+        self.mirroring = match state.u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            3 => Mirroring::SingleScreenUpper,
+            _ => return Err(StateError::Invalid("mirroring")),
+        };
+        self.irq_enabled = state.bool()?;
+        self.irq_counter = state.u16()?;
+        self.irq_latch = state.u16()?;
+        self.irq = state.bool()?;
+        self.eeprom_readable = state.bool()?;
+        self.eeprom.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartridge() -> Cartridge {
+        Cartridge {
+            prg_rom: (0..8).flat_map(|bank| vec![bank; 0x4000]).collect(),
+            chr_rom: (0..16).flat_map(|bank| vec![bank; 0x400]).collect(),
+            mapper: 16,
+            mirroring: Mirroring::Horizontal,
+            battery: true,
+        }
+    }
+
+    #[test]
+    fn banking_and_irq() {
+        let mut mapper = BandaiFcg::new(cartridge(), EepromChip::C24C02);
+        assert_eq!(mapper.peek_prg(0xC000), 7);
+        mapper.write_prg(0x8008, 3);
+        mapper.write_prg(0x8003, 9);
+        mapper.write_prg(0x6009, 3);
+        assert_eq!(mapper.peek_prg(0x8000), 3);
+        assert_eq!(mapper.read_chr(0x0C00), 9);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        mapper.write_prg(0x800B, 10);
+        mapper.write_prg(0x800C, 0);
+        mapper.write_prg(0x800A, 1);
+        mapper.tick(10);
+        assert!(!mapper.irq());
+        mapper.tick(1);
+        assert!(mapper.irq());
+        mapper.write_prg(0x800A, 0);
+        assert!(!mapper.irq());
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::nes::nes::Nes;
+
+/// Keeps a cartridge's battery backed RAM or EEPROM in a `.sav` file next to the ROM.
+/// Saves go to a temporary file that's renamed over the old one once it's safely on disk, and
+/// the previous save is kept as `.sav.bak`, so a crash mid-write never loses both
+pub struct Battery {
+    pub path: PathBuf,
+    /// frames between checks for changes that need writing out
+    pub interval: u64,
+    frames: u64,
+    /// what's in the file, to skip writes when nothing changed
+    saved: Vec<u8>,
+}
+
+impl Battery {
+    /// `game.nes` saves to `game.sav`
+    pub fn for_rom(rom_path: impl AsRef<Path>) -> Self {
+        Self::new(rom_path.as_ref().with_extension("sav"))
+    }
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            interval: 60 * 10,
+            frames: 0,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Loads the save into the cartridge, false when it has no battery or there's no save yet.
+    /// A save of the wrong size fills what it can
+    pub fn load(&mut self, nes: &mut Nes) -> io::Result<bool> {
+        let Some(memory) = nes.cpu.bus.mapper.battery_mut() else {
+            return Ok(false);
+        };
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                self.saved = memory.to_vec();
+                return Ok(false);
+            }
+            Err(error) => return Err(error),
+        };
+        let len = bytes.len().min(memory.len());
+        memory[..len].copy_from_slice(&bytes[..len]);
+        self.saved = memory.to_vec();
+        Ok(true)
+    }
+
+    /// Call once per frame, writes the save out every `interval` frames if it changed
+    pub fn tick(&mut self, nes: &Nes) -> io::Result<()> {
+        self.frames += 1;
+        if self.frames.is_multiple_of(self.interval.max(1)) {
+            self.flush(nes)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the save out now if it changed since it was last written or loaded
+    pub fn flush(&mut self, nes: &Nes) -> io::Result<()> {
+        let Some(memory) = nes.cpu.bus.mapper.battery() else {
+            return Ok(());
+        };
+        if memory == self.saved.as_slice() {
+            return Ok(());
+        }
+        write_atomic(&self.path, memory)?;
+        self.saved = memory.to_vec();
+        Ok(())
+    }
+}
+
+/// writes `<path>.tmp`, moves the old file to `<path>.bak`, then renames the new one into place
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let with_suffix = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    let temporary = with_suffix(".tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    if path.exists() {
+        fs::copy(path, with_suffix(".bak"))?;
+    }
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battery_nes() -> Nes {
+        let mut rom = b"NES\x1A\x01\x01\x02\x00".to_vec();
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        Nes::from_ines(&rom).unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let directory = std::env::temp_dir().join(format!("battery-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let mut battery = Battery::for_rom(directory.join("game.nes"));
+        assert_eq!(battery.path, directory.join("game.sav"));
+
+        let mut nes = battery_nes();
+        assert!(!battery.load(&mut nes).unwrap());
+        battery.flush(&nes).unwrap();
+        assert!(!battery.path.exists());
+
+        nes.cpu.bus.mapper.write_prg(0x6000, 0x42);
+        battery.interval = 2;
+        battery.tick(&nes).unwrap();
+        assert!(!battery.path.exists());
+        battery.tick(&nes).unwrap();
+        assert_eq!(fs::read(&battery.path).unwrap()[0], 0x42);
+
+        nes.cpu.bus.mapper.write_prg(0x6000, 0x43);
+        battery.flush(&nes).unwrap();
+        assert_eq!(fs::read(directory.join("game.sav.bak")).unwrap()[0], 0x42);
+
+        let mut other = battery_nes();
+        assert!(Battery::for_rom(directory.join("game.nes"))
+            .load(&mut other)
+            .unwrap());
+        assert_eq!(other.cpu.bus.mapper.peek_prg(0x6000), 0x43);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let mut nes = Nes::from_ines(&rom).unwrap();
+        assert!(!battery.load(&mut nes).unwrap());
+    }
+}
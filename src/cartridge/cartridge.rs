@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::library;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    /// every nametable is the first 1K of VRAM
+    SingleScreenLower,
+    /// every nametable is the second 1K of VRAM
+    SingleScreenUpper,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// the file doesn't start with `NES<EOF>`
+    BadMagic,
+    /// the header promises more PRG/CHR data than the file holds
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// the header gives no PRG-ROM banks, so there's no code to run
+    NoPrgRom,
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an iNES image"),
+            Self::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "iNES image is truncated: expected {} bytes, got {}",
+                    expected, actual
+                )
+            }
+            Self::NoPrgRom => write!(f, "iNES image has no PRG-ROM"),
+            Self::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+/// A game cartridge as described by an iNES image
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    /// the cartridge has battery backed PRG-RAM at $6000-$7FFF
+    pub battery: bool,
+}
+
+impl Cartridge {
+    const MAGIC: [u8; 4] = *b"NES\x1A";
+    const HEADER_LEN: usize = 16;
+    const TRAINER_LEN: usize = 512;
+    pub const PRG_BANK_LEN: usize = 0x4000;
+    pub const CHR_BANK_LEN: usize = 0x2000;
+
+    /// CRC-32 of the PRG and CHR data, identifies the game regardless of its header
+    pub fn crc32(&self) -> u32 {
+        library::crc32(&[self.prg_rom.as_slice(), &self.chr_rom].concat())
+    }
+
+    /// MD5 of the PRG and CHR data, which is how FCEUX movies name the game they belong to
+    pub fn md5(&self) -> [u8; 16] {
+        md5::compute([self.prg_rom.as_slice(), &self.chr_rom].concat()).0
+    }
+
+    pub fn from_ines(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < Self::HEADER_LEN || bytes[0..4] != Self::MAGIC {
+            return Err(CartridgeError::BadMagic);
+        }
+        if bytes[4] == 0 {
+            return Err(CartridgeError::NoPrgRom);
+        }
+        let prg_len = bytes[4] as usize * Self::PRG_BANK_LEN;
+        let chr_len = bytes[5] as usize * Self::CHR_BANK_LEN;
+        let flags6 = bytes[6];
+        let flags7 = bytes[7];
+
+        let mut start = Self::HEADER_LEN;
+        if flags6 & 0b0100 != 0 {
+            start += Self::TRAINER_LEN;
+        }
+        let expected = start + prg_len + chr_len;
+        if bytes.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let mirroring = if flags6 & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0b0001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        Ok(Self {
+            prg_rom: bytes[start..start + prg_len].to_vec(),
+            chr_rom: bytes[start + prg_len..expected].to_vec(),
+            mapper: (flags7 & 0xF0) | (flags6 >> 4),
+            mirroring,
+            battery: flags6 & 0b0010 != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::assembler::assembler::Program;
+
+    /// an NROM image with CHR-RAM and 32K of PRG-ROM holding `program`, assembled for $8000
+    pub(crate) fn nrom(program: &Program) -> Vec<u8> {
+        let mut rom = b"NES\x1A\x02\x00\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prg = vec![0; 0x8000];
+        program.patch(&mut prg, 0x8000).unwrap();
+        rom.extend(prg);
+        rom
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let mut rom = b"NES\x1A\x00\x01\x00\x00".to_vec();
+        rom.resize(16 + 0x2000, 0);
+        assert!(matches!(
+            Cartridge::from_ines(&rom),
+            Err(CartridgeError::NoPrgRom)
+        ));
+        rom[4] = 1;
+        assert!(matches!(
+            Cartridge::from_ines(&rom),
+            Err(CartridgeError::Truncated {
+                expected: 0x6010,
+                actual: 0x2010
+            })
+        ));
+        assert!(matches!(
+            Cartridge::from_ines(b"NES"),
+            Err(CartridgeError::BadMagic)
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+/// The serial EEPROMs Bandai boards save games in instead of battery backed RAM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromChip {
+    /// Xicor X24C01: 128 bytes, addressed straight after the start condition, LSB first
+    X24C01,
+    /// 24C02: 256 bytes, standard I2C with a device address then a word address, MSB first
+    C24C02,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    /// the first byte after a start condition
+    Control,
+    /// the 24C02's word address
+    Word,
+    Write,
+    Read,
+}
+
+impl Phase {
+    fn from_u8(value: u8) -> Result<Self, StateError> {
+        Ok(match value {
+            0 => Self::Idle,
+            1 => Self::Control,
+            2 => Self::Word,
+            3 => Self::Write,
+            4 => Self::Read,
+            _ => return Err(StateError::Invalid("EEPROM phase")),
+        })
+    }
+}
+
+/// An I2C EEPROM driven by bit-banging its clock and data lines.
+/// The chip samples data on the rising edge of SCL and changes its own output while SCL is low;
+/// SDA falling while SCL is high starts a transfer and SDA rising while SCL is high stops it
+pub struct Eeprom {
+    pub chip: EepromChip,
+    pub data: Vec<u8>,
+    scl: bool,
+    sda: bool,
+    /// what the chip drives SDA to, high when it lets go of the line
+    output: bool,
+    phase: Phase,
+    /// the byte being shifted in or out
+    shift: u8,
+    /// the bit of the current byte, 8 is the acknowledge clock and 9 is just after it
+    bit: u8,
+    /// the byte just transferred came from the chip, so the host acknowledges it
+    sent: bool,
+    address: u8,
+}
+
+impl Eeprom {
+    pub fn new(chip: EepromChip) -> Self {
+        let len = match chip {
+            EepromChip::X24C01 => 128,
+            EepromChip::C24C02 => 256,
+        };
+        Self {
+            chip,
+            data: vec![0; len],
+            scl: false,
+            sda: false,
+            output: true,
+            phase: Phase::Idle,
+            shift: 0,
+            bit: 0,
+            sent: false,
+            address: 0,
+        }
+    }
+
+    /// what the chip drives onto SDA
+    pub fn read(&self) -> bool {
+        self.output
+    }
+
+    /// sets both lines as the mapper's control register drives them
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda != sda {
+            if sda {
+                self.phase = Phase::Idle;
+            } else {
+                self.phase = Phase::Control;
+                self.bit = 0;
+                self.sent = false;
+            }
+            self.output = true;
+        } else if !self.scl && scl {
+            self.rise(sda);
+        } else if self.scl && !scl {
+            self.fall();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn lsb_first(&self) -> bool {
+        self.chip == EepromChip::X24C01
+    }
+
+    fn rise(&mut self, sda: bool) {
+        match self.bit {
+            0..=7 => {
+                if self.phase != Phase::Read {
+                    self.shift = if self.lsb_first() {
+                        self.shift >> 1 | (sda as u8) << 7
+                    } else {
+                        self.shift << 1 | sda as u8
+                    };
+                }
+                self.bit += 1;
+            }
+            8 => {
+                // the host doesn't acknowledge the last byte it wants
+                if self.sent && sda {
+                    self.phase = Phase::Idle;
+                }
+                self.bit = 9;
+            }
+            _ => {}
+        }
+    }
+
+    fn fall(&mut self) {
+        match (self.bit, self.phase) {
+            (_, Phase::Idle) => self.output = true,
+            (8, Phase::Read) if self.sent => self.output = true,
+            (8, _) => self.output = !self.received(),
+            (9, _) => {
+                self.bit = 0;
+                self.output = true;
+                self.sent = self.phase == Phase::Read;
+                if self.sent {
+                    self.shift = self.data[self.address as usize];
+                    self.address = self.next(self.address, self.data.len());
+                    self.output = self.sending();
+                }
+            }
+            (_, Phase::Read) => self.output = self.sending(),
+            _ => {}
+        }
+    }
+
+    /// the bit of the byte being read that goes out next
+    fn sending(&self) -> bool {
+        let index = if self.lsb_first() {
+            self.bit
+        } else {
+            7 - self.bit
+        };
+        self.shift >> index & 1 != 0
+    }
+
+    /// handles a whole byte from the host, returning whether the chip acknowledges it
+    fn received(&mut self) -> bool {
+        let byte = self.shift;
+        match (self.chip, self.phase) {
+            (EepromChip::X24C01, Phase::Control) => {
+                self.address = byte & 0x7F;
+                self.phase = if byte & 0x80 != 0 {
+                    Phase::Read
+                } else {
+                    Phase::Write
+                };
+            }
+            (EepromChip::C24C02, Phase::Control) => {
+                if byte & 0xF0 != 0xA0 {
+                    self.phase = Phase::Idle;
+                    return false;
+                }
+                self.phase = if byte & 1 != 0 {
+                    Phase::Read
+                } else {
+                    Phase::Word
+                };
+            }
+            (_, Phase::Word) => {
+                self.address = byte;
+                self.phase = Phase::Write;
+            }
+            (_, Phase::Write) => {
+                self.data[self.address as usize] = byte;
+                // writes wrap around within a page
+                let page = match self.chip {
+                    EepromChip::X24C01 => 4,
+                    EepromChip::C24C02 => 8,
+                };
+                self.address = self.address & !(page - 1) | self.next(self.address, page as usize);
+            }
+            _ => {}
+        }
+        // a read acknowledges its control byte, then the first data bit follows the ack clock
+        true
+    }
+
+    fn next(&self, address: u8, len: usize) -> u8 {
+        ((address as usize + 1) % len) as u8
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.block(&self.data);
+        state.bool(self.scl);
+        state.bool(self.sda);
+        state.bool(self.output);
+        state.u8(self.phase as u8);
+        state.u8(self.shift);
+        state.u8(self.bit);
+        state.bool(self.sent);
+        state.u8(self.address);
+    }
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.block_into(&mut self.data, "EEPROM")?;
+        self.scl = state.bool()?;
+        self.sda = state.bool()?;
+        self.output = state.bool()?;
+        self.phase = Phase::from_u8(state.u8()?)?;
+        self.shift = state.u8()?;
+        self.bit = state.u8()?;
+        self.sent = state.bool()?;
+        self.address = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// drives the lines like a game would
+    struct Host<'a>(&'a mut Eeprom);
+
+    impl Host<'_> {
+        fn start(&mut self) {
+            self.0.write(false, true);
+            self.0.write(true, true);
+            self.0.write(true, false);
+            self.0.write(false, false);
+        }
+        fn stop(&mut self) {
+            self.0.write(false, false);
+            self.0.write(true, false);
+            self.0.write(true, true);
+        }
+        fn clock(&mut self, sda: bool) -> bool {
+            self.0.write(false, sda);
+            self.0.write(true, sda);
+            let bit = self.0.read();
+            self.0.write(false, sda);
+            bit
+        }
+        /// sends a byte and returns whether the chip acknowledged it
+        fn send(&mut self, byte: u8, lsb_first: bool) -> bool {
+            for index in 0..8 {
+                let index = if lsb_first { index } else { 7 - index };
+                self.clock(byte >> index & 1 != 0);
+            }
+            !self.clock(true)
+        }
+        fn receive(&mut self, lsb_first: bool, last: bool) -> u8 {
+            let mut byte = 0;
+            for index in 0..8 {
+                let index = if lsb_first { index } else { 7 - index };
+                byte |= (self.clock(true) as u8) << index;
+            }
+            self.clock(last);
+            byte
+        }
+    }
+
+    #[test]
+    fn c24c02_random_read() {
+        let mut eeprom = Eeprom::new(EepromChip::C24C02);
+        let mut host = Host(&mut eeprom);
+        host.start();
+        assert!(host.send(0xA0, false));
+        assert!(host.send(0x10, false));
+        assert!(host.send(0x12, false));
+        assert!(host.send(0x34, false));
+        host.stop();
+        host.start();
+        assert!(!host.send(0x50, false));
+        host.stop();
+
+        host.start();
+        assert!(host.send(0xA0, false));
+        assert!(host.send(0x10, false));
+        host.start();
+        assert!(host.send(0xA1, false));
+        assert_eq!(host.receive(false, false), 0x12);
+        assert_eq!(host.receive(false, true), 0x34);
+        host.stop();
+        assert_eq!(eeprom.data[0x10..0x12], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn x24c01_read_write() {
+        let mut eeprom = Eeprom::new(EepromChip::X24C01);
+        let mut host = Host(&mut eeprom);
+        host.start();
+        assert!(host.send(0x05, true));
+        assert!(host.send(0xC3, true));
+        host.stop();
+        host.start();
+        assert!(host.send(0x85, true));
+        assert_eq!(host.receive(true, true), 0xC3);
+        host.stop();
+        assert_eq!(eeprom.data[5], 0xC3);
+
+        let mut state = StateWriter::new();
+        eeprom.save_state(&mut state);
+        let mut copy = Eeprom::new(EepromChip::X24C01);
+        copy.load_state(&mut StateReader::new(&state.bytes))
+            .unwrap();
+        assert_eq!(copy.data, eeprom.data);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::cartridge::bandai::BandaiFcg;
+use crate::cartridge::cartridge::{Cartridge, CartridgeError, Mirroring};
+use crate::cartridge::eeprom::EepromChip;
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+/// The banking hardware on a cartridge, sitting between the consoles' buses and the cartridge memory
+pub trait Mapper {
+    /// handles CPU reads in $4020-$FFFF
+    fn read_prg(&mut self, address: u16) -> u8 {
+        self.peek_prg(address)
+    }
+    /// reads PRG space without side effects, for tracing and debugging
+    fn peek_prg(&self, address: u16) -> u8;
+    /// handles CPU writes in $4020-$FFFF, which is where bank registers live
+    fn write_prg(&mut self, address: u16, value: u8);
+    /// handles PPU reads in the pattern table space $0000-$1FFF
+    fn read_chr(&self, address: u16) -> u8;
+    fn write_chr(&mut self, address: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+    /// lets the cartridge count `cycles` CPU cycles, for IRQ timers
+    fn tick(&mut self, _cycles: u32) {}
+    /// whether the cartridge is holding the IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+    /// the ROM images, CHR is empty when the cartridge has CHR-RAM
+    fn prg_rom(&self) -> &[u8];
+    fn chr_rom(&self) -> &[u8];
+    /// where in PRG-ROM a CPU address is mapped right now, for code/data logging
+    fn prg_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+    /// where in CHR-ROM a PPU address is mapped right now, `None` for CHR-RAM
+    fn chr_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+    /// the number of the 16K PRG-ROM bank mapped at a CPU address, for trace filters
+    fn prg_bank(&self, address: u16) -> Option<u16> {
+        let offset = self.prg_offset(address)?;
+        Some((offset / Cartridge::PRG_BANK_LEN) as u16)
+    }
+    /// the memory that survives power off, battery backed RAM or an EEPROM, for `.sav` files
+    fn battery(&self) -> Option<&[u8]> {
+        None
+    }
+    fn battery_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    /// the RAM at $6000-$7FFF, on cartridges that have some there rather than registers
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    /// writes the bank registers and any RAM on the cartridge, ROM is never saved
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Builds the mapper the cartridge's header asks for
+pub fn from_cartridge(cartridge: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match cartridge.mapper {
+        0 => Ok(Box::new(Nrom::new(cartridge))),
+        16 => Ok(Box::new(BandaiFcg::new(cartridge, EepromChip::C24C02))),
+        159 => Ok(Box::new(BandaiFcg::new(cartridge, EepromChip::X24C01))),
+        mapper => Err(CartridgeError::UnsupportedMapper(mapper)),
+    }
+}
+
+/// Mapper 0: up to 32K of PRG-ROM and 8K of CHR, no banking at all
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    /// CHR-ROM, or 8K of CHR-RAM when the cartridge has none
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// Family Basic style PRG-RAM at $6000-$7FFF, test ROMs report their results through it
+    prg_ram: Vec<u8>,
+    battery: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub const PRG_RAM_LEN: usize = 0x2000;
+
+    pub fn new(cartridge: Cartridge) -> Self {
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; Cartridge::CHR_BANK_LEN]
+        } else {
+            cartridge.chr_rom
+        };
+        Self {
+            prg_rom: cartridge.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram: vec![0; Self::PRG_RAM_LEN],
+            battery: cartridge.battery,
+            mirroring: cartridge.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn peek_prg(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram[address as usize - 0x6000],
+            // a 16K image shows up twice
+            0x8000..=0xFFFF => self.prg_rom[(address as usize - 0x8000) % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address {
+            self.prg_ram[address as usize - 0x6000] = value;
+        }
+    }
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[address as usize % self.chr.len()]
+    }
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[address as usize % len] = value;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+    fn chr_rom(&self) -> &[u8] {
+        if self.chr_is_ram {
+            &[]
+        } else {
+            &self.chr
+        }
+    }
+    fn prg_offset(&self, address: u16) -> Option<usize> {
+        Some((address.checked_sub(0x8000)? as usize) % self.prg_rom.len())
+    }
+    fn chr_offset(&self, address: u16) -> Option<usize> {
+        (!self.chr_is_ram).then(|| address as usize % self.chr.len())
+    }
+    fn battery(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+    fn battery_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+    fn save_state(&self, state: &mut StateWriter) {
+        state.block(&self.prg_ram);
+        if self.chr_is_ram {
+            state.block(&self.chr);
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.block_into(&mut self.prg_ram, "PRG-RAM")?;
+        if self.chr_is_ram {
+            state.block_into(&mut self.chr, "CHR-RAM")?;
+        }
+        Ok(())
+    }
+}
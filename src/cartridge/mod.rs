@@ -0,0 +1,6 @@
+pub mod bandai;
+pub mod battery;
+#[allow(clippy::module_inception)]
+pub mod cartridge;
+pub mod eeprom;
+pub mod mapper;
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::cheats::game_genie::{self, Patch};
+use crate::nes::nes::Nes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// a Game Genie code, patching what the CPU reads from the cartridge
+    GameGenie(Patch),
+    /// a Pro Action Replay style `AAAA:VV`, writing a byte of RAM before every frame. Frozen
+    /// bytes at $6000-$7FFF go to the cartridge's PRG-RAM and do nothing on cartridges
+    /// without any
+    Freeze { address: u16, value: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    /// the code as it was typed
+    pub code: String,
+    pub name: String,
+    pub kind: CheatKind,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Parses a Game Genie code or an `AAAA:VV` freeze, both in hex
+    pub fn parse(code: &str, name: &str) -> Result<Self, String> {
+        let kind = match code.split_once(':') {
+            Some((address, value)) => {
+                let address = u16::from_str_radix(address, 16)
+                    .map_err(|_| format!("bad address `{}`", address))?;
+                let value =
+                    u8::from_str_radix(value, 16).map_err(|_| format!("bad value `{}`", value))?;
+                // only memory can be frozen, writing registers every frame would be chaos
+                if !matches!(address, 0x0000..=0x1FFF | 0x6000..=0x7FFF) {
+                    return Err(format!("${:04X} isn't RAM", address));
+                }
+                CheatKind::Freeze { address, value }
+            }
+            None => CheatKind::GameGenie(game_genie::decode(code)?),
+        };
+        Ok(Self {
+            code: code.to_uppercase(),
+            name: name.to_string(),
+            kind,
+            enabled: true,
+        })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enabled = if self.enabled { "" } else { "-" };
+        write!(f, "{}{}", enabled, self.code)?;
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The cheats for a game, kept in a text file of one code per line followed by its name.
+/// `#` starts a comment and a `-` before the code keeps it switched off
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut cheats = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (code, name) = line.split_once(' ').unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let mut cheat = Cheat::parse(code, name.trim())
+                .map_err(|message| format!("line {}: {}", index + 1, message))?;
+            cheat.enabled = enabled;
+            cheats.cheats.push(cheat);
+        }
+        Ok(cheats)
+    }
+    pub fn to_text(&self) -> String {
+        self.cheats
+            .iter()
+            .map(|cheat| format!("{}\n", cheat))
+            .collect()
+    }
+
+    /// flips a cheat on or off, returning whether it's now on
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    /// Call before every frame: hands the enabled Game Genie codes to the bus and writes the
+    /// frozen bytes straight into RAM, as a bus write could hit mapper registers mirrored over
+    /// the cartridge's RAM
+    pub fn apply(&self, nes: &mut Nes) {
+        let bus = &mut nes.cpu.bus;
+        bus.patches.clear();
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            match cheat.kind {
+                CheatKind::GameGenie(patch) => bus.patches.push(patch),
+                CheatKind::Freeze {
+                    address: address @ 0x0000..=0x1FFF,
+                    value,
+                } => bus.ram[address as usize & 0x7FF] = value,
+                CheatKind::Freeze { address, value } => {
+                    if let Some(ram) = bus.mapper.prg_ram_mut() {
+                        let len = ram.len();
+                        ram[(address as usize - 0x6000) % len] = value;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::bus::Bus;
+
+    #[test]
+    fn cheat_files() {
+        let text = "# Super Mario Bros.\nSXIOPO Infinite lives\n-0075:09 Nine lives\n\nAAAA\n";
+        assert_eq!(
+            Cheats::parse(text),
+            Err("line 5: Game Genie codes have 6 or 8 letters, not 4".to_string())
+        );
+        let mut cheats = Cheats::parse(&text.replace("AAAA", "")).unwrap();
+        assert_eq!(cheats.cheats.len(), 2);
+        assert!(!cheats.cheats[1].enabled);
+        assert_eq!(
+            cheats.cheats[1].kind,
+            CheatKind::Freeze {
+                address: 0x0075,
+                value: 0x09
+            }
+        );
+        assert_eq!(cheats.toggle(1), Some(true));
+        assert_eq!(
+            cheats.to_text(),
+            "SXIOPO Infinite lives\n0075:09 Nine lives\n"
+        );
+        assert!(Cheat::parse("2002:00", "").is_err());
+    }
+
+    #[test]
+    fn applies_to_the_bus() {
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        rom[16 + 0x11DD] = 0x33;
+        let mut nes = Nes::from_ines(&rom).unwrap();
+        let mut cheats = Cheats::parse("GOSSIP\n0010:AB\n7000:CD").unwrap();
+        cheats.apply(&mut nes);
+        assert_eq!(nes.cpu.bus.read(0xD1DD), 0x14);
+        assert_eq!(nes.cpu.bus.peek(0x91DD), 0x33);
+        assert_eq!(nes.cpu.bus.ram[0x10], 0xAB);
+        assert_eq!(nes.cpu.bus.peek(0x7000), 0xCD);
+
+        cheats.toggle(0);
+        cheats.apply(&mut nes);
+        assert_eq!(nes.cpu.bus.read(0xD1DD), 0x33);
+
+        // Bandai FCG has its registers at $6000-$7FFF and no RAM to freeze there
+        let mut rom = b"NES\x1A\x04\x01\x00\x10".to_vec();
+        rom.resize(16 + 0x10000 + 0x2000, 0);
+        let mut nes = Nes::from_ines(&rom).unwrap();
+        Cheats::parse("6008:03").unwrap().apply(&mut nes);
+        assert_eq!(nes.cpu.bus.mapper.prg_offset(0x8000), Some(0));
+    }
+}
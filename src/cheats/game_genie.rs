@@ -0,0 +1,93 @@
+/// A Game Genie letter stands for the nibble of its position here
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// A replacement for what the CPU reads from cartridge space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Patch {
+    pub address: u16,
+    pub value: u8,
+    /// only replace the byte when the ROM holds this, so the code survives bank switching
+    pub compare: Option<u8>,
+}
+
+impl Patch {
+    /// what the CPU sees at `address` when the cartridge holds `original` there
+    pub fn apply(&self, address: u16, original: u8) -> u8 {
+        if address == self.address && self.compare.is_none_or(|compare| compare == original) {
+            self.value
+        } else {
+            original
+        }
+    }
+}
+
+/// Decodes a 6 or 8 letter Game Genie code. The letters' bits are scrambled into a 15 bit
+/// address in $8000-$FFFF, the value and, for 8 letter codes, a compare byte
+pub fn decode(code: &str) -> Result<Patch, String> {
+    let nibbles = code
+        .bytes()
+        .map(|letter| {
+            LETTERS
+                .iter()
+                .position(|candidate| *candidate == letter.to_ascii_uppercase())
+                .map(|nibble| nibble as u16)
+                .ok_or_else(|| format!("`{}` isn't a Game Genie letter", letter as char))
+        })
+        .collect::<Result<Vec<u16>, String>>()?;
+    let n = match nibbles.len() {
+        6 | 8 => nibbles,
+        len => return Err(format!("Game Genie codes have 6 or 8 letters, not {}", len)),
+    };
+    let address = 0x8000
+        | (n[3] & 7) << 12
+        | (n[5] & 7) << 8
+        | (n[4] & 8) << 8
+        | (n[2] & 7) << 4
+        | (n[1] & 8) << 4
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7);
+    let (value, compare) = if n.len() == 6 {
+        (value | (n[5] & 8), None)
+    } else {
+        let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8);
+        (value | (n[7] & 8), Some(compare as u8))
+    };
+    Ok(Patch {
+        address,
+        value: value as u8,
+        compare,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_codes() {
+        assert_eq!(
+            decode("GOSSIP"),
+            Ok(Patch {
+                address: 0xD1DD,
+                value: 0x14,
+                compare: None
+            })
+        );
+        assert_eq!(
+            decode("zexpygla"),
+            Ok(Patch {
+                address: 0x94A7,
+                value: 0x02,
+                compare: Some(0x03)
+            })
+        );
+        assert!(decode("GOSSI").is_err());
+        assert!(decode("GOSSIQ").is_err());
+
+        let patch = decode("ZEXPYGLA").unwrap();
+        assert_eq!(patch.apply(0x94A7, 0x03), 0x02);
+        assert_eq!(patch.apply(0x94A7, 0x05), 0x05);
+        assert_eq!(patch.apply(0x94A8, 0x03), 0x03);
+    }
+}
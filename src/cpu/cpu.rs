@@ -1,8 +1,48 @@
 #![allow(dead_code)]
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::assembler::assembler::Program;
 use crate::cpu::flags::Flags;
+use crate::cpu::opcodes::{self, AddressingMode, Mnemonic, Opcode};
 use crate::library;
+use crate::memory::bus::Bus;
 use crate::memory::memory::Memory;
-pub struct Cpu {
+use crate::memory::recording::{AccessKind, BusAccess};
+use crate::state::state::{StateError, StateReader, StateWriter};
+/// Which member of the 6502 family the core behaves like
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// the original NMOS 6502, with decimal mode and its odd N/V/Z flags
+    Nmos6502,
+    /// the NES's CPU, an NMOS 6502 with decimal mode cut out, the D flag does nothing
+    #[default]
+    Ricoh2A03,
+    /// the CMOS 65C02, whose decimal mode sets valid flags at the cost of a cycle
+    Cmos65C02,
+}
+
+impl CpuVariant {
+    pub fn has_decimal_mode(self) -> bool {
+        self != CpuVariant::Ricoh2A03
+    }
+}
+
+impl FromStr for CpuVariant {
+    type Err = String;
+
+    /// `6502`, `2a03` or `65c02`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "6502" => Ok(Self::Nmos6502),
+            "2a03" => Ok(Self::Ricoh2A03),
+            "65c02" => Ok(Self::Cmos65C02),
+            _ => Err(format!("expected 6502, 2a03 or 65c02, got `{}`", text)),
+        }
+    }
+}
+
+pub struct Cpu<B: Bus = Memory> {
     pub address_bus: u16,
     pub program_counter: u16,
     pub stack_pointer: u8,
@@ -10,13 +50,81 @@ pub struct Cpu {
     pub idx: u8,
     pub idy: u8,
     pub flags: Flags,
-    pub memory: Memory,
+    pub bus: B,
+    /// cycles elapsed since power-on
+    pub cycles: u64,
+    /// set once a JAM or STP opcode has locked up the processor, only a reset recovers from it
+    pub jammed: bool,
+    /// set by the 65C02's WAI until an interrupt comes in
+    pub waiting: bool,
+    /// the vector of the NMI or IRQ taken since `step` last started, for profilers
+    pub interrupt: Option<u16>,
+    pub variant: CpuVariant,
+    /// the reads and writes the instruction running has made, one per cycle
+    bus_cycles: u8,
+    /// an NMI edge seen on the bus that hasn't been serviced yet
+    nmi_detected: bool,
+    /// what the interrupt lines looked like after the first, the second-to-last and the last
+    /// cycle of the instruction running
+    first_poll: Poll,
+    penultimate_poll: Poll,
+    last_poll: Poll,
+    trace: Option<Box<dyn Write>>,
+    /// every read and write since the last `take_accesses`, while recording is on
+    accesses: Option<Vec<BusAccess>>,
+}
+
+/// The interrupts the CPU would take if it polled its lines at the end of a cycle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Poll {
+    nmi: bool,
+    /// IRQ held and not masked by the I flag
+    irq: bool,
+}
+
+impl Poll {
+    fn or(self, other: Poll) -> Poll {
+        Poll {
+            nmi: self.nmi || other.nmi,
+            irq: self.irq || other.irq,
+        }
+    }
+}
+
+/// The operand of a decoded instruction after its addressing mode has been resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Implied,
+    Accumulator,
+    Immediate(u8),
+    Address(u16),
+    Relative(i8),
+    /// a zero page address and a branch offset
+    ZeroPageRelative(u16, i8),
 }
 
 impl Cpu {
+    pub fn new() -> Self {
+        Self::with_bus(Memory::new())
+    }
+    /// a CPU with flat memory holding `program`, about to run its first instruction
+    pub fn with_program(program: Program) -> Self {
+        let mut cpu = Self::new();
+        program.load_into(&mut cpu.bus);
+        cpu.program_counter = program.entry();
+        cpu
+    }
+}
+
+impl<B: Bus> Cpu<B> {
     const STACK_LOCATION_OFFSET: u16 = 0x100;
     const SIGN_BIT: u8 = 7;
-    pub fn new() -> Self {
+    pub const NMI_VECTOR: u16 = 0xFFFA;
+    pub const RESET_VECTOR: u16 = 0xFFFC;
+    pub const IRQ_VECTOR: u16 = 0xFFFE;
+    /// the magic constant the unstable ANE/LXA opcodes OR into the accumulator
+    const UNSTABLE_MAGIC: u8 = 0xEE;
+    pub fn with_bus(bus: B) -> Self {
         Self {
             address_bus: 0,
             program_counter: 0,
@@ -25,17 +133,583 @@ impl Cpu {
             idx: 0,
             idy: 0,
             flags: Flags::default(),
-            memory: Memory::new(),
+            bus,
+            cycles: 0,
+            jammed: false,
+            waiting: false,
+            interrupt: None,
+            variant: CpuVariant::default(),
+            bus_cycles: 0,
+            nmi_detected: false,
+            first_poll: Poll::default(),
+            penultimate_poll: Poll::default(),
+            last_poll: Poll::default(),
+            trace: None,
+            accesses: None,
+        }
+    }
+    /// reads the byte at the program counter and advances past it
+    pub fn fetch(&mut self) -> u8 {
+        let value = self.read_memory(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+    pub fn fetch_u16(&mut self) -> u16 {
+        let lo = self.fetch();
+        let hi = self.fetch();
+        u16::from_le_bytes([lo, hi])
+    }
+    pub fn decode(&self, byte: u8) -> Opcode {
+        opcodes::decode_for(self.variant, byte)
+    }
+    /// Runs a single instruction, then the NMI or IRQ it polled on the bus, and returns the
+    /// number of cycles that took. Every cycle is a read or a write on the bus, dummy ones
+    /// included, followed by [`Bus::on_cycle`]
+    pub fn step(&mut self) -> u8 {
+        self.interrupt = None;
+        if self.jammed {
+            return 0;
+        }
+        // time goes by while WAI waits for an interrupt, even a masked IRQ ends the wait
+        if self.waiting {
+            self.dummy_read_memory(self.program_counter);
+            self.cycles += 1;
+            if self.nmi_detected || self.bus.irq() {
+                self.waiting = false;
+            }
+            return 1 + self.take_interrupt(self.last_poll);
+        }
+        if let Some(mut trace) = self.trace.take() {
+            // a failing trace sink shouldn't take the emulation down with it
+            let _ = writeln!(trace, "{}", self.trace_line());
+            self.trace = Some(trace);
+        }
+        self.bus_cycles = 0;
+        let byte = self.fetch();
+        let opcode = self.decode(byte);
+        let cycles = self.execute(opcode);
+        if self.variant == CpuVariant::Cmos65C02 {
+            debug_assert!(
+                self.bus_cycles <= cycles,
+                "{:?} made too many accesses",
+                opcode
+            );
+            // internal cycles of the 65C02 that aren't modelled one by one re-read the program
+            // counter, so the bus still sees one access per cycle
+            while self.bus_cycles < cycles {
+                self.dummy_read_memory(self.program_counter);
+            }
+        } else {
+            debug_assert_eq!(
+                self.bus_cycles, cycles,
+                "{:?} made the wrong number of accesses",
+                opcode
+            );
+        }
+        self.cycles += cycles as u64;
+        let poll = self.polled(opcode, cycles);
+        cycles + self.take_interrupt(poll)
+    }
+    /// Interrupts are polled at the end of the second-to-last cycle, which is why an
+    /// instruction that changes the I flag on its last cycle, CLI, SEI or PLP, only affects
+    /// whether the next one can be interrupted. Branches poll after fetching their offset,
+    /// and again before fixing the high byte of the program counter when they cross a page
+    fn polled(&self, opcode: Opcode, cycles: u8) -> Poll {
+        if opcode.mode != AddressingMode::Relative || cycles == opcode.cycles {
+            return self.penultimate_poll;
+        }
+        if cycles == opcode.cycles + 1 {
+            self.first_poll
+        } else {
+            self.first_poll.or(self.penultimate_poll)
+        }
+    }
+    /// the I flag was checked when the IRQ was polled, SEI may have set it since
+    fn take_interrupt(&mut self, poll: Poll) -> u8 {
+        if poll.nmi {
+            self.nmi()
+        } else if poll.irq {
+            self.interrupt(Self::IRQ_VECTOR)
+        } else {
+            0
+        }
+    }
+    /// samples the interrupt lines at the end of a cycle, an NMI edge stays detected until
+    /// it is serviced
+    fn sample_interrupts(&mut self) {
+        self.nmi_detected |= self.bus.take_nmi();
+        let poll = Poll {
+            nmi: self.nmi_detected,
+            irq: self.bus.irq() && !self.flags.interrupt_disable,
+        };
+        if self.bus_cycles == 1 {
+            self.first_poll = poll;
         }
+        self.penultimate_poll = self.last_poll;
+        self.last_poll = poll;
+    }
+    /// Starts emitting a nestest style line for every instruction before it executes
+    pub fn enable_trace(&mut self, sink: Box<dyn Write>) {
+        self.trace = Some(sink);
     }
-    pub fn fetch(&self) {
-        unimplemented!();
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
     }
-    pub fn decode(&self) {
-        unimplemented!();
+
+    /// resolves the operand of `mode`, fetching its bytes from the program counter.
+    /// the returned flag tells whether indexing crossed a page boundary
+    pub fn fetch_operand(&mut self, mode: AddressingMode) -> (Operand, bool) {
+        let (operand, page_crossed) = match mode {
+            AddressingMode::Implied => (Operand::Implied, false),
+            AddressingMode::Accumulator => (Operand::Accumulator, false),
+            AddressingMode::Immediate => (Operand::Immediate(self.fetch()), false),
+            AddressingMode::ZeroPage => (Operand::Address(self.fetch() as u16), false),
+            AddressingMode::ZeroPageX => {
+                let address = self.fetch_zero_page_base().wrapping_add(self.idx);
+                (Operand::Address(address as u16), false)
+            }
+            AddressingMode::ZeroPageY => {
+                let address = self.fetch_zero_page_base().wrapping_add(self.idy);
+                (Operand::Address(address as u16), false)
+            }
+            AddressingMode::Absolute => (Operand::Address(self.fetch_u16()), false),
+            AddressingMode::AbsoluteX => {
+                let base = self.fetch_u16();
+                Self::indexed(base, self.idx)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.fetch_u16();
+                Self::indexed(base, self.idy)
+            }
+            AddressingMode::Indirect if self.variant == CpuVariant::Cmos65C02 => {
+                // the 65C02 fixed JMP ($xxFF) to read the high byte from the next page
+                let pointer = self.fetch_u16();
+                (Operand::Address(self.read_u16(pointer)), false)
+            }
+            AddressingMode::Indirect => {
+                let pointer = self.fetch_u16();
+                (Operand::Address(self.read_pointer(pointer)), false)
+            }
+            AddressingMode::IndirectX => {
+                let pointer = self.fetch_zero_page_base().wrapping_add(self.idx);
+                (Operand::Address(self.read_pointer(pointer as u16)), false)
+            }
+            AddressingMode::IndirectY => {
+                let pointer = self.fetch();
+                let base = self.read_pointer(pointer as u16);
+                Self::indexed(base, self.idy)
+            }
+            AddressingMode::Relative => (Operand::Relative(self.fetch() as i8), false),
+            AddressingMode::ZeroPageIndirect => {
+                let pointer = self.fetch();
+                (Operand::Address(self.read_pointer(pointer as u16)), false)
+            }
+            AddressingMode::AbsoluteIndirectX => {
+                let pointer = self.fetch_u16().wrapping_add(self.idx as u16);
+                (Operand::Address(self.read_u16(pointer)), false)
+            }
+            AddressingMode::ZeroPageRelative => {
+                let address = self.fetch() as u16;
+                let offset = self.fetch() as i8;
+                (Operand::ZeroPageRelative(address, offset), false)
+            }
+        };
+        if let Operand::Address(address) = operand {
+            self.address_bus = address;
+        }
+        (operand, page_crossed)
+    }
+    /// fetches a zero page address, which is read while the index is being added to it
+    fn fetch_zero_page_base(&mut self) -> u8 {
+        let base = self.fetch();
+        self.dummy_read_memory(base as u16);
+        base
+    }
+    fn indexed(base: u16, index: u8) -> (Operand, bool) {
+        let address = base.wrapping_add(index as u16);
+        (Operand::Address(address), base & 0xFF00 != address & 0xFF00)
+    }
+    /// reads a little endian pointer without carrying into the high byte,
+    /// which reproduces both zero page wrapping and the `JMP ($xxFF)` bug
+    pub fn read_pointer(&mut self, pointer: u16) -> u16 {
+        let lo = self.read_memory(pointer);
+        let hi = self.read_memory(Self::pointer_high(pointer));
+        u16::from_le_bytes([lo, hi])
+    }
+    /// side effect free version of [`Cpu::read_pointer`]
+    pub fn peek_pointer(&self, pointer: u16) -> u16 {
+        let lo = self.peek_memory(pointer);
+        let hi = self.peek_memory(Self::pointer_high(pointer));
+        u16::from_le_bytes([lo, hi])
+    }
+    /// reads a little endian word without the page wrap of [`Cpu::read_pointer`]
+    pub fn read_u16(&mut self, address: u16) -> u16 {
+        let lo = self.read_memory(address);
+        let hi = self.read_memory(address.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+    fn pointer_high(pointer: u16) -> u16 {
+        (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF)
+    }
+    fn operand_value(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Accumulator => self.accumulator,
+            Operand::Immediate(value) => value,
+            Operand::Address(address) => self.read_memory(address),
+            Operand::Implied | Operand::Relative(_) | Operand::ZeroPageRelative(..) => {
+                unreachable!("{:?} has no value", operand)
+            }
+        }
+    }
+    fn operand_address(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Address(address) => address,
+            _ => unreachable!("{:?} has no address", operand),
+        }
+    }
+    fn operand_offset(&self, operand: Operand) -> i8 {
+        match operand {
+            Operand::Relative(offset) => offset,
+            _ => unreachable!("{:?} is not a branch offset", operand),
+        }
     }
-    pub fn execute(&self) {
-        unimplemented!();
+    /// feeds the operand's value to a read instruction
+    fn load(&mut self, operand: Operand, instruction: fn(&mut Self, u8)) {
+        let value = self.operand_value(operand);
+        instruction(self, value);
+    }
+    /// applies a read-modify-write instruction to the accumulator or memory, returning the result
+    fn modify(&mut self, operand: Operand, instruction: fn(&mut Self, &mut u8)) -> u8 {
+        let mut value = self.operand_value(operand);
+        let original = value;
+        instruction(self, &mut value);
+        match operand {
+            Operand::Accumulator => self.accumulator = value,
+            _ => {
+                let address = self.operand_address(operand);
+                self.modify_cycle(address, original);
+                self.write_memory(address, value);
+            }
+        }
+        value
+    }
+    /// the cycle between the read and the write of a read-modify-write instruction. The NMOS
+    /// parts write the unmodified value back, which mappers like MMC1 see as a second write,
+    /// the 65C02 reads it again instead
+    fn modify_cycle(&mut self, address: u16, original: u8) {
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.dummy_read_memory(address);
+        } else {
+            self.write_memory(address, original);
+        }
+    }
+
+    /// Executes an already fetched opcode and returns the number of cycles it took
+    pub fn execute(&mut self, opcode: Opcode) -> u8 {
+        if opcode.mnemonic == Mnemonic::Jsr {
+            self.jsr_absolute();
+            return opcode.cycles;
+        }
+        let (operand, page_crossed) = self.fetch_operand(opcode.mode);
+        let mut cycles = opcode.cycles;
+        if opcode.page_penalty && page_crossed {
+            cycles += 1;
+        }
+        self.dummy_read(opcode, operand, page_crossed);
+        match opcode.mnemonic {
+            Mnemonic::Lda => self.load(operand, Self::lda),
+            Mnemonic::Ldx => self.load(operand, Self::ldx),
+            Mnemonic::Ldy => self.load(operand, Self::ldy),
+            Mnemonic::Sta => self.sta(self.operand_address(operand)),
+            Mnemonic::Stx => self.stx(self.operand_address(operand)),
+            Mnemonic::Sty => self.sty(self.operand_address(operand)),
+            Mnemonic::Tax => self.tax(),
+            Mnemonic::Tay => self.tay(),
+            Mnemonic::Txa => self.txa(),
+            Mnemonic::Tya => self.tya(),
+            Mnemonic::Tsx => self.tsx(),
+            Mnemonic::Txs => self.txs(),
+            Mnemonic::Pha => self.pha(),
+            Mnemonic::Php => self.php(),
+            Mnemonic::Pla => self.pla(),
+            Mnemonic::Plp => self.plp(),
+            Mnemonic::And => self.load(operand, Self::and),
+            Mnemonic::Eor => self.load(operand, Self::eor),
+            Mnemonic::Ora => self.load(operand, Self::ora),
+            Mnemonic::Bit if opcode.mode == AddressingMode::Immediate => {
+                self.load(operand, Self::bit_immediate)
+            }
+            Mnemonic::Bit => self.load(operand, Self::bit),
+            Mnemonic::Adc => self.load(operand, Self::adc),
+            Mnemonic::Sbc => self.load(operand, Self::sbc),
+            Mnemonic::Cmp => self.load(operand, Self::cmp),
+            Mnemonic::Cpx => self.load(operand, Self::cmx),
+            Mnemonic::Cpy => self.load(operand, Self::cmy),
+            Mnemonic::Inc if operand == Operand::Accumulator => self.ina(),
+            Mnemonic::Inc => {
+                self.inc(self.operand_address(operand));
+            }
+            Mnemonic::Inx => self.inx(),
+            Mnemonic::Iny => self.iny(),
+            Mnemonic::Dec if operand == Operand::Accumulator => self.dea(),
+            Mnemonic::Dec => {
+                self.dec(self.operand_address(operand));
+            }
+            Mnemonic::Dex => self.dex(),
+            Mnemonic::Dey => self.dey(),
+            Mnemonic::Asl => {
+                self.modify(operand, Self::asl);
+            }
+            Mnemonic::Lsr => {
+                self.modify(operand, Self::lsr);
+            }
+            Mnemonic::Rol => {
+                self.modify(operand, Self::rol);
+            }
+            Mnemonic::Ror => {
+                self.modify(operand, Self::ror);
+            }
+            Mnemonic::Jmp => self.jmp(self.operand_address(operand)),
+            Mnemonic::Jsr => unreachable!("JSR fetches its own operand"),
+            Mnemonic::Rts => self.rts(),
+            Mnemonic::Bcc
+            | Mnemonic::Bcs
+            | Mnemonic::Beq
+            | Mnemonic::Bmi
+            | Mnemonic::Bne
+            | Mnemonic::Bpl
+            | Mnemonic::Bvc
+            | Mnemonic::Bvs
+            | Mnemonic::Bra => {
+                let origin = self.program_counter;
+                let offset = self.operand_offset(operand);
+                let taken = match opcode.mnemonic {
+                    Mnemonic::Bcc => self.bcc(offset),
+                    Mnemonic::Bcs => self.bcs(offset),
+                    Mnemonic::Beq => self.beq(offset),
+                    Mnemonic::Bmi => self.bmi(offset),
+                    Mnemonic::Bne => self.bne(offset),
+                    Mnemonic::Bpl => self.bpl(offset),
+                    Mnemonic::Bvc => self.bvc(offset),
+                    Mnemonic::Bvs => self.bvs(offset),
+                    _ => self.bra(offset),
+                };
+                cycles += self.branch_penalty(origin, taken);
+            }
+            Mnemonic::Bbr(bit) | Mnemonic::Bbs(bit) => {
+                let Operand::ZeroPageRelative(address, offset) = operand else {
+                    unreachable!("{:?} is not a zero page branch", operand)
+                };
+                let origin = self.program_counter;
+                let taken = if let Mnemonic::Bbr(_) = opcode.mnemonic {
+                    self.bbr(bit, address, offset)
+                } else {
+                    self.bbs(bit, address, offset)
+                };
+                cycles += self.branch_penalty(origin, taken);
+            }
+            Mnemonic::Rmb(bit) => self.rmb(bit, self.operand_address(operand)),
+            Mnemonic::Smb(bit) => self.smb(bit, self.operand_address(operand)),
+            Mnemonic::Phx => self.phx(),
+            Mnemonic::Phy => self.phy(),
+            Mnemonic::Plx => self.plx(),
+            Mnemonic::Ply => self.ply(),
+            Mnemonic::Stz => self.stz(self.operand_address(operand)),
+            Mnemonic::Tsb => {
+                self.modify(operand, Self::tsb);
+            }
+            Mnemonic::Trb => {
+                self.modify(operand, Self::trb);
+            }
+            Mnemonic::Wai => self.waiting = true,
+            Mnemonic::Stp => self.jammed = true,
+            Mnemonic::Clc => self.clc(),
+            Mnemonic::Cld => self.cld(),
+            Mnemonic::Cli => self.cli(),
+            Mnemonic::Clv => self.clv(),
+            Mnemonic::Sec => self.sec(),
+            Mnemonic::Sed => self.sed(),
+            Mnemonic::Sei => self.sei(),
+            Mnemonic::Brk => self.brk(),
+            Mnemonic::Rti => self.rti(),
+            Mnemonic::Nop => {
+                // the undocumented variants still read their operand
+                if let Operand::Address(address) = operand {
+                    self.read_memory(address);
+                }
+                self.nop()
+            }
+
+            // undocumented instructions, mostly a read-modify-write fused with an ALU operation
+            Mnemonic::Slo => {
+                let value = self.modify(operand, Self::asl);
+                self.ora(value);
+            }
+            Mnemonic::Rla => {
+                let value = self.modify(operand, Self::rol);
+                self.and(value);
+            }
+            Mnemonic::Sre => {
+                let value = self.modify(operand, Self::lsr);
+                self.eor(value);
+            }
+            Mnemonic::Rra => {
+                let value = self.modify(operand, Self::ror);
+                self.adc(value);
+            }
+            Mnemonic::Dcp => {
+                let value = self.dec(self.operand_address(operand));
+                self.cmp(value);
+            }
+            Mnemonic::Isb => {
+                let value = self.inc(self.operand_address(operand));
+                self.sbc(value);
+            }
+            Mnemonic::Lax => {
+                self.load(operand, Self::lda);
+                self.idx = self.accumulator;
+            }
+            Mnemonic::Sax => {
+                self.write_memory(self.operand_address(operand), self.accumulator & self.idx)
+            }
+            Mnemonic::Anc => {
+                self.load(operand, Self::and);
+                self.flags.carry = self.flags.negative;
+            }
+            Mnemonic::Alr => {
+                self.load(operand, Self::and);
+                self.modify(Operand::Accumulator, Self::lsr);
+            }
+            Mnemonic::Arr => {
+                self.load(operand, Self::and);
+                let result = self.modify(Operand::Accumulator, Self::ror);
+                self.flags.carry = library::isolate_bit_u8(result, 6) != 0;
+                self.flags.overflow =
+                    library::isolate_bit_u8(result, 6) ^ library::isolate_bit_u8(result, 5) != 0;
+            }
+            Mnemonic::Axs => {
+                let rhs = self.operand_value(operand);
+                let lhs = self.accumulator & self.idx;
+                self.flags.carry = lhs >= rhs;
+                self.idx = lhs.wrapping_sub(rhs);
+                self.update_flags(self.idx);
+            }
+            Mnemonic::Las => {
+                let value = self.operand_value(operand) & self.stack_pointer;
+                self.accumulator = value;
+                self.idx = value;
+                self.stack_pointer = value;
+                self.update_flags(value);
+            }
+            Mnemonic::Lxa => {
+                let value = (self.accumulator | Self::UNSTABLE_MAGIC) & self.operand_value(operand);
+                self.accumulator = value;
+                self.idx = value;
+                self.update_flags(value);
+            }
+            Mnemonic::Ane => {
+                let value = (self.accumulator | Self::UNSTABLE_MAGIC)
+                    & self.idx
+                    & self.operand_value(operand);
+                self.accumulator = value;
+                self.update_flags(value);
+            }
+            Mnemonic::Sha => {
+                let index = self.idy;
+                self.unstable_store(operand, index, self.accumulator & self.idx)
+            }
+            Mnemonic::Shx => {
+                let index = self.idy;
+                self.unstable_store(operand, index, self.idx)
+            }
+            Mnemonic::Shy => {
+                let index = self.idx;
+                self.unstable_store(operand, index, self.idy)
+            }
+            Mnemonic::Tas => {
+                self.stack_pointer = self.accumulator & self.idx;
+                let index = self.idy;
+                self.unstable_store(operand, index, self.stack_pointer)
+            }
+            Mnemonic::Jam => {
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                self.jammed = true;
+            }
+        }
+        // the 65C02 spends a cycle fixing up the flags of a decimal ADC or SBC
+        if matches!(opcode.mnemonic, Mnemonic::Adc | Mnemonic::Sbc)
+            && self.variant == CpuVariant::Cmos65C02
+            && self.flags.decimal_mode
+        {
+            cycles += 1;
+        }
+        cycles
+    }
+    /// The reads a 6502 makes while it's busy with something else: implied instructions read
+    /// the next byte, pulls read the stack before moving the stack pointer, and indexing reads
+    /// the address before its high byte is fixed, which read instructions skip when no page
+    /// was crossed. The 65C02 reads the last operand byte again instead of the unfixed address
+    fn dummy_read(&mut self, opcode: Opcode, operand: Operand, page_crossed: bool) {
+        match opcode.mode {
+            AddressingMode::Implied | AddressingMode::Accumulator if opcode.cycles > 1 => {
+                self.dummy_read_memory(self.program_counter);
+            }
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY
+                if page_crossed || !opcode.page_penalty =>
+            {
+                let address = self.operand_address(operand);
+                let unfixed = if self.variant == CpuVariant::Cmos65C02 {
+                    self.program_counter.wrapping_sub(1)
+                } else if page_crossed {
+                    address.wrapping_sub(0x100)
+                } else {
+                    address
+                };
+                self.dummy_read_memory(unfixed);
+            }
+            _ => {}
+        }
+        if matches!(
+            opcode.mnemonic,
+            Mnemonic::Pla
+                | Mnemonic::Plp
+                | Mnemonic::Plx
+                | Mnemonic::Ply
+                | Mnemonic::Rts
+                | Mnemonic::Rti
+        ) {
+            self.dummy_read_memory(self.stack_location());
+        }
+    }
+    /// a taken branch takes a cycle more, reading the next opcode, and another reading from
+    /// the wrong page when it lands on a different one
+    fn branch_penalty(&mut self, origin: u16, taken: bool) -> u8 {
+        let target = self.program_counter;
+        match (taken, origin & 0xFF00 != target & 0xFF00) {
+            (false, _) => 0,
+            (true, false) => {
+                self.dummy_read_memory(origin);
+                1
+            }
+            (true, true) => {
+                self.dummy_read_memory(origin);
+                self.dummy_read_memory((origin & 0xFF00) | (target & 0x00FF));
+                2
+            }
+        }
+    }
+    /// SHA/SHX/SHY/TAS store `value & (H + 1)` where H is the high byte of the unindexed base
+    /// address, and when indexing crosses a page that same value replaces the target's high byte
+    fn unstable_store(&mut self, operand: Operand, index: u8, value: u8) {
+        let address = self.operand_address(operand);
+        let base = address.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if base & 0xFF00 != address & 0xFF00 {
+            (address & 0x00FF) | (value as u16) << 8
+        } else {
+            address
+        };
+        self.write_memory(address, value);
     }
 
     /// helper function to determine if the flags zero and negative need to be updated after an instruction
@@ -43,11 +717,54 @@ impl Cpu {
         self.flags.zero = register == 0;
         self.flags.negative = library::isolate_bit_u8(register, Self::SIGN_BIT) != 0;
     }
-    pub fn read_memory(&self, location: u16) -> u8 {
-        self.memory[location]
+    pub fn read_memory(&mut self, location: u16) -> u8 {
+        let value = self.bus.read(location);
+        self.record(location, value, AccessKind::Read);
+        self.end_cycle();
+        value
+    }
+    /// a read whose value is thrown away, see [`Bus::dummy_read`]
+    pub fn dummy_read_memory(&mut self, location: u16) {
+        let value = self.bus.dummy_read(location);
+        self.record(location, value, AccessKind::Read);
+        self.end_cycle();
+    }
+    /// reads memory without triggering any side effects on the bus
+    pub fn peek_memory(&self, location: u16) -> u8 {
+        self.bus.peek(location)
+    }
+    pub fn write_memory(&mut self, location: u16, value: u8) {
+        self.bus.write(location, value);
+        self.record(location, value, AccessKind::Write);
+        self.end_cycle();
+    }
+    fn end_cycle(&mut self) {
+        self.bus_cycles = self.bus_cycles.wrapping_add(1);
+        self.bus.on_cycle();
+        self.sample_interrupts();
+    }
+    /// Starts or stops reporting the bus accesses the CPU makes, which is what watchpoints need
+    pub fn record_accesses(&mut self, enabled: bool) {
+        self.accesses = enabled.then(Vec::new);
+    }
+    /// the accesses made since the last call, empty while recording is off
+    pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+        self.accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+    fn record(&mut self, address: u16, value: u8, kind: AccessKind) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(BusAccess {
+                address,
+                value,
+                kind,
+            });
+        }
     }
     pub fn push_to_stack(&mut self, value: u8) {
-        self.memory[Self::STACK_LOCATION_OFFSET + self.stack_pointer as u16] = value;
+        self.write_memory(self.stack_location(), value);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
     pub fn pull_from_stack(&mut self) -> u8 {
@@ -57,8 +774,57 @@ impl Cpu {
     pub fn stack_location(&self) -> u16 {
         Self::STACK_LOCATION_OFFSET + self.stack_pointer as u16
     }
+    /// the status register as pushed by PHP and BRK, with the break bit set
+    fn status_for_push(&self) -> u8 {
+        self.flags.into_u8() | 1 << 4
+    }
+    /// loads the status register from a pulled byte, the break bit doesn't exist in the register itself
+    fn set_status(&mut self, value: u8) {
+        self.flags = Flags::from_u8(value);
+        self.flags.break_command = false;
+    }
+    /// Puts the CPU in the state it is in after the reset sequence, which takes 7 cycles: the
+    /// bus sees an interrupt whose pushes are turned into reads
     pub fn reset(&mut self) {
-        self.stack_pointer = 0xFF;
+        self.dummy_read_memory(self.program_counter);
+        self.dummy_read_memory(self.program_counter);
+        for _ in 0..3 {
+            self.dummy_read_memory(self.stack_location());
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        }
+        self.stack_pointer = 0xFD;
+        self.flags.interrupt_disable = true;
+        self.program_counter = self.read_pointer(Self::RESET_VECTOR);
+        self.cycles = 7;
+        self.jammed = false;
+        self.waiting = false;
+        self.nmi_detected = false;
+    }
+    /// writes the registers, whatever is on the bus is saved by its owner
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.address_bus);
+        state.u16(self.program_counter);
+        state.u8(self.stack_pointer);
+        state.u8(self.accumulator);
+        state.u8(self.idx);
+        state.u8(self.idy);
+        state.u8(self.flags.into_u8());
+        state.u64(self.cycles);
+        state.bool(self.jammed);
+        state.bool(self.nmi_detected);
+    }
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.address_bus = state.u16()?;
+        self.program_counter = state.u16()?;
+        self.stack_pointer = state.u8()?;
+        self.accumulator = state.u8()?;
+        self.idx = state.u8()?;
+        self.idy = state.u8()?;
+        self.flags = Flags::from_u8(state.u8()?);
+        self.cycles = state.u64()?;
+        self.jammed = state.bool()?;
+        self.nmi_detected = state.bool()?;
+        Ok(())
     }
     // Load store instructions
     pub fn lda(&mut self, value: u8) {
@@ -74,13 +840,16 @@ impl Cpu {
         self.update_flags(self.idy);
     }
     pub fn sta(&mut self, address: u16) {
-        self.memory[address] = self.accumulator;
+        self.write_memory(address, self.accumulator);
     }
     pub fn stx(&mut self, address: u16) {
-        self.memory[address] = self.idx;
+        self.write_memory(address, self.idx);
     }
     pub fn sty(&mut self, address: u16) {
-        self.memory[address] = self.idy;
+        self.write_memory(address, self.idy);
+    }
+    pub fn stz(&mut self, address: u16) {
+        self.write_memory(address, 0);
     }
 
     // Transfer instructions
@@ -109,66 +878,163 @@ impl Cpu {
     pub fn txs(&mut self) {
         self.stack_pointer = self.idx;
     }
+    pub fn phx(&mut self) {
+        self.push_to_stack(self.idx);
+    }
+    pub fn phy(&mut self) {
+        self.push_to_stack(self.idy);
+    }
+    pub fn plx(&mut self) {
+        self.idx = self.pull_from_stack();
+        self.update_flags(self.idx);
+    }
+    pub fn ply(&mut self) {
+        self.idy = self.pull_from_stack();
+        self.update_flags(self.idy);
+    }
     pub fn pha(&mut self) {
         self.push_to_stack(self.accumulator);
-        self.accumulator -= 1;
     }
     pub fn php(&mut self) {
-        self.push_to_stack(self.flags.into_u8());
-        self.stack_pointer -= 1;
+        self.push_to_stack(self.status_for_push());
     }
     pub fn pla(&mut self) {
-        self.stack_pointer += 1;
-        self.accumulator = self.read_memory(self.stack_location());
+        self.accumulator = self.pull_from_stack();
         self.update_flags(self.accumulator);
     }
     pub fn plp(&mut self) {
-        self.stack_pointer += 1;
-        self.flags.from_u8(self.read_memory(self.stack_location()));
+        let status = self.pull_from_stack();
+        self.set_status(status);
     }
 
     // logical instructions
     pub fn and(&mut self, rhs: u8) {
         self.accumulator &= rhs;
+        self.update_flags(self.accumulator);
     }
     pub fn eor(&mut self, rhs: u8) {
         self.accumulator ^= rhs;
+        self.update_flags(self.accumulator);
     }
     pub fn ora(&mut self, rhs: u8) {
         self.accumulator |= rhs;
+        self.update_flags(self.accumulator);
     }
     pub fn bit(&mut self, rhs: u8) {
-        let res = self.accumulator & rhs;
-        self.flags.zero = res == 0;
-        self.flags.overflow = library::isolate_bit_u8(res, 6) == 1;
-        self.flags.negative = library::isolate_bit_u8(res, Self::SIGN_BIT) == 1;
+        self.flags.zero = self.accumulator & rhs == 0;
+        self.flags.overflow = library::isolate_bit_u8(rhs, 6) == 1;
+        self.flags.negative = library::isolate_bit_u8(rhs, Self::SIGN_BIT) == 1;
+    }
+    /// the 65C02's BIT #imm, which only has a zero flag to set
+    pub fn bit_immediate(&mut self, rhs: u8) {
+        self.flags.zero = self.accumulator & rhs == 0;
+    }
+    /// sets the accumulator's bits in memory, Z tells whether any of them were set already
+    pub fn tsb(&mut self, value: &mut u8) {
+        self.flags.zero = self.accumulator & *value == 0;
+        *value |= self.accumulator;
+    }
+    /// clears the accumulator's bits in memory, Z tells whether any of them were set
+    pub fn trb(&mut self, value: &mut u8) {
+        self.flags.zero = self.accumulator & *value == 0;
+        *value &= !self.accumulator;
+    }
+    pub fn rmb(&mut self, bit: u8, location: u16) {
+        let value = self.read_memory(location);
+        self.modify_cycle(location, value);
+        self.write_memory(location, value & !(1 << bit));
+    }
+    pub fn smb(&mut self, bit: u8, location: u16) {
+        let value = self.read_memory(location);
+        self.modify_cycle(location, value);
+        self.write_memory(location, value | 1 << bit);
     }
 
     // Arithematic instructions
     pub fn adc(&mut self, rhs: u8) {
-        let (res, carry) = self.accumulator.overflowing_add(rhs);
-        self.accumulator = res;
-        self.flags.carry = carry;
-        self.update_flags(self.accumulator);
+        if self.decimal() {
+            self.adc_decimal(rhs);
+        } else {
+            self.adc_binary(rhs);
+        }
+    }
+    pub fn sbc(&mut self, rhs: u8) {
+        if self.decimal() {
+            self.sbc_decimal(rhs);
+        } else {
+            // A - M - !C is A + !M + C in two's complement, borrow being the inverted carry
+            self.adc_binary(!rhs);
+        }
+    }
+    fn decimal(&self) -> bool {
+        self.flags.decimal_mode && self.variant.has_decimal_mode()
+    }
+    fn adc_binary(&mut self, rhs: u8) {
+        let sum = self.accumulator as u16 + rhs as u16 + self.flags.carry as u16;
+        let res = sum as u8;
         let res_bit_7 = library::isolate_bit_u8(res, Self::SIGN_BIT) != 0;
         let accumulator_bit_7 = library::isolate_bit_u8(self.accumulator, Self::SIGN_BIT) != 0;
         let rhs_bit_7 = library::isolate_bit_u8(rhs, Self::SIGN_BIT) != 0;
 
         self.flags.overflow = (accumulator_bit_7 == rhs_bit_7) && (res_bit_7 != accumulator_bit_7);
-    }
-    pub fn sbc(&mut self, rhs: u8) {
-        let carry = if self.flags.carry { 0 } else { 1 }; // Carry inverted for SBC
-        let (intermediate_result, borrow1) = self.accumulator.overflowing_sub(rhs);
-        let (result, borrow2) = intermediate_result.overflowing_sub(carry);
-        self.accumulator = result;
-
-        self.flags.carry = !(borrow1 || borrow2); // Carry set when no borrow
+        self.flags.carry = sum > 0xFF;
+        self.accumulator = res;
         self.update_flags(self.accumulator);
-
-        let res_bit_7 = library::isolate_bit_u8(result, Self::SIGN_BIT) != 0;
-        let accumulator_bit_7 = library::isolate_bit_u8(self.accumulator, Self::SIGN_BIT) != 0;
-        let rhs_bit_7 = library::isolate_bit_u8(rhs, Self::SIGN_BIT) != 0;
-        self.flags.overflow = (accumulator_bit_7 != rhs_bit_7) && (res_bit_7 != accumulator_bit_7);
+    }
+    /// BCD addition a digit at a time. The NMOS 6502 takes Z from the binary sum and N and V
+    /// from the high digit before it is adjusted, the 65C02 sets N and Z from the result
+    fn adc_decimal(&mut self, rhs: u8) {
+        let (lhs, carry) = (self.accumulator, self.flags.carry as u8);
+        let mut low = (lhs & 0x0F) + (rhs & 0x0F) + carry;
+        if low > 9 {
+            low += 6;
+        }
+        let mut high = (lhs >> 4) + (rhs >> 4) + (low > 0x0F) as u8;
+        self.flags.zero = lhs.wrapping_add(rhs).wrapping_add(carry) == 0;
+        self.flags.negative = high & 0x08 != 0;
+        self.flags.overflow = !(lhs ^ rhs) & (lhs ^ (high << 4)) & 0x80 != 0;
+        if high > 9 {
+            high += 6;
+        }
+        self.flags.carry = high > 0x0F;
+        self.accumulator = (high << 4) | (low & 0x0F);
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_flags(self.accumulator);
+        }
+    }
+    /// BCD subtraction. Every flag comes from the binary difference, except N and Z on the
+    /// 65C02, which adjusts the result differently as well
+    fn sbc_decimal(&mut self, rhs: u8) {
+        let (lhs, borrow) = (self.accumulator as i16, !self.flags.carry as i16);
+        let rhs = rhs as i16;
+        self.adc_binary(!rhs as u8);
+        let low = (lhs & 0x0F) - (rhs & 0x0F) - borrow;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut result = lhs - rhs - borrow;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 0x06;
+            }
+            result
+        } else {
+            let low = if low < 0 {
+                ((low - 6) & 0x0F) - 0x10
+            } else {
+                low
+            };
+            let result = (lhs & 0xF0) - (rhs & 0xF0) + low;
+            if result < 0 {
+                result - 0x60
+            } else {
+                result
+            }
+        };
+        self.accumulator = result as u8;
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_flags(self.accumulator);
+        }
     }
     pub fn cmp(&mut self, rhs: u8) {
         let result = self.accumulator.wrapping_sub(rhs);
@@ -190,42 +1056,47 @@ impl Cpu {
     }
 
     // increments/decrements
-    pub fn inc(&mut self, location: u16) {
-        let carry;
-        (self.memory[location], carry) = self.memory[location].overflowing_add(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.memory[location], Self::SIGN_BIT) != 0;
+    /// increments memory, returning the result
+    pub fn inc(&mut self, location: u16) -> u8 {
+        let original = self.read_memory(location);
+        self.modify_cycle(location, original);
+        let value = original.wrapping_add(1);
+        self.write_memory(location, value);
+        self.update_flags(value);
+        value
     }
     pub fn inx(&mut self) {
-        let carry;
-        (self.idx, carry) = self.idx.overflowing_add(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.idx, Self::SIGN_BIT) != 0;
+        self.idx = self.idx.wrapping_add(1);
+        self.update_flags(self.idx);
     }
     pub fn iny(&mut self) {
-        let carry;
-        (self.idy, carry) = self.idx.overflowing_add(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.idy, Self::SIGN_BIT) != 0;
+        self.idy = self.idy.wrapping_add(1);
+        self.update_flags(self.idy);
+    }
+    pub fn ina(&mut self) {
+        self.accumulator = self.accumulator.wrapping_add(1);
+        self.update_flags(self.accumulator);
     }
 
-    pub fn dec(&mut self, location: u16) {
-        let carry;
-        (self.memory[location], carry) = self.memory[location].overflowing_sub(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.memory[location], Self::SIGN_BIT) != 0;
+    pub fn dec(&mut self, location: u16) -> u8 {
+        let original = self.read_memory(location);
+        self.modify_cycle(location, original);
+        let value = original.wrapping_sub(1);
+        self.write_memory(location, value);
+        self.update_flags(value);
+        value
     }
     pub fn dex(&mut self) {
-        let carry;
-        (self.idx, carry) = self.idx.overflowing_sub(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.idx, Self::SIGN_BIT) != 0;
+        self.idx = self.idx.wrapping_sub(1);
+        self.update_flags(self.idx);
     }
     pub fn dey(&mut self) {
-        let carry;
-        (self.idy, carry) = self.idy.overflowing_add(1);
-        self.flags.carry = carry;
-        self.flags.negative = library::isolate_bit_u8(self.idy, Self::SIGN_BIT) != 0;
+        self.idy = self.idy.wrapping_sub(1);
+        self.update_flags(self.idy);
+    }
+    pub fn dea(&mut self) {
+        self.accumulator = self.accumulator.wrapping_sub(1);
+        self.update_flags(self.accumulator);
     }
 
     // shifting operations
@@ -245,16 +1116,18 @@ impl Cpu {
     }
 
     pub fn rol(&mut self, value: &mut u8) {
+        let carry_in = self.flags.carry as u8;
         self.flags.carry = library::isolate_bit_u8(*value, Self::SIGN_BIT) != 0; // carry is sign
                                                                                  // bit before rotation
-        *value = value.rotate_left(1);
+        *value = *value << 1 | carry_in;
         self.flags.zero = *value == 0;
         self.flags.negative = library::isolate_bit_u8(*value, Self::SIGN_BIT) != 0;
     }
 
     pub fn ror(&mut self, value: &mut u8) {
+        let carry_in = self.flags.carry as u8;
         self.flags.carry = library::isolate_bit_u8(*value, 0) != 0;
-        *value = value.rotate_right(1);
+        *value = *value >> 1 | carry_in << Self::SIGN_BIT;
         self.flags.zero = *value == 0;
         self.flags.negative = library::isolate_bit_u8(*value, Self::SIGN_BIT) != 0;
     }
@@ -263,47 +1136,77 @@ impl Cpu {
     pub fn jmp(&mut self, location: u16) {
         self.program_counter = location;
     }
+    /// pushes the address of the last byte of the JSR instruction, expecting the
+    /// program counter to already point past it
     pub fn jsr(&mut self, location: u16) {
-        let [lo, hi] = self.program_counter.to_le_bytes();
-        self.push_to_stack(lo);
+        let [lo, hi] = self.program_counter.wrapping_sub(1).to_le_bytes();
         self.push_to_stack(hi);
+        self.push_to_stack(lo);
         self.program_counter = location;
     }
+    /// JSR as the hardware runs it, pushing the return address between fetching the low
+    /// and the high byte of its target
+    fn jsr_absolute(&mut self) {
+        let lo = self.fetch();
+        self.dummy_read_memory(self.stack_location());
+        let [return_lo, return_hi] = self.program_counter.to_le_bytes();
+        self.push_to_stack(return_hi);
+        self.push_to_stack(return_lo);
+        let hi = self.read_memory(self.program_counter);
+        self.address_bus = u16::from_le_bytes([lo, hi]);
+        self.program_counter = self.address_bus;
+    }
+    /// pulls the return address, then reads the byte it points at while incrementing it
     pub fn rts(&mut self) {
         let lo = self.pull_from_stack();
         let hi = self.pull_from_stack();
-        self.program_counter = u16::from_le_bytes([lo, hi]);
+        let address = u16::from_le_bytes([lo, hi]);
+        self.dummy_read_memory(address);
+        self.program_counter = address.wrapping_add(1);
     }
 
     // Branching
-    pub fn branch_if(&mut self, condition: bool, immediate: i8) {
+    pub fn branch_if(&mut self, condition: bool, immediate: i8) -> bool {
         if condition {
             self.program_counter = self.program_counter.wrapping_add(immediate as i16 as u16);
         }
+        condition
+    }
+    pub fn bcc(&mut self, immediate: i8) -> bool {
+        self.branch_if(!self.flags.carry, immediate)
+    }
+    pub fn bcs(&mut self, immediate: i8) -> bool {
+        self.branch_if(self.flags.carry, immediate)
+    }
+    pub fn beq(&mut self, immediate: i8) -> bool {
+        self.branch_if(self.flags.zero, immediate)
     }
-    pub fn bcc(&mut self, immediate: i8) {
-        self.branch_if(!self.flags.carry, immediate);
+    pub fn bmi(&mut self, immediate: i8) -> bool {
+        self.branch_if(self.flags.negative, immediate)
     }
-    pub fn bcs(&mut self, immediate: i8) {
-        self.branch_if(self.flags.carry, immediate);
+    pub fn bne(&mut self, immediate: i8) -> bool {
+        self.branch_if(!self.flags.zero, immediate)
     }
-    pub fn beq(&mut self, immediate: i8) {
-        self.branch_if(self.flags.zero, immediate);
+    pub fn bpl(&mut self, immediate: i8) -> bool {
+        self.branch_if(!self.flags.negative, immediate)
     }
-    pub fn bmi(&mut self, immediate: i8) {
-        self.branch_if(self.flags.negative, immediate);
+    pub fn bvc(&mut self, immediate: i8) -> bool {
+        self.branch_if(!self.flags.overflow, immediate)
     }
-    pub fn bne(&mut self, immediate: i8) {
-        self.branch_if(!self.flags.zero, immediate);
+    pub fn bvs(&mut self, immediate: i8) -> bool {
+        self.branch_if(self.flags.overflow, immediate)
     }
-    pub fn bpl(&mut self, immediate: i8) {
-        self.branch_if(!self.flags.negative, immediate);
+    pub fn bra(&mut self, immediate: i8) -> bool {
+        self.branch_if(true, immediate)
     }
-    pub fn bvc(&mut self, immediate: i8) {
-        self.branch_if(!self.flags.overflow, immediate);
+    /// branches when `bit` of the zero page byte at `location` is clear
+    pub fn bbr(&mut self, bit: u8, location: u16, immediate: i8) -> bool {
+        let value = self.read_memory(location);
+        self.branch_if(value & 1 << bit == 0, immediate)
     }
-    pub fn bvs(&mut self, immediate: i8) {
-        self.branch_if(self.flags.overflow, immediate);
+    pub fn bbs(&mut self, bit: u8, location: u16, immediate: i8) -> bool {
+        let value = self.read_memory(location);
+        self.branch_if(value & 1 << bit != 0, immediate)
     }
 
     // status flag changes
@@ -330,27 +1233,84 @@ impl Cpu {
     }
 
     // system functions
+    /// BRK is a two byte instruction, the program counter is expected to point
+    /// just past the opcode so the padding byte, read by then, is skipped on return
     pub fn brk(&mut self) {
-        let [lo, hi] = (self.program_counter + 2).to_le_bytes();
+        let [lo, hi] = self.program_counter.wrapping_add(1).to_le_bytes();
         self.push_to_stack(hi);
         self.push_to_stack(lo);
-        self.flags.break_command = true;
-        let bitflags = self.flags.into_u8();
+        let vector = self.hijack(Self::IRQ_VECTOR);
+        let bitflags = self.status_for_push();
         self.push_to_stack(bitflags);
+        self.flags.interrupt_disable = true;
+        self.clear_decimal_on_interrupt();
 
-        let lo = self.memory[0xFFFE];
-        let hi = self.memory[0xFFFF];
-        self.program_counter = u16::from_le_bytes([lo, hi]);
+        self.program_counter = self.read_pointer(vector);
+    }
+    /// An NMI that comes in while BRK or IRQ is still pushing the return address takes over
+    /// its vector, the B flag pushed is left as it was
+    fn hijack(&mut self, vector: u16) -> u16 {
+        if vector == Self::NMI_VECTOR || !self.nmi_detected {
+            return vector;
+        }
+        self.nmi_detected = false;
+        self.interrupt = Some(Self::NMI_VECTOR);
+        Self::NMI_VECTOR
     }
 
     pub fn nop(&self) {}
 
+    /// services a non-maskable interrupt, returning the 7 cycles it took
+    pub fn nmi(&mut self) -> u8 {
+        self.nmi_detected = false;
+        self.interrupt(Self::NMI_VECTOR)
+    }
+    /// services an interrupt request unless the interrupt disable flag masks it. A masked
+    /// request still ends a WAI, which then carries on with the next instruction
+    pub fn irq(&mut self) -> u8 {
+        if self.flags.interrupt_disable {
+            self.waiting = false;
+            return 0;
+        }
+        self.interrupt(Self::IRQ_VECTOR)
+    }
+    fn interrupt(&mut self, vector: u16) -> u8 {
+        self.interrupt = Some(vector);
+        self.waiting = false;
+        // the opcode fetch and operand read the interrupt replaces
+        self.dummy_read_memory(self.program_counter);
+        self.dummy_read_memory(self.program_counter);
+        let [lo, hi] = self.program_counter.to_le_bytes();
+        self.push_to_stack(hi);
+        self.push_to_stack(lo);
+        let vector = self.hijack(vector);
+        self.push_to_stack(self.flags.into_u8());
+        self.flags.interrupt_disable = true;
+        self.clear_decimal_on_interrupt();
+        self.program_counter = self.read_pointer(vector);
+        self.cycles += 7;
+        7
+    }
+
+    /// the 65C02 leaves decimal mode when it takes an interrupt, the NMOS parts keep D as is
+    fn clear_decimal_on_interrupt(&mut self) {
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.flags.decimal_mode = false;
+        }
+    }
+
+    /// unlike PLP, RTI restores the I flag in time for the next interrupt poll
     pub fn rti(&mut self) {
         let flags = self.pull_from_stack();
+        self.set_status(flags);
         let lo = self.pull_from_stack();
         let hi = self.pull_from_stack();
-
-        self.flags.from_u8(flags);
         self.program_counter = u16::from_le_bytes([lo, hi]);
     }
 }
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,7 +1,10 @@
 #[cfg(test)]
 mod tests {
-    use crate::cpu::cpu::Cpu;
+    use crate::asm;
+    use crate::cpu::cpu::{Cpu, CpuVariant};
     use crate::cpu::flags::Flags;
+    use crate::memory::bus::Bus;
+    use crate::memory::memory::Memory;
 
     fn setup_cpu() -> Cpu {
         Cpu::new()
@@ -23,15 +26,15 @@ mod tests {
         let mut cpu = setup_cpu();
         cpu.lda(0x42);
         assert_eq!(cpu.accumulator, 0x42);
-        assert_eq!(cpu.flags.zero, false);
-        assert_eq!(cpu.flags.negative, false);
+        assert!(!cpu.flags.zero);
+        assert!(!cpu.flags.negative);
 
         cpu.lda(0x00);
         assert_eq!(cpu.accumulator, 0x00);
-        assert_eq!(cpu.flags.zero, true);
+        assert!(cpu.flags.zero);
 
         cpu.lda(0x80); // Test negative flag.
-        assert_eq!(cpu.flags.negative, true);
+        assert!(cpu.flags.negative);
     }
 
     #[test]
@@ -67,19 +70,159 @@ mod tests {
         cpu.lda(0x10);
         cpu.adc(0x20);
         assert_eq!(cpu.accumulator, 0x30);
-        assert_eq!(cpu.flags.carry, false);
+        assert!(!cpu.flags.carry);
 
         cpu.adc(0xF0); // Overflow test.
         assert_eq!(cpu.accumulator, 0x20);
-        assert_eq!(cpu.flags.carry, true);
+        assert!(cpu.flags.carry);
 
         cpu.sbc(0x10);
         assert_eq!(cpu.accumulator, 0x10);
-        assert_eq!(cpu.flags.carry, true);
+        assert!(cpu.flags.carry);
 
         cpu.sbc(0x20);
         assert_eq!(cpu.accumulator, 0xF0);
-        assert_eq!(cpu.flags.carry, false);
+        assert!(!cpu.flags.carry);
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        let mut cpu = setup_cpu();
+        cpu.flags.decimal_mode = true;
+        cpu.lda(0x09);
+        cpu.adc(0x01); // the 2A03 ignores the D flag
+        assert_eq!(cpu.accumulator, 0x0A);
+
+        cpu.variant = CpuVariant::Nmos6502;
+        cpu.lda(0x58);
+        cpu.flags.carry = true;
+        cpu.adc(0x46);
+        assert_eq!(cpu.accumulator, 0x05);
+        assert!(cpu.flags.carry);
+
+        cpu.lda(0x99);
+        cpu.flags.carry = false;
+        cpu.adc(0x01); // Z from the binary sum, N from the unadjusted high digit
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.flags.carry && !cpu.flags.zero && cpu.flags.negative);
+
+        cpu.lda(0x46);
+        cpu.flags.carry = true;
+        cpu.sbc(0x12);
+        assert_eq!(cpu.accumulator, 0x34);
+        assert!(cpu.flags.carry);
+
+        cpu.lda(0x00);
+        cpu.sbc(0x01);
+        assert_eq!(cpu.accumulator, 0x99);
+        assert!(!cpu.flags.carry && cpu.flags.negative);
+
+        cpu.variant = CpuVariant::Cmos65C02;
+        cpu.lda(0x99);
+        cpu.flags.carry = false;
+        cpu.adc(0x01);
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.flags.carry && cpu.flags.zero && !cpu.flags.negative);
+
+        cpu.lda(0x20);
+        cpu.flags.carry = true;
+        cpu.sbc(0x01);
+        assert_eq!(cpu.accumulator, 0x19);
+        assert!(cpu.flags.carry && !cpu.flags.negative);
+
+        cpu.bus.load(0x0600, &[0x69, 0x01]); // ADC #$01, carry still set
+        cpu.program_counter = 0x0600;
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.accumulator, 0x21);
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let mut cpu = setup_cpu();
+        cpu.variant = CpuVariant::Cmos65C02;
+        #[rustfmt::skip]
+        cpu.bus.load(0x0600, &[
+            0xA9, 0x0F,       // LDA #$0F
+            0x04, 0x10,       // TSB $10
+            0x14, 0x10,       // TRB $10
+            0x1A,             // INC A
+            0xDA,             // PHX
+            0xA2, 0x05,       // LDX #5
+            0xFA,             // PLX
+            0x64, 0x11,       // STZ $11
+            0x87, 0x11,       // SMB0 $11
+            0x0F, 0x11, 0x02, // BBR0 $11,+2
+            0x8F, 0x11, 0x01, // BBS0 $11,+1
+            0xDB,             // STP
+            0xB2, 0x12,       // LDA ($12)
+            0x80, 0x01,       // BRA +1
+            0xDB,             // STP
+            0x89, 0x00,       // BIT #0
+            0x7C, 0x20, 0x00, // JMP ($0020,X)
+        ]);
+        cpu.bus.load(0x0630, &[0xCB, 0xEA]); // WAI, NOP
+        cpu.bus.load(0x0010, &[0xF0, 0xAA, 0x00, 0x03]);
+        cpu.bus.load(0x0020, &[0x30, 0x06]);
+        cpu.bus[0x0300] = 0x42;
+        cpu.bus.load(0xFFFE, &[0x00, 0x07]);
+        cpu.program_counter = 0x0600;
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.bus[0x0010], 0xFF);
+        assert!(cpu.flags.zero);
+        cpu.step();
+        assert_eq!(cpu.bus[0x0010], 0xF0);
+        assert!(!cpu.flags.zero);
+        cpu.step();
+        assert_eq!(cpu.accumulator, 0x10);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.idx, 0);
+        assert!(cpu.flags.zero);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.bus[0x0011], 0x01);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.program_counter, 0x0612);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.program_counter, 0x0616);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.program_counter, 0x061B);
+        cpu.flags.overflow = true;
+        cpu.step();
+        assert!(cpu.flags.zero && cpu.flags.overflow);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0630);
+
+        cpu.step();
+        assert!(cpu.waiting);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.program_counter, 0x0631);
+        cpu.flags.decimal_mode = true;
+        assert_eq!(cpu.irq(), 7);
+        assert!(!cpu.waiting && !cpu.flags.decimal_mode);
+        assert_eq!(cpu.program_counter, 0x0700);
+    }
+
+    #[test]
+    fn test_indirect_jump_page_wrap() {
+        for (variant, target) in [
+            (CpuVariant::Ricoh2A03, 0x0900),
+            (CpuVariant::Cmos65C02, 0x0700),
+        ] {
+            let mut cpu = setup_cpu();
+            cpu.variant = variant;
+            cpu.bus.load(0x0600, &[0x6C, 0xFF, 0x02]); // JMP ($02FF)
+            cpu.bus.load(0x02FF, &[0x00, 0x07]);
+            cpu.bus[0x0200] = 0x09;
+            cpu.program_counter = 0x0600;
+            cpu.step();
+            assert_eq!(cpu.program_counter, target);
+        }
     }
 
     #[test]
@@ -102,22 +245,22 @@ mod tests {
         let mut value = 0b0100_0000;
         cpu.asl(&mut value);
         assert_eq!(value, 0b1000_0000);
-        assert_eq!(cpu.flags.carry, false);
+        assert!(!cpu.flags.carry);
 
         let mut value = 0b1000_0001;
         cpu.lsr(&mut value);
         assert_eq!(value, 0b0100_0000);
-        assert_eq!(cpu.flags.carry, true);
+        assert!(cpu.flags.carry);
 
         let mut value = 0b1000_0000;
         cpu.rol(&mut value);
         assert_eq!(value, 0b0000_0001);
-        assert_eq!(cpu.flags.carry, true);
+        assert!(cpu.flags.carry);
 
         let mut value = 0b0000_0001;
         cpu.ror(&mut value);
         assert_eq!(value, 0b1000_0000);
-        assert_eq!(cpu.flags.carry, true);
+        assert!(cpu.flags.carry);
     }
 
     #[test]
@@ -144,4 +287,241 @@ mod tests {
         cpu.sta(0x2000);
         assert_eq!(cpu.read_memory(0x2000), cpu.accumulator);
     }
+
+    #[test]
+    fn test_step_decodes_and_executes() {
+        let mut cpu = setup_cpu();
+        cpu.bus
+            .load(0x0600, &[0xA9, 0x7F, 0x69, 0x01, 0xC8, 0x88, 0x88]); // LDA #$7F, ADC #$01, INY, DEY, DEY
+        cpu.program_counter = 0x0600;
+
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.accumulator, 0x80);
+        assert!(cpu.flags.overflow);
+        assert!(cpu.flags.negative);
+
+        cpu.step();
+        assert_eq!(cpu.idy, 1);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.idy, 0xFF);
+        assert!(cpu.flags.negative);
+        assert_eq!(cpu.program_counter, 0x0607);
+        assert_eq!(cpu.cycles, 10);
+    }
+
+    #[test]
+    fn test_subroutine_round_trip() {
+        let mut cpu = setup_cpu();
+        cpu.bus.load(0x0600, &[0x20, 0x00, 0x07]); // JSR $0700
+        cpu.bus.load(0x0700, &[0x08, 0x28, 0x60]); // PHP, PLP, RTS
+        cpu.program_counter = 0x0600;
+
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.read_memory(0x01FF), 0x06);
+        assert_eq!(cpu.read_memory(0x01FE), 0x02);
+        cpu.step();
+        assert_eq!(cpu.read_memory(0x01FD), 0x30); // PHP pushes the break bit
+        cpu.step();
+        assert_eq!(cpu.flags, Flags::default());
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert_eq!(cpu.stack_pointer, 0xFF);
+    }
+
+    #[test]
+    fn test_branch_page_cross_cycles() {
+        let mut cpu = setup_cpu();
+        cpu.bus.load(0x06F0, &[0xD0, 0x20]); // BNE +$20
+        cpu.program_counter = 0x06F0;
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.program_counter, 0x0712);
+    }
+
+    /// flat memory counting the cycles the CPU reports through `on_cycle`
+    struct CycleCounter {
+        memory: Memory,
+        cycles: u64,
+    }
+
+    impl Bus for CycleCounter {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address]
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address] = value;
+        }
+        fn peek(&self, address: u16) -> u8 {
+            self.memory[address]
+        }
+        fn on_cycle(&mut self) {
+            self.cycles += 1;
+        }
+    }
+
+    #[test]
+    fn test_cycle_hook() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02] {
+            for byte in 0..=0xFF {
+                let mut cpu = Cpu::with_bus(CycleCounter {
+                    memory: Memory::new(),
+                    cycles: 0,
+                });
+                cpu.variant = variant;
+                // indexing $02FF crosses a page
+                cpu.bus.memory.load(0x0600, &[byte, 0xFF, 0x02]);
+                cpu.program_counter = 0x0600;
+                cpu.idx = 1;
+                cpu.idy = 1;
+                let cycles = cpu.step() as u64;
+                assert_eq!(cpu.bus.cycles, cycles, "{:02X} on {:?}", byte, variant);
+            }
+        }
+
+        let mut cpu = Cpu::with_bus(CycleCounter {
+            memory: Memory::new(),
+            cycles: 0,
+        });
+        cpu.flags.interrupt_disable = false;
+        assert_eq!(cpu.irq(), 7);
+        assert_eq!(cpu.bus.cycles, 7);
+    }
+
+    /// flat memory with interrupt lines the test drives, NMI going active on a given cycle
+    struct Lines {
+        memory: Memory,
+        cycles: u64,
+        irq: bool,
+        nmi_at: Option<u64>,
+    }
+
+    impl Bus for Lines {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address]
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address] = value;
+        }
+        fn peek(&self, address: u16) -> u8 {
+            self.memory[address]
+        }
+        fn on_cycle(&mut self) {
+            self.cycles += 1;
+        }
+        fn take_nmi(&mut self) -> bool {
+            self.nmi_at == Some(self.cycles)
+        }
+        fn irq(&self) -> bool {
+            self.irq
+        }
+    }
+
+    /// `program` at $0600, with NOPs for the NMI handler at $0700 and the IRQ one at $0800
+    fn lines(program: &[u8]) -> Cpu<Lines> {
+        let mut memory = Memory::new();
+        memory.load(0x0600, program);
+        memory.load(0x0700, &[0xEA]);
+        memory.load(0x0800, &[0xEA]);
+        memory.load(0xFFFA, &[0x00, 0x07, 0x00, 0x06, 0x00, 0x08]);
+        let mut cpu = Cpu::with_bus(Lines {
+            memory,
+            cycles: 0,
+            irq: false,
+            nmi_at: None,
+        });
+        cpu.program_counter = 0x0600;
+        cpu.flags.interrupt_disable = false;
+        cpu
+    }
+
+    #[test]
+    fn test_interrupt_polling() {
+        let irq = Cpu::<Lines>::IRQ_VECTOR;
+        let nmi = Cpu::<Lines>::NMI_VECTOR;
+
+        // CLI only lets the IRQ in after the next instruction
+        let mut cpu = lines(&[0x58, 0xEA]);
+        cpu.flags.interrupt_disable = true;
+        cpu.bus.irq = true;
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.interrupt, None);
+        assert_eq!(cpu.step(), 2 + 7);
+        assert_eq!(cpu.interrupt, Some(irq));
+        assert_eq!(cpu.program_counter, 0x0800);
+
+        // and SEI still lets one in, which pushes I set
+        let mut cpu = lines(&[0x78]);
+        cpu.bus.irq = true;
+        assert_eq!(cpu.step(), 2 + 7);
+        assert_eq!(cpu.interrupt, Some(irq));
+        assert_eq!(cpu.peek_memory(0x01FD) & 0x04, 0x04);
+
+        // an NMI on the last cycle waits for the next instruction
+        let mut cpu = lines(&[0xEA, 0xEA]);
+        cpu.bus.nmi_at = Some(2);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 2 + 7);
+        assert_eq!(cpu.interrupt, Some(nmi));
+
+        // a taken branch that stays on its page doesn't poll on its last two cycles
+        let mut cpu = lines(&[0xD0, 0x00, 0xEA]); // BNE +0
+        cpu.bus.nmi_at = Some(2);
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.interrupt, None);
+        assert_eq!(cpu.step(), 2 + 7);
+        assert_eq!(cpu.interrupt, Some(nmi));
+
+        // an NMI while BRK pushes takes over its vector, B still gets pushed
+        let mut cpu = lines(&[0x00, 0x00]);
+        cpu.bus.nmi_at = Some(4);
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert_eq!(cpu.peek_memory(0x01FD) & 0x10, 0x10);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.interrupt, None);
+    }
+
+    #[test]
+    fn test_trace_line() {
+        let mut cpu = setup_cpu();
+        cpu.bus.load(0xC000, &[0x4C, 0xF5, 0xC5]);
+        cpu.reset();
+        cpu.program_counter = 0xC000;
+        assert_eq!(
+            cpu.trace_line(),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_assembled_program() {
+        let mut cpu = Cpu::with_program(asm!("LDA #$42\n STA $0200"));
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.read_memory(0x0200), 0x42);
+    }
+
+    #[test]
+    fn test_assembled_loop() {
+        let mut cpu = Cpu::with_program(asm!(
+            "
+                    LDX #5
+                    LDA #0
+                    CLC
+            @add:   ADC table-1,X
+                    DEX
+                    BNE @add
+                    STA $00
+            @done:  JMP @done
+            table:  .byte 1, 2, 3, 4, 5
+            "
+        ));
+        while cpu.program_counter != 0x060D {
+            cpu.step();
+        }
+        assert_eq!(cpu.read_memory(0x00), 15);
+        assert_eq!(cpu.idx, 0);
+        assert!(cpu.flags.zero);
+    }
 }
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use crate::cpu::cpu::CpuVariant;
+use crate::cpu::opcodes::{self, AddressingMode, Opcode};
+use crate::memory::bus::Bus;
+use crate::symbols::symbols::SymbolTable;
+
+/// A decoded instruction, read straight out of memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub byte: u8,
+    pub opcode: Opcode,
+    /// the operand bytes as a little endian value, zero when there are none
+    pub operand: u16,
+}
+
+impl Instruction {
+    /// the raw bytes of the instruction, opcode included
+    pub fn bytes(&self) -> Vec<u8> {
+        let [lo, hi] = self.operand.to_le_bytes();
+        [self.byte, lo, hi][..self.opcode.size() as usize].to_vec()
+    }
+    /// the address the operand refers to before indexing, or the branch destination
+    pub fn target(&self) -> Option<u16> {
+        match self.opcode.mode {
+            AddressingMode::Implied | AddressingMode::Accumulator | AddressingMode::Immediate => {
+                None
+            }
+            AddressingMode::Relative => Some(
+                self.address
+                    .wrapping_add(2)
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            AddressingMode::ZeroPageRelative => Some(
+                self.address
+                    .wrapping_add(3)
+                    .wrapping_add((self.operand >> 8) as u8 as i8 as u16),
+            ),
+            _ => Some(self.operand),
+        }
+    }
+    /// formats the instruction, naming addresses found in `symbols`
+    pub fn to_string_with(&self, symbols: Option<&SymbolTable>) -> String {
+        self.format(|address| symbols.and_then(|symbols| symbols.label(address)))
+    }
+    /// formats the instruction, naming addresses in the banks that are mapped on `bus`
+    pub fn to_string_on<B: Bus>(&self, bus: &B, symbols: &SymbolTable) -> String {
+        self.format(|address| symbols.label_on(bus, address))
+    }
+    fn format<'a>(&self, label: impl Fn(u16) -> Option<&'a str>) -> String {
+        let mnemonic = self.opcode.mnemonic;
+        let wide =
+            self.opcode.mode.operand_len() == 2 || self.opcode.mode == AddressingMode::Relative;
+        let target = match self.target() {
+            Some(address) => match label(address) {
+                Some(label) => label.to_string(),
+                None if wide => format!("${:04X}", address),
+                None => format!("${:02X}", address),
+            },
+            None => String::new(),
+        };
+        match self.opcode.mode {
+            AddressingMode::Implied => format!("{}", mnemonic),
+            AddressingMode::Accumulator => format!("{} A", mnemonic),
+            AddressingMode::Immediate => format!("{} #${:02X}", mnemonic, self.operand),
+            AddressingMode::ZeroPage | AddressingMode::Absolute | AddressingMode::Relative => {
+                format!("{} {}", mnemonic, target)
+            }
+            AddressingMode::ZeroPageX | AddressingMode::AbsoluteX => {
+                format!("{} {},X", mnemonic, target)
+            }
+            AddressingMode::ZeroPageY | AddressingMode::AbsoluteY => {
+                format!("{} {},Y", mnemonic, target)
+            }
+            AddressingMode::Indirect => format!("{} ({})", mnemonic, target),
+            AddressingMode::IndirectX => format!("{} ({},X)", mnemonic, target),
+            AddressingMode::IndirectY => format!("{} ({}),Y", mnemonic, target),
+            AddressingMode::ZeroPageIndirect => format!("{} ({})", mnemonic, target),
+            AddressingMode::AbsoluteIndirectX => format!("{} ({},X)", mnemonic, target),
+            AddressingMode::ZeroPageRelative => {
+                let zero_page = self.operand & 0xFF;
+                match label(zero_page) {
+                    Some(name) => format!("{} {},{}", mnemonic, name, target),
+                    None => format!("{} ${:02X},{}", mnemonic, zero_page, target),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.to_string_with(None))
+    }
+}
+
+/// Decodes the instruction at `address` with the opcode table a `variant` CPU executes from,
+/// returning it along with its length in bytes. Memory is only peeked
+pub fn disassemble<B: Bus>(bus: &B, address: u16, variant: CpuVariant) -> (Instruction, u16) {
+    let byte = bus.peek(address);
+    let opcode = opcodes::decode_for(variant, byte);
+    let operand = match opcode.mode.operand_len() {
+        0 => 0,
+        1 => bus.peek(address.wrapping_add(1)) as u16,
+        _ => u16::from_le_bytes([
+            bus.peek(address.wrapping_add(1)),
+            bus.peek(address.wrapping_add(2)),
+        ]),
+    };
+    let instruction = Instruction {
+        address,
+        byte,
+        opcode,
+        operand,
+    };
+    (instruction, opcode.size())
+}
+
+/// Linearly disassembles every instruction starting in `start..=end`
+pub fn disassemble_range<B: Bus>(
+    bus: &B,
+    start: u16,
+    end: u16,
+    variant: CpuVariant,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let (instruction, len) = disassemble(bus, address as u16, variant);
+        instructions.push(instruction);
+        address += len as u32;
+    }
+    instructions
+}
+
+/// An assembler style listing of `start..=end`, with labels on lines of their own and the
+/// source line each instruction came from, when `symbols` know it
+pub fn listing<B: Bus>(
+    bus: &B,
+    start: u16,
+    end: u16,
+    variant: CpuVariant,
+    symbols: Option<&SymbolTable>,
+) -> String {
+    let empty = SymbolTable::new();
+    let symbols = symbols.unwrap_or(&empty);
+    let mut listing = String::new();
+    for instruction in disassemble_range(bus, start, end, variant) {
+        if let Some(label) = symbols.label_on(bus, instruction.address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        let bytes: Vec<String> = instruction
+            .bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let mut line = format!(
+            "{:04X}  {:<8}  {}",
+            instruction.address,
+            bytes.join(" "),
+            instruction.to_string_on(bus, symbols)
+        );
+        if let Some((file, number)) = symbols.source_on(bus, instruction.address) {
+            line = format!("{:<30}; {}:{}", line, file, number);
+        }
+        listing.push_str(&line);
+        listing.push('\n');
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::memory::Memory;
+
+    fn memory_with(start: u16, program: &[u8]) -> Memory {
+        let mut memory = Memory::new();
+        memory.load(start, program);
+        memory
+    }
+
+    #[test]
+    fn standard_syntax() {
+        let cases: [(&[u8], &str); 9] = [
+            (&[0xBD, 0x34, 0x12], "LDA $1234,X"),
+            (&[0xD0, 0xEE], "BNE $C0F2"),
+            (&[0xA9, 0x42], "LDA #$42"),
+            (&[0x0A], "ASL A"),
+            (&[0xB6, 0x10], "LDX $10,Y"),
+            (&[0x6C, 0xFC, 0xFF], "JMP ($FFFC)"),
+            (&[0xA1, 0x80], "LDA ($80,X)"),
+            (&[0x91, 0x02], "STA ($02),Y"),
+            (&[0xA7, 0x10], "LAX $10"),
+        ];
+        for (bytes, expected) in cases {
+            let memory = memory_with(0xC102, bytes);
+            let (instruction, len) = disassemble(&memory, 0xC102, CpuVariant::Ricoh2A03);
+            assert_eq!(instruction.to_string(), expected);
+            assert_eq!(len as usize, bytes.len());
+            assert_eq!(instruction.bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn cmos_syntax() {
+        let cases: [(&[u8], &str); 6] = [
+            (&[0xB2, 0x10], "LDA ($10)"),
+            (&[0x7C, 0x00, 0x20], "JMP ($2000,X)"),
+            (&[0x8F, 0x10, 0xFD], "BBS0 $10,$C102"),
+            (&[0x77, 0x10], "RMB7 $10"),
+            (&[0x1A], "INC A"),
+            (&[0xDA], "PHX"),
+        ];
+        for (bytes, expected) in cases {
+            let memory = memory_with(0xC102, bytes);
+            let (instruction, len) = disassemble(&memory, 0xC102, CpuVariant::Cmos65C02);
+            assert_eq!(instruction.to_string(), expected);
+            assert_eq!(len as usize, bytes.len());
+        }
+    }
+
+    #[test]
+    fn symbolic_listing() {
+        let memory = memory_with(
+            0xC000,
+            &[0x20, 0x06, 0xC0, 0x4C, 0x00, 0xC0, 0x85, 0x10, 0x60],
+        );
+        let symbols =
+            SymbolTable::parse_vice("al 00C000 .Reset\nal 00C006 .Update\nal 000010 .frame")
+                .unwrap();
+        let instructions = disassemble_range(&memory, 0xC000, 0xC008, CpuVariant::Ricoh2A03);
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(
+            listing(
+                &memory,
+                0xC000,
+                0xC008,
+                CpuVariant::Ricoh2A03,
+                Some(&symbols)
+            ),
+            "Reset:\n\
+             C000  20 06 C0  JSR Update\n\
+             C003  4C 00 C0  JMP Reset\n\
+             Update:\n\
+             C006  85 10     STA frame\n\
+             C008  60        RTS\n"
+        );
+    }
+}
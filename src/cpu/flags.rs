@@ -1,4 +1,6 @@
-#[derive(Default, Debug, PartialEq)]
+use std::fmt;
+
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub struct Flags {
     pub carry: bool,
     pub zero: bool,
@@ -18,7 +20,7 @@ pub struct Flags {
 // 0	Carry (C)	Set if a carry/borrow has occurred in arithmetic operations
 
 impl Flags {
-    pub fn into_u8(&self) -> u8 {
+    pub fn into_u8(self) -> u8 {
         (self.negative as u8) << 7 |
         (self.overflow as u8) << 6 |
         1 << 5 | // bit 5 is always set
@@ -26,15 +28,38 @@ impl Flags {
         (self.decimal_mode as u8) << 3 |
         (self.interrupt_disable as u8) << 2 |
         (self.zero as u8) << 1 |
-        (self.carry as u8) << 0
+        (self.carry as u8)
+    }
+    pub fn from_u8(val: u8) -> Self {
+        Self {
+            negative: val & (1 << 7) != 0,
+            overflow: val & (1 << 6) != 0,
+            break_command: val & (1 << 4) != 0,
+            decimal_mode: val & (1 << 3) != 0,
+            interrupt_disable: val & (1 << 2) != 0,
+            zero: val & (1 << 1) != 0,
+            carry: val & 1 != 0,
+        }
     }
-    pub fn from_u8(&mut self, val: u8) {
-        self.negative = (val as u8) << 7 == 1;
-        self.overflow = (val as u8) << 6 == 1;
-        self.break_command = (val as u8) << 4 == 1;
-        self.decimal_mode = (val as u8) << 3 == 1;
-        self.interrupt_disable = (val as u8) << 2 == 1;
-        self.zero = (val as u8) << 1 == 1;
-        self.carry = (val as u8) << 0 == 1;
+}
+
+/// `NV-BDIZC`, upper case when set and lower case when clear
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            ('n', self.negative),
+            ('v', self.overflow),
+            ('-', false),
+            ('b', self.break_command),
+            ('d', self.decimal_mode),
+            ('i', self.interrupt_disable),
+            ('z', self.zero),
+            ('c', self.carry),
+        ];
+        for (name, set) in flags {
+            let name = if set { name.to_ascii_uppercase() } else { name };
+            write!(f, "{}", name)?;
+        }
+        Ok(())
     }
 }
@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 pub mod cpu;
 #[cfg(test)]
 mod cpu_tests;
-mod flags;
-mod opcodes;
+pub mod disassembler;
+pub mod flags;
+pub mod opcodes;
+mod trace;
@@ -1,2 +1,722 @@
-use crate::cpu::cpu::Cpu;
-impl Cpu {}
+use std::fmt;
+
+use crate::cpu::cpu::CpuVariant;
+
+use AddressingMode::*;
+use Mnemonic::*;
+
+/// Every instruction the 2A03 can decode, including the undocumented ones that
+/// commercial games and test ROMs (nestest in particular) rely on, and the ones the
+/// 65C02 added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+    // undocumented
+    Alr,
+    Anc,
+    Ane,
+    Arr,
+    Axs,
+    Dcp,
+    Isb,
+    Jam,
+    Las,
+    Lax,
+    Lxa,
+    Rla,
+    Rra,
+    Sax,
+    Sha,
+    Shx,
+    Shy,
+    Slo,
+    Sre,
+    Tas,
+    // 65C02
+    Bra,
+    Phx,
+    Phy,
+    Plx,
+    Ply,
+    Stz,
+    Trb,
+    Tsb,
+    Wai,
+    Stp,
+    /// the Rockwell and WDC bit instructions, with the bit they work on
+    Rmb(u8),
+    Smb(u8),
+    Bbr(u8),
+    Bbs(u8),
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rmb(bit) => f.pad(&format!("RMB{}", bit)),
+            Smb(bit) => f.pad(&format!("SMB{}", bit)),
+            Bbr(bit) => f.pad(&format!("BBR{}", bit)),
+            Bbs(bit) => f.pad(&format!("BBS{}", bit)),
+            _ => f.pad(&format!("{:?}", self).to_uppercase()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    /// `($nn,X)`
+    IndirectX,
+    /// `($nn),Y`
+    IndirectY,
+    Relative,
+    /// `($nn)`, 65C02 only
+    ZeroPageIndirect,
+    /// `($nnnn,X)`, the 65C02's JMP
+    AbsoluteIndirectX,
+    /// `$nn,label`, a zero page address and a branch offset for BBR and BBS
+    ZeroPageRelative,
+}
+
+impl AddressingMode {
+    /// number of operand bytes following the opcode
+    pub const fn operand_len(&self) -> u16 {
+        match self {
+            Implied | Accumulator => 0,
+            Absolute | AbsoluteX | AbsoluteY | Indirect | AbsoluteIndirectX | ZeroPageRelative => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    /// base cycle count, not including page crossing or taken branch penalties
+    pub cycles: u8,
+    /// read instructions take one more cycle when indexing crosses a page
+    pub page_penalty: bool,
+    pub official: bool,
+}
+
+impl Opcode {
+    const fn page_penalty(mut self) -> Self {
+        self.page_penalty = true;
+        self
+    }
+    const fn unofficial(mut self) -> Self {
+        self.official = false;
+        self
+    }
+    /// total instruction length in bytes, opcode included
+    pub const fn size(&self) -> u16 {
+        1 + self.mode.operand_len()
+    }
+}
+
+const fn op(mnemonic: Mnemonic, mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        cycles,
+        page_penalty: false,
+        official: true,
+    }
+}
+
+pub const OPCODES: [Opcode; 256] = [
+    /* 00 */ op(Brk, Implied, 7),
+    /* 01 */ op(Ora, IndirectX, 6),
+    /* 02 */ op(Jam, Implied, 2).unofficial(),
+    /* 03 */ op(Slo, IndirectX, 8).unofficial(),
+    /* 04 */ op(Nop, ZeroPage, 3).unofficial(),
+    /* 05 */ op(Ora, ZeroPage, 3),
+    /* 06 */ op(Asl, ZeroPage, 5),
+    /* 07 */ op(Slo, ZeroPage, 5).unofficial(),
+    /* 08 */ op(Php, Implied, 3),
+    /* 09 */ op(Ora, Immediate, 2),
+    /* 0A */ op(Asl, Accumulator, 2),
+    /* 0B */ op(Anc, Immediate, 2).unofficial(),
+    /* 0C */ op(Nop, Absolute, 4).unofficial(),
+    /* 0D */ op(Ora, Absolute, 4),
+    /* 0E */ op(Asl, Absolute, 6),
+    /* 0F */ op(Slo, Absolute, 6).unofficial(),
+    /* 10 */ op(Bpl, Relative, 2),
+    /* 11 */ op(Ora, IndirectY, 5).page_penalty(),
+    /* 12 */ op(Jam, Implied, 2).unofficial(),
+    /* 13 */ op(Slo, IndirectY, 8).unofficial(),
+    /* 14 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* 15 */ op(Ora, ZeroPageX, 4),
+    /* 16 */ op(Asl, ZeroPageX, 6),
+    /* 17 */ op(Slo, ZeroPageX, 6).unofficial(),
+    /* 18 */ op(Clc, Implied, 2),
+    /* 19 */ op(Ora, AbsoluteY, 4).page_penalty(),
+    /* 1A */ op(Nop, Implied, 2).unofficial(),
+    /* 1B */ op(Slo, AbsoluteY, 7).unofficial(),
+    /* 1C */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* 1D */ op(Ora, AbsoluteX, 4).page_penalty(),
+    /* 1E */ op(Asl, AbsoluteX, 7),
+    /* 1F */ op(Slo, AbsoluteX, 7).unofficial(),
+    /* 20 */ op(Jsr, Absolute, 6),
+    /* 21 */ op(And, IndirectX, 6),
+    /* 22 */ op(Jam, Implied, 2).unofficial(),
+    /* 23 */ op(Rla, IndirectX, 8).unofficial(),
+    /* 24 */ op(Bit, ZeroPage, 3),
+    /* 25 */ op(And, ZeroPage, 3),
+    /* 26 */ op(Rol, ZeroPage, 5),
+    /* 27 */ op(Rla, ZeroPage, 5).unofficial(),
+    /* 28 */ op(Plp, Implied, 4),
+    /* 29 */ op(And, Immediate, 2),
+    /* 2A */ op(Rol, Accumulator, 2),
+    /* 2B */ op(Anc, Immediate, 2).unofficial(),
+    /* 2C */ op(Bit, Absolute, 4),
+    /* 2D */ op(And, Absolute, 4),
+    /* 2E */ op(Rol, Absolute, 6),
+    /* 2F */ op(Rla, Absolute, 6).unofficial(),
+    /* 30 */ op(Bmi, Relative, 2),
+    /* 31 */ op(And, IndirectY, 5).page_penalty(),
+    /* 32 */ op(Jam, Implied, 2).unofficial(),
+    /* 33 */ op(Rla, IndirectY, 8).unofficial(),
+    /* 34 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* 35 */ op(And, ZeroPageX, 4),
+    /* 36 */ op(Rol, ZeroPageX, 6),
+    /* 37 */ op(Rla, ZeroPageX, 6).unofficial(),
+    /* 38 */ op(Sec, Implied, 2),
+    /* 39 */ op(And, AbsoluteY, 4).page_penalty(),
+    /* 3A */ op(Nop, Implied, 2).unofficial(),
+    /* 3B */ op(Rla, AbsoluteY, 7).unofficial(),
+    /* 3C */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* 3D */ op(And, AbsoluteX, 4).page_penalty(),
+    /* 3E */ op(Rol, AbsoluteX, 7),
+    /* 3F */ op(Rla, AbsoluteX, 7).unofficial(),
+    /* 40 */ op(Rti, Implied, 6),
+    /* 41 */ op(Eor, IndirectX, 6),
+    /* 42 */ op(Jam, Implied, 2).unofficial(),
+    /* 43 */ op(Sre, IndirectX, 8).unofficial(),
+    /* 44 */ op(Nop, ZeroPage, 3).unofficial(),
+    /* 45 */ op(Eor, ZeroPage, 3),
+    /* 46 */ op(Lsr, ZeroPage, 5),
+    /* 47 */ op(Sre, ZeroPage, 5).unofficial(),
+    /* 48 */ op(Pha, Implied, 3),
+    /* 49 */ op(Eor, Immediate, 2),
+    /* 4A */ op(Lsr, Accumulator, 2),
+    /* 4B */ op(Alr, Immediate, 2).unofficial(),
+    /* 4C */ op(Jmp, Absolute, 3),
+    /* 4D */ op(Eor, Absolute, 4),
+    /* 4E */ op(Lsr, Absolute, 6),
+    /* 4F */ op(Sre, Absolute, 6).unofficial(),
+    /* 50 */ op(Bvc, Relative, 2),
+    /* 51 */ op(Eor, IndirectY, 5).page_penalty(),
+    /* 52 */ op(Jam, Implied, 2).unofficial(),
+    /* 53 */ op(Sre, IndirectY, 8).unofficial(),
+    /* 54 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* 55 */ op(Eor, ZeroPageX, 4),
+    /* 56 */ op(Lsr, ZeroPageX, 6),
+    /* 57 */ op(Sre, ZeroPageX, 6).unofficial(),
+    /* 58 */ op(Cli, Implied, 2),
+    /* 59 */ op(Eor, AbsoluteY, 4).page_penalty(),
+    /* 5A */ op(Nop, Implied, 2).unofficial(),
+    /* 5B */ op(Sre, AbsoluteY, 7).unofficial(),
+    /* 5C */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* 5D */ op(Eor, AbsoluteX, 4).page_penalty(),
+    /* 5E */ op(Lsr, AbsoluteX, 7),
+    /* 5F */ op(Sre, AbsoluteX, 7).unofficial(),
+    /* 60 */ op(Rts, Implied, 6),
+    /* 61 */ op(Adc, IndirectX, 6),
+    /* 62 */ op(Jam, Implied, 2).unofficial(),
+    /* 63 */ op(Rra, IndirectX, 8).unofficial(),
+    /* 64 */ op(Nop, ZeroPage, 3).unofficial(),
+    /* 65 */ op(Adc, ZeroPage, 3),
+    /* 66 */ op(Ror, ZeroPage, 5),
+    /* 67 */ op(Rra, ZeroPage, 5).unofficial(),
+    /* 68 */ op(Pla, Implied, 4),
+    /* 69 */ op(Adc, Immediate, 2),
+    /* 6A */ op(Ror, Accumulator, 2),
+    /* 6B */ op(Arr, Immediate, 2).unofficial(),
+    /* 6C */ op(Jmp, Indirect, 5),
+    /* 6D */ op(Adc, Absolute, 4),
+    /* 6E */ op(Ror, Absolute, 6),
+    /* 6F */ op(Rra, Absolute, 6).unofficial(),
+    /* 70 */ op(Bvs, Relative, 2),
+    /* 71 */ op(Adc, IndirectY, 5).page_penalty(),
+    /* 72 */ op(Jam, Implied, 2).unofficial(),
+    /* 73 */ op(Rra, IndirectY, 8).unofficial(),
+    /* 74 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* 75 */ op(Adc, ZeroPageX, 4),
+    /* 76 */ op(Ror, ZeroPageX, 6),
+    /* 77 */ op(Rra, ZeroPageX, 6).unofficial(),
+    /* 78 */ op(Sei, Implied, 2),
+    /* 79 */ op(Adc, AbsoluteY, 4).page_penalty(),
+    /* 7A */ op(Nop, Implied, 2).unofficial(),
+    /* 7B */ op(Rra, AbsoluteY, 7).unofficial(),
+    /* 7C */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* 7D */ op(Adc, AbsoluteX, 4).page_penalty(),
+    /* 7E */ op(Ror, AbsoluteX, 7),
+    /* 7F */ op(Rra, AbsoluteX, 7).unofficial(),
+    /* 80 */ op(Nop, Immediate, 2).unofficial(),
+    /* 81 */ op(Sta, IndirectX, 6),
+    /* 82 */ op(Nop, Immediate, 2).unofficial(),
+    /* 83 */ op(Sax, IndirectX, 6).unofficial(),
+    /* 84 */ op(Sty, ZeroPage, 3),
+    /* 85 */ op(Sta, ZeroPage, 3),
+    /* 86 */ op(Stx, ZeroPage, 3),
+    /* 87 */ op(Sax, ZeroPage, 3).unofficial(),
+    /* 88 */ op(Dey, Implied, 2),
+    /* 89 */ op(Nop, Immediate, 2).unofficial(),
+    /* 8A */ op(Txa, Implied, 2),
+    /* 8B */ op(Ane, Immediate, 2).unofficial(),
+    /* 8C */ op(Sty, Absolute, 4),
+    /* 8D */ op(Sta, Absolute, 4),
+    /* 8E */ op(Stx, Absolute, 4),
+    /* 8F */ op(Sax, Absolute, 4).unofficial(),
+    /* 90 */ op(Bcc, Relative, 2),
+    /* 91 */ op(Sta, IndirectY, 6),
+    /* 92 */ op(Jam, Implied, 2).unofficial(),
+    /* 93 */ op(Sha, IndirectY, 6).unofficial(),
+    /* 94 */ op(Sty, ZeroPageX, 4),
+    /* 95 */ op(Sta, ZeroPageX, 4),
+    /* 96 */ op(Stx, ZeroPageY, 4),
+    /* 97 */ op(Sax, ZeroPageY, 4).unofficial(),
+    /* 98 */ op(Tya, Implied, 2),
+    /* 99 */ op(Sta, AbsoluteY, 5),
+    /* 9A */ op(Txs, Implied, 2),
+    /* 9B */ op(Tas, AbsoluteY, 5).unofficial(),
+    /* 9C */ op(Shy, AbsoluteX, 5).unofficial(),
+    /* 9D */ op(Sta, AbsoluteX, 5),
+    /* 9E */ op(Shx, AbsoluteY, 5).unofficial(),
+    /* 9F */ op(Sha, AbsoluteY, 5).unofficial(),
+    /* A0 */ op(Ldy, Immediate, 2),
+    /* A1 */ op(Lda, IndirectX, 6),
+    /* A2 */ op(Ldx, Immediate, 2),
+    /* A3 */ op(Lax, IndirectX, 6).unofficial(),
+    /* A4 */ op(Ldy, ZeroPage, 3),
+    /* A5 */ op(Lda, ZeroPage, 3),
+    /* A6 */ op(Ldx, ZeroPage, 3),
+    /* A7 */ op(Lax, ZeroPage, 3).unofficial(),
+    /* A8 */ op(Tay, Implied, 2),
+    /* A9 */ op(Lda, Immediate, 2),
+    /* AA */ op(Tax, Implied, 2),
+    /* AB */ op(Lxa, Immediate, 2).unofficial(),
+    /* AC */ op(Ldy, Absolute, 4),
+    /* AD */ op(Lda, Absolute, 4),
+    /* AE */ op(Ldx, Absolute, 4),
+    /* AF */ op(Lax, Absolute, 4).unofficial(),
+    /* B0 */ op(Bcs, Relative, 2),
+    /* B1 */ op(Lda, IndirectY, 5).page_penalty(),
+    /* B2 */ op(Jam, Implied, 2).unofficial(),
+    /* B3 */ op(Lax, IndirectY, 5).page_penalty().unofficial(),
+    /* B4 */ op(Ldy, ZeroPageX, 4),
+    /* B5 */ op(Lda, ZeroPageX, 4),
+    /* B6 */ op(Ldx, ZeroPageY, 4),
+    /* B7 */ op(Lax, ZeroPageY, 4).unofficial(),
+    /* B8 */ op(Clv, Implied, 2),
+    /* B9 */ op(Lda, AbsoluteY, 4).page_penalty(),
+    /* BA */ op(Tsx, Implied, 2),
+    /* BB */ op(Las, AbsoluteY, 4).page_penalty().unofficial(),
+    /* BC */ op(Ldy, AbsoluteX, 4).page_penalty(),
+    /* BD */ op(Lda, AbsoluteX, 4).page_penalty(),
+    /* BE */ op(Ldx, AbsoluteY, 4).page_penalty(),
+    /* BF */ op(Lax, AbsoluteY, 4).page_penalty().unofficial(),
+    /* C0 */ op(Cpy, Immediate, 2),
+    /* C1 */ op(Cmp, IndirectX, 6),
+    /* C2 */ op(Nop, Immediate, 2).unofficial(),
+    /* C3 */ op(Dcp, IndirectX, 8).unofficial(),
+    /* C4 */ op(Cpy, ZeroPage, 3),
+    /* C5 */ op(Cmp, ZeroPage, 3),
+    /* C6 */ op(Dec, ZeroPage, 5),
+    /* C7 */ op(Dcp, ZeroPage, 5).unofficial(),
+    /* C8 */ op(Iny, Implied, 2),
+    /* C9 */ op(Cmp, Immediate, 2),
+    /* CA */ op(Dex, Implied, 2),
+    /* CB */ op(Axs, Immediate, 2).unofficial(),
+    /* CC */ op(Cpy, Absolute, 4),
+    /* CD */ op(Cmp, Absolute, 4),
+    /* CE */ op(Dec, Absolute, 6),
+    /* CF */ op(Dcp, Absolute, 6).unofficial(),
+    /* D0 */ op(Bne, Relative, 2),
+    /* D1 */ op(Cmp, IndirectY, 5).page_penalty(),
+    /* D2 */ op(Jam, Implied, 2).unofficial(),
+    /* D3 */ op(Dcp, IndirectY, 8).unofficial(),
+    /* D4 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* D5 */ op(Cmp, ZeroPageX, 4),
+    /* D6 */ op(Dec, ZeroPageX, 6),
+    /* D7 */ op(Dcp, ZeroPageX, 6).unofficial(),
+    /* D8 */ op(Cld, Implied, 2),
+    /* D9 */ op(Cmp, AbsoluteY, 4).page_penalty(),
+    /* DA */ op(Nop, Implied, 2).unofficial(),
+    /* DB */ op(Dcp, AbsoluteY, 7).unofficial(),
+    /* DC */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* DD */ op(Cmp, AbsoluteX, 4).page_penalty(),
+    /* DE */ op(Dec, AbsoluteX, 7),
+    /* DF */ op(Dcp, AbsoluteX, 7).unofficial(),
+    /* E0 */ op(Cpx, Immediate, 2),
+    /* E1 */ op(Sbc, IndirectX, 6),
+    /* E2 */ op(Nop, Immediate, 2).unofficial(),
+    /* E3 */ op(Isb, IndirectX, 8).unofficial(),
+    /* E4 */ op(Cpx, ZeroPage, 3),
+    /* E5 */ op(Sbc, ZeroPage, 3),
+    /* E6 */ op(Inc, ZeroPage, 5),
+    /* E7 */ op(Isb, ZeroPage, 5).unofficial(),
+    /* E8 */ op(Inx, Implied, 2),
+    /* E9 */ op(Sbc, Immediate, 2),
+    /* EA */ op(Nop, Implied, 2),
+    /* EB */ op(Sbc, Immediate, 2).unofficial(),
+    /* EC */ op(Cpx, Absolute, 4),
+    /* ED */ op(Sbc, Absolute, 4),
+    /* EE */ op(Inc, Absolute, 6),
+    /* EF */ op(Isb, Absolute, 6).unofficial(),
+    /* F0 */ op(Beq, Relative, 2),
+    /* F1 */ op(Sbc, IndirectY, 5).page_penalty(),
+    /* F2 */ op(Jam, Implied, 2).unofficial(),
+    /* F3 */ op(Isb, IndirectY, 8).unofficial(),
+    /* F4 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* F5 */ op(Sbc, ZeroPageX, 4),
+    /* F6 */ op(Inc, ZeroPageX, 6),
+    /* F7 */ op(Isb, ZeroPageX, 6).unofficial(),
+    /* F8 */ op(Sed, Implied, 2),
+    /* F9 */ op(Sbc, AbsoluteY, 4).page_penalty(),
+    /* FA */ op(Nop, Implied, 2).unofficial(),
+    /* FB */ op(Isb, AbsoluteY, 7).unofficial(),
+    /* FC */ op(Nop, AbsoluteX, 4).page_penalty().unofficial(),
+    /* FD */ op(Sbc, AbsoluteX, 4).page_penalty(),
+    /* FE */ op(Inc, AbsoluteX, 7),
+    /* FF */ op(Isb, AbsoluteX, 7).unofficial(),
+];
+
+pub fn decode(byte: u8) -> Opcode {
+    OPCODES[byte as usize]
+}
+
+/// The WDC 65C02 with the Rockwell bit instructions. Every opcode the NMOS parts left
+/// undocumented is a NOP of some length here, those are marked unofficial
+pub const OPCODES_65C02: [Opcode; 256] = [
+    /* 00 */ op(Brk, Implied, 7),
+    /* 01 */ op(Ora, IndirectX, 6),
+    /* 02 */ op(Nop, Immediate, 2).unofficial(),
+    /* 03 */ op(Nop, Implied, 1).unofficial(),
+    /* 04 */ op(Tsb, ZeroPage, 5),
+    /* 05 */ op(Ora, ZeroPage, 3),
+    /* 06 */ op(Asl, ZeroPage, 5),
+    /* 07 */ op(Rmb(0), ZeroPage, 5),
+    /* 08 */ op(Php, Implied, 3),
+    /* 09 */ op(Ora, Immediate, 2),
+    /* 0A */ op(Asl, Accumulator, 2),
+    /* 0B */ op(Nop, Implied, 1).unofficial(),
+    /* 0C */ op(Tsb, Absolute, 6),
+    /* 0D */ op(Ora, Absolute, 4),
+    /* 0E */ op(Asl, Absolute, 6),
+    /* 0F */ op(Bbr(0), ZeroPageRelative, 5),
+    /* 10 */ op(Bpl, Relative, 2),
+    /* 11 */ op(Ora, IndirectY, 5).page_penalty(),
+    /* 12 */ op(Ora, ZeroPageIndirect, 5),
+    /* 13 */ op(Nop, Implied, 1).unofficial(),
+    /* 14 */ op(Trb, ZeroPage, 5),
+    /* 15 */ op(Ora, ZeroPageX, 4),
+    /* 16 */ op(Asl, ZeroPageX, 6),
+    /* 17 */ op(Rmb(1), ZeroPage, 5),
+    /* 18 */ op(Clc, Implied, 2),
+    /* 19 */ op(Ora, AbsoluteY, 4).page_penalty(),
+    /* 1A */ op(Inc, Accumulator, 2),
+    /* 1B */ op(Nop, Implied, 1).unofficial(),
+    /* 1C */ op(Trb, Absolute, 6),
+    /* 1D */ op(Ora, AbsoluteX, 4).page_penalty(),
+    /* 1E */ op(Asl, AbsoluteX, 6).page_penalty(),
+    /* 1F */ op(Bbr(1), ZeroPageRelative, 5),
+    /* 20 */ op(Jsr, Absolute, 6),
+    /* 21 */ op(And, IndirectX, 6),
+    /* 22 */ op(Nop, Immediate, 2).unofficial(),
+    /* 23 */ op(Nop, Implied, 1).unofficial(),
+    /* 24 */ op(Bit, ZeroPage, 3),
+    /* 25 */ op(And, ZeroPage, 3),
+    /* 26 */ op(Rol, ZeroPage, 5),
+    /* 27 */ op(Rmb(2), ZeroPage, 5),
+    /* 28 */ op(Plp, Implied, 4),
+    /* 29 */ op(And, Immediate, 2),
+    /* 2A */ op(Rol, Accumulator, 2),
+    /* 2B */ op(Nop, Implied, 1).unofficial(),
+    /* 2C */ op(Bit, Absolute, 4),
+    /* 2D */ op(And, Absolute, 4),
+    /* 2E */ op(Rol, Absolute, 6),
+    /* 2F */ op(Bbr(2), ZeroPageRelative, 5),
+    /* 30 */ op(Bmi, Relative, 2),
+    /* 31 */ op(And, IndirectY, 5).page_penalty(),
+    /* 32 */ op(And, ZeroPageIndirect, 5),
+    /* 33 */ op(Nop, Implied, 1).unofficial(),
+    /* 34 */ op(Bit, ZeroPageX, 4),
+    /* 35 */ op(And, ZeroPageX, 4),
+    /* 36 */ op(Rol, ZeroPageX, 6),
+    /* 37 */ op(Rmb(3), ZeroPage, 5),
+    /* 38 */ op(Sec, Implied, 2),
+    /* 39 */ op(And, AbsoluteY, 4).page_penalty(),
+    /* 3A */ op(Dec, Accumulator, 2),
+    /* 3B */ op(Nop, Implied, 1).unofficial(),
+    /* 3C */ op(Bit, AbsoluteX, 4).page_penalty(),
+    /* 3D */ op(And, AbsoluteX, 4).page_penalty(),
+    /* 3E */ op(Rol, AbsoluteX, 6).page_penalty(),
+    /* 3F */ op(Bbr(3), ZeroPageRelative, 5),
+    /* 40 */ op(Rti, Implied, 6),
+    /* 41 */ op(Eor, IndirectX, 6),
+    /* 42 */ op(Nop, Immediate, 2).unofficial(),
+    /* 43 */ op(Nop, Implied, 1).unofficial(),
+    /* 44 */ op(Nop, ZeroPage, 3).unofficial(),
+    /* 45 */ op(Eor, ZeroPage, 3),
+    /* 46 */ op(Lsr, ZeroPage, 5),
+    /* 47 */ op(Rmb(4), ZeroPage, 5),
+    /* 48 */ op(Pha, Implied, 3),
+    /* 49 */ op(Eor, Immediate, 2),
+    /* 4A */ op(Lsr, Accumulator, 2),
+    /* 4B */ op(Nop, Implied, 1).unofficial(),
+    /* 4C */ op(Jmp, Absolute, 3),
+    /* 4D */ op(Eor, Absolute, 4),
+    /* 4E */ op(Lsr, Absolute, 6),
+    /* 4F */ op(Bbr(4), ZeroPageRelative, 5),
+    /* 50 */ op(Bvc, Relative, 2),
+    /* 51 */ op(Eor, IndirectY, 5).page_penalty(),
+    /* 52 */ op(Eor, ZeroPageIndirect, 5),
+    /* 53 */ op(Nop, Implied, 1).unofficial(),
+    /* 54 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* 55 */ op(Eor, ZeroPageX, 4),
+    /* 56 */ op(Lsr, ZeroPageX, 6),
+    /* 57 */ op(Rmb(5), ZeroPage, 5),
+    /* 58 */ op(Cli, Implied, 2),
+    /* 59 */ op(Eor, AbsoluteY, 4).page_penalty(),
+    /* 5A */ op(Phy, Implied, 3),
+    /* 5B */ op(Nop, Implied, 1).unofficial(),
+    /* 5C */ op(Nop, Absolute, 8).unofficial(),
+    /* 5D */ op(Eor, AbsoluteX, 4).page_penalty(),
+    /* 5E */ op(Lsr, AbsoluteX, 6).page_penalty(),
+    /* 5F */ op(Bbr(5), ZeroPageRelative, 5),
+    /* 60 */ op(Rts, Implied, 6),
+    /* 61 */ op(Adc, IndirectX, 6),
+    /* 62 */ op(Nop, Immediate, 2).unofficial(),
+    /* 63 */ op(Nop, Implied, 1).unofficial(),
+    /* 64 */ op(Stz, ZeroPage, 3),
+    /* 65 */ op(Adc, ZeroPage, 3),
+    /* 66 */ op(Ror, ZeroPage, 5),
+    /* 67 */ op(Rmb(6), ZeroPage, 5),
+    /* 68 */ op(Pla, Implied, 4),
+    /* 69 */ op(Adc, Immediate, 2),
+    /* 6A */ op(Ror, Accumulator, 2),
+    /* 6B */ op(Nop, Implied, 1).unofficial(),
+    /* 6C */ op(Jmp, Indirect, 6),
+    /* 6D */ op(Adc, Absolute, 4),
+    /* 6E */ op(Ror, Absolute, 6),
+    /* 6F */ op(Bbr(6), ZeroPageRelative, 5),
+    /* 70 */ op(Bvs, Relative, 2),
+    /* 71 */ op(Adc, IndirectY, 5).page_penalty(),
+    /* 72 */ op(Adc, ZeroPageIndirect, 5),
+    /* 73 */ op(Nop, Implied, 1).unofficial(),
+    /* 74 */ op(Stz, ZeroPageX, 4),
+    /* 75 */ op(Adc, ZeroPageX, 4),
+    /* 76 */ op(Ror, ZeroPageX, 6),
+    /* 77 */ op(Rmb(7), ZeroPage, 5),
+    /* 78 */ op(Sei, Implied, 2),
+    /* 79 */ op(Adc, AbsoluteY, 4).page_penalty(),
+    /* 7A */ op(Ply, Implied, 4),
+    /* 7B */ op(Nop, Implied, 1).unofficial(),
+    /* 7C */ op(Jmp, AbsoluteIndirectX, 6),
+    /* 7D */ op(Adc, AbsoluteX, 4).page_penalty(),
+    /* 7E */ op(Ror, AbsoluteX, 6).page_penalty(),
+    /* 7F */ op(Bbr(7), ZeroPageRelative, 5),
+    /* 80 */ op(Bra, Relative, 2),
+    /* 81 */ op(Sta, IndirectX, 6),
+    /* 82 */ op(Nop, Immediate, 2).unofficial(),
+    /* 83 */ op(Nop, Implied, 1).unofficial(),
+    /* 84 */ op(Sty, ZeroPage, 3),
+    /* 85 */ op(Sta, ZeroPage, 3),
+    /* 86 */ op(Stx, ZeroPage, 3),
+    /* 87 */ op(Smb(0), ZeroPage, 5),
+    /* 88 */ op(Dey, Implied, 2),
+    /* 89 */ op(Bit, Immediate, 2),
+    /* 8A */ op(Txa, Implied, 2),
+    /* 8B */ op(Nop, Implied, 1).unofficial(),
+    /* 8C */ op(Sty, Absolute, 4),
+    /* 8D */ op(Sta, Absolute, 4),
+    /* 8E */ op(Stx, Absolute, 4),
+    /* 8F */ op(Bbs(0), ZeroPageRelative, 5),
+    /* 90 */ op(Bcc, Relative, 2),
+    /* 91 */ op(Sta, IndirectY, 6),
+    /* 92 */ op(Sta, ZeroPageIndirect, 5),
+    /* 93 */ op(Nop, Implied, 1).unofficial(),
+    /* 94 */ op(Sty, ZeroPageX, 4),
+    /* 95 */ op(Sta, ZeroPageX, 4),
+    /* 96 */ op(Stx, ZeroPageY, 4),
+    /* 97 */ op(Smb(1), ZeroPage, 5),
+    /* 98 */ op(Tya, Implied, 2),
+    /* 99 */ op(Sta, AbsoluteY, 5),
+    /* 9A */ op(Txs, Implied, 2),
+    /* 9B */ op(Nop, Implied, 1).unofficial(),
+    /* 9C */ op(Stz, Absolute, 4),
+    /* 9D */ op(Sta, AbsoluteX, 5),
+    /* 9E */ op(Stz, AbsoluteX, 5),
+    /* 9F */ op(Bbs(1), ZeroPageRelative, 5),
+    /* A0 */ op(Ldy, Immediate, 2),
+    /* A1 */ op(Lda, IndirectX, 6),
+    /* A2 */ op(Ldx, Immediate, 2),
+    /* A3 */ op(Nop, Implied, 1).unofficial(),
+    /* A4 */ op(Ldy, ZeroPage, 3),
+    /* A5 */ op(Lda, ZeroPage, 3),
+    /* A6 */ op(Ldx, ZeroPage, 3),
+    /* A7 */ op(Smb(2), ZeroPage, 5),
+    /* A8 */ op(Tay, Implied, 2),
+    /* A9 */ op(Lda, Immediate, 2),
+    /* AA */ op(Tax, Implied, 2),
+    /* AB */ op(Nop, Implied, 1).unofficial(),
+    /* AC */ op(Ldy, Absolute, 4),
+    /* AD */ op(Lda, Absolute, 4),
+    /* AE */ op(Ldx, Absolute, 4),
+    /* AF */ op(Bbs(2), ZeroPageRelative, 5),
+    /* B0 */ op(Bcs, Relative, 2),
+    /* B1 */ op(Lda, IndirectY, 5).page_penalty(),
+    /* B2 */ op(Lda, ZeroPageIndirect, 5),
+    /* B3 */ op(Nop, Implied, 1).unofficial(),
+    /* B4 */ op(Ldy, ZeroPageX, 4),
+    /* B5 */ op(Lda, ZeroPageX, 4),
+    /* B6 */ op(Ldx, ZeroPageY, 4),
+    /* B7 */ op(Smb(3), ZeroPage, 5),
+    /* B8 */ op(Clv, Implied, 2),
+    /* B9 */ op(Lda, AbsoluteY, 4).page_penalty(),
+    /* BA */ op(Tsx, Implied, 2),
+    /* BB */ op(Nop, Implied, 1).unofficial(),
+    /* BC */ op(Ldy, AbsoluteX, 4).page_penalty(),
+    /* BD */ op(Lda, AbsoluteX, 4).page_penalty(),
+    /* BE */ op(Ldx, AbsoluteY, 4).page_penalty(),
+    /* BF */ op(Bbs(3), ZeroPageRelative, 5),
+    /* C0 */ op(Cpy, Immediate, 2),
+    /* C1 */ op(Cmp, IndirectX, 6),
+    /* C2 */ op(Nop, Immediate, 2).unofficial(),
+    /* C3 */ op(Nop, Implied, 1).unofficial(),
+    /* C4 */ op(Cpy, ZeroPage, 3),
+    /* C5 */ op(Cmp, ZeroPage, 3),
+    /* C6 */ op(Dec, ZeroPage, 5),
+    /* C7 */ op(Smb(4), ZeroPage, 5),
+    /* C8 */ op(Iny, Implied, 2),
+    /* C9 */ op(Cmp, Immediate, 2),
+    /* CA */ op(Dex, Implied, 2),
+    /* CB */ op(Wai, Implied, 3),
+    /* CC */ op(Cpy, Absolute, 4),
+    /* CD */ op(Cmp, Absolute, 4),
+    /* CE */ op(Dec, Absolute, 6),
+    /* CF */ op(Bbs(4), ZeroPageRelative, 5),
+    /* D0 */ op(Bne, Relative, 2),
+    /* D1 */ op(Cmp, IndirectY, 5).page_penalty(),
+    /* D2 */ op(Cmp, ZeroPageIndirect, 5),
+    /* D3 */ op(Nop, Implied, 1).unofficial(),
+    /* D4 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* D5 */ op(Cmp, ZeroPageX, 4),
+    /* D6 */ op(Dec, ZeroPageX, 6),
+    /* D7 */ op(Smb(5), ZeroPage, 5),
+    /* D8 */ op(Cld, Implied, 2),
+    /* D9 */ op(Cmp, AbsoluteY, 4).page_penalty(),
+    /* DA */ op(Phx, Implied, 3),
+    /* DB */ op(Stp, Implied, 3),
+    /* DC */ op(Nop, Absolute, 4).unofficial(),
+    /* DD */ op(Cmp, AbsoluteX, 4).page_penalty(),
+    /* DE */ op(Dec, AbsoluteX, 7),
+    /* DF */ op(Bbs(5), ZeroPageRelative, 5),
+    /* E0 */ op(Cpx, Immediate, 2),
+    /* E1 */ op(Sbc, IndirectX, 6),
+    /* E2 */ op(Nop, Immediate, 2).unofficial(),
+    /* E3 */ op(Nop, Implied, 1).unofficial(),
+    /* E4 */ op(Cpx, ZeroPage, 3),
+    /* E5 */ op(Sbc, ZeroPage, 3),
+    /* E6 */ op(Inc, ZeroPage, 5),
+    /* E7 */ op(Smb(6), ZeroPage, 5),
+    /* E8 */ op(Inx, Implied, 2),
+    /* E9 */ op(Sbc, Immediate, 2),
+    /* EA */ op(Nop, Implied, 2),
+    /* EB */ op(Nop, Implied, 1).unofficial(),
+    /* EC */ op(Cpx, Absolute, 4),
+    /* ED */ op(Sbc, Absolute, 4),
+    /* EE */ op(Inc, Absolute, 6),
+    /* EF */ op(Bbs(6), ZeroPageRelative, 5),
+    /* F0 */ op(Beq, Relative, 2),
+    /* F1 */ op(Sbc, IndirectY, 5).page_penalty(),
+    /* F2 */ op(Sbc, ZeroPageIndirect, 5),
+    /* F3 */ op(Nop, Implied, 1).unofficial(),
+    /* F4 */ op(Nop, ZeroPageX, 4).unofficial(),
+    /* F5 */ op(Sbc, ZeroPageX, 4),
+    /* F6 */ op(Inc, ZeroPageX, 6),
+    /* F7 */ op(Smb(7), ZeroPage, 5),
+    /* F8 */ op(Sed, Implied, 2),
+    /* F9 */ op(Sbc, AbsoluteY, 4).page_penalty(),
+    /* FA */ op(Plx, Implied, 4),
+    /* FB */ op(Nop, Implied, 1).unofficial(),
+    /* FC */ op(Nop, Absolute, 4).unofficial(),
+    /* FD */ op(Sbc, AbsoluteX, 4).page_penalty(),
+    /* FE */ op(Inc, AbsoluteX, 7),
+    /* FF */ op(Bbs(7), ZeroPageRelative, 5),
+];
+
+/// decodes with the opcode table of `variant`
+pub fn decode_for(variant: CpuVariant, byte: u8) -> Opcode {
+    match variant {
+        CpuVariant::Cmos65C02 => OPCODES_65C02[byte as usize],
+        CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 => OPCODES[byte as usize],
+    }
+}
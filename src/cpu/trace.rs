@@ -0,0 +1,114 @@
+use crate::cpu::cpu::Cpu;
+use crate::cpu::disassembler::{self, Instruction};
+use crate::cpu::opcodes::{AddressingMode, Mnemonic};
+use crate::memory::bus::Bus;
+
+impl<B: Bus> Cpu<B> {
+    /// Formats the instruction at the program counter the way Nintendulator's nestest.log does,
+    /// e.g. `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`.
+    /// The PPU position is derived from the cycle counter (three dots per cycle)
+    pub fn trace_line(&self) -> String {
+        let (instruction, _) =
+            disassembler::disassemble(&self.bus, self.program_counter, self.variant);
+        let bytes: Vec<String> = instruction
+            .bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let marker = if instruction.opcode.official {
+            ' '
+        } else {
+            '*'
+        };
+        let dots = self.cycles * 3;
+        format!(
+            "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            instruction.address,
+            bytes.join(" "),
+            marker,
+            format!("{}{}", instruction, self.trace_annotation(&instruction)),
+            self.accumulator,
+            self.idx,
+            self.idy,
+            self.flags.into_u8(),
+            self.stack_pointer,
+            dots / 341 % 262,
+            dots % 341,
+            self.cycles
+        )
+    }
+
+    /// nestest's annotations after the operand: effective addresses and the values they hold
+    fn trace_annotation(&self, instruction: &Instruction) -> String {
+        let operand = instruction.operand;
+        match instruction.opcode.mode {
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::Relative => String::new(),
+            AddressingMode::ZeroPage => format!(" = {:02X}", self.peek_memory(operand)),
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let index = if instruction.opcode.mode == AddressingMode::ZeroPageX {
+                    self.idx
+                } else {
+                    self.idy
+                };
+                let address = (operand as u8).wrapping_add(index);
+                format!(
+                    " @ {:02X} = {:02X}",
+                    address,
+                    self.peek_memory(address as u16)
+                )
+            }
+            AddressingMode::Absolute => match instruction.opcode.mnemonic {
+                Mnemonic::Jmp | Mnemonic::Jsr => String::new(),
+                _ => format!(" = {:02X}", self.peek_memory(operand)),
+            },
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let index = if instruction.opcode.mode == AddressingMode::AbsoluteX {
+                    self.idx
+                } else {
+                    self.idy
+                };
+                let address = operand.wrapping_add(index as u16);
+                format!(" @ {:04X} = {:02X}", address, self.peek_memory(address))
+            }
+            AddressingMode::Indirect => format!(" = {:04X}", self.peek_pointer(operand)),
+            AddressingMode::IndirectX => {
+                let pointer = (operand as u8).wrapping_add(self.idx);
+                let address = self.peek_pointer(pointer as u16);
+                format!(
+                    " @ {:02X} = {:04X} = {:02X}",
+                    pointer,
+                    address,
+                    self.peek_memory(address)
+                )
+            }
+            AddressingMode::IndirectY => {
+                let base = self.peek_pointer(operand);
+                let address = base.wrapping_add(self.idy as u16);
+                format!(
+                    " = {:04X} @ {:04X} = {:02X}",
+                    base,
+                    address,
+                    self.peek_memory(address)
+                )
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let address = self.peek_pointer(operand);
+                format!(" = {:04X} = {:02X}", address, self.peek_memory(address))
+            }
+            AddressingMode::AbsoluteIndirectX => {
+                let pointer = operand.wrapping_add(self.idx as u16);
+                let address = u16::from_le_bytes([
+                    self.peek_memory(pointer),
+                    self.peek_memory(pointer.wrapping_add(1)),
+                ]);
+                format!(" @ {:04X} = {:04X}", pointer, address)
+            }
+            AddressingMode::ZeroPageRelative => {
+                format!(" = {:02X}", self.peek_memory(operand & 0xFF))
+            }
+        }
+    }
+}
@@ -0,0 +1,610 @@
+use std::cmp::Ordering;
+
+use crate::cpu::disassembler;
+use crate::debugger::debugger::{Debugger, Stop, Target, Watchpoint};
+use crate::debugger::profiler::Profiler;
+use crate::debugger::search::{Format, Operand, RamSearch, Watch};
+use crate::debugger::tracer::Tracer;
+
+/// What the front end should do after a console command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// print this and read the next command
+    Output(String),
+    /// leave the console and let the machine run, checking with `Debugger::step`
+    Resume,
+    Quit,
+}
+
+const HELP: &str = "\
+b <addr> [if <cond>]     break at an address, optionally only when cond holds
+w <r|w|x|rw> <addr> [end]  watch reads, writes or execution of an address range
+d <n> / dw <n>           delete breakpoint / watchpoint n
+l                        list breakpoints and watchpoints
+c                        continue
+s [n]                    step into, n instructions
+n                        step over
+finish                   step out of the current subroutine
+x/<n> <addr>             dump n bytes of memory
+u [addr]                 disassemble 8 instructions, from the program counter by default
+regs                     show the registers
+p <expr>                 evaluate an expression
+search <b|w|sb|sw>       start a RAM search over bytes or little endian words, s for signed
+search <op> [value]      keep candidates that compare by ==, !=, <, >, <= or >= against value,
+                         their last value when it's left out, or their last value plus +n/-n
+search                   list the candidates
+mw <addr> [fmt] [label]  add a memory watch, mw alone shows them
+dmw <n>                  delete memory watch n
+trace <file>             log every instruction to a file
+trace ring <n> [file]    keep the last n instructions, written out on a breakpoint or jam
+trace dump / trace off   write out the ring now / stop tracing
+trace range <a> <b>      only trace code in a..=b, can be given more than once
+trace bank <n>           only trace code in PRG-ROM bank n, can be given more than once
+trace frame <n>          only trace from frame n on
+trace all                drop the trace filters
+profile on / off / reset start, stop or restart counting cycles per address and subroutine
+profile [total] [n]      the n busiest addresses of the last frame, or of the whole run
+profile tree [total]     the call tree of the last frame, or of the whole run
+profile json <file>      write every frame's profile as JSON
+profile folded <file>    write the whole run as collapsed stacks for flamegraph tools
+q                        quit";
+
+/// A gdb flavoured command line over a `Debugger`, an empty line repeats the last command
+#[derive(Default)]
+pub struct Console {
+    pub debugger: Debugger,
+    pub search: Option<RamSearch>,
+    pub watches: Vec<Watch>,
+    last: String,
+}
+
+impl Console {
+    /// instructions `n` and `finish` may run before giving up
+    pub const STEP_LIMIT: u64 = 10_000_000;
+    /// search candidates listed at most
+    const SEARCH_LIST_LIMIT: usize = 32;
+    /// addresses `profile` lists by default
+    const PROFILE_LIST_LIMIT: usize = 16;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn execute<T: Target>(&mut self, line: &str, target: &mut T) -> Reply {
+        let line = if line.trim().is_empty() {
+            self.last.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last = line.clone();
+        match self.command(&line, target) {
+            Ok(reply) => reply,
+            Err(message) => Reply::Output(format!("error: {}", message)),
+        }
+    }
+
+    fn command<T: Target>(&mut self, line: &str, target: &mut T) -> Result<Reply, String> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        let output = match command {
+            "" => String::new(),
+            "help" | "h" | "?" => HELP.to_string(),
+            "q" | "quit" => return Ok(Reply::Quit),
+            "c" | "continue" => return Ok(Reply::Resume),
+            "b" | "break" => {
+                let (address, condition) = match rest.split_once(" if ") {
+                    Some((address, condition)) => (address, Some(condition.trim())),
+                    None => (rest, None),
+                };
+                let address = self.address(target, address)?;
+                let index = self
+                    .debugger
+                    .add_breakpoint(target.cpu(), address, condition)?;
+                format!("breakpoint {} at ${:04X}", index, address)
+            }
+            "w" | "watch" => {
+                let arguments: Vec<&str> = rest.split_whitespace().collect();
+                let (kind, start, end) = match arguments[..] {
+                    [kind, start] => (kind, start, start),
+                    [kind, start, end] => (kind, start, end),
+                    _ => return Err("usage: w <r|w|x|rw> <addr> [end]".to_string()),
+                };
+                if kind.is_empty() || !kind.chars().all(|c| "rwx".contains(c)) {
+                    return Err(format!("bad watch kind `{}`", kind));
+                }
+                let watchpoint = Watchpoint {
+                    start: self.address(target, start)?,
+                    end: self.address(target, end)?,
+                    read: kind.contains('r'),
+                    write: kind.contains('w'),
+                    execute: kind.contains('x'),
+                };
+                let index = self.debugger.add_watchpoint(watchpoint);
+                format!(
+                    "watchpoint {} on ${:04X}-${:04X}",
+                    index, watchpoint.start, watchpoint.end
+                )
+            }
+            "d" | "delete" => {
+                let index = self.index(rest, self.debugger.breakpoints.len())?;
+                self.debugger.breakpoints.remove(index);
+                format!("deleted breakpoint {}", index)
+            }
+            "dw" => {
+                let index = self.index(rest, self.debugger.watchpoints.len())?;
+                self.debugger.watchpoints.remove(index);
+                format!("deleted watchpoint {}", index)
+            }
+            "l" | "list" => self.list(),
+            "s" | "step" => {
+                let count = match rest {
+                    "" => 1,
+                    count => self.debugger.evaluate(target.cpu(), count)?.max(1),
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    stop = self.debugger.step_into(target);
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+                self.stopped(stop, target)
+            }
+            "n" | "next" => {
+                let stop = self.debugger.step_over(target, Self::STEP_LIMIT);
+                self.stopped(stop, target)
+            }
+            "finish" => {
+                let stop = self.debugger.step_out(target, Self::STEP_LIMIT);
+                self.stopped(stop, target)
+            }
+            "regs" | "r" => self.registers(target),
+            "p" | "print" => {
+                let value = self.debugger.evaluate(target.cpu(), rest)?;
+                format!("{} ${:X}", value, value)
+            }
+            "u" => {
+                let mut address = match rest {
+                    "" => target.cpu().program_counter,
+                    address => self.address(target, address)?,
+                };
+                let mut lines = Vec::new();
+                for _ in 0..8 {
+                    let bus = &target.cpu().bus;
+                    if let Some(label) = self.debugger.symbols.label_on(bus, address) {
+                        lines.push(format!("{}:", label));
+                    }
+                    lines.push(self.describe(target, address));
+                    let (_, len) = disassembler::disassemble(bus, address, target.cpu().variant);
+                    address = address.wrapping_add(len);
+                }
+                lines.join("\n")
+            }
+            "search" | "sr" => self.search(target, rest)?,
+            "trace" => self.trace(target, rest)?,
+            "profile" => self.profile(rest)?,
+            "mw" if rest.is_empty() => {
+                let bus = &target.cpu().bus;
+                let lines: Vec<String> = self
+                    .watches
+                    .iter()
+                    .enumerate()
+                    .map(|(index, watch)| {
+                        format!("m{} {}", index, watch.show(bus, &self.debugger.symbols))
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    "no memory watches".to_string()
+                } else {
+                    lines.join("\n")
+                }
+            }
+            "mw" => {
+                let mut arguments = rest.splitn(3, ' ');
+                let address = self.address(target, arguments.next().unwrap())?;
+                let (format, label) = match arguments.next() {
+                    Some(format) => match Format::parse(format) {
+                        Some(format) => (format, arguments.next().unwrap_or("")),
+                        None => (Format::BYTE, rest.split_once(' ').unwrap().1),
+                    },
+                    None => (Format::BYTE, ""),
+                };
+                self.watches.push(Watch {
+                    address,
+                    format,
+                    label: label.trim().to_string(),
+                });
+                let watch = self.watches.last().unwrap();
+                format!(
+                    "m{} {}",
+                    self.watches.len() - 1,
+                    watch.show(&target.cpu().bus, &self.debugger.symbols)
+                )
+            }
+            "dmw" => {
+                let index = self.index(rest, self.watches.len())?;
+                self.watches.remove(index);
+                format!("deleted memory watch {}", index)
+            }
+            _ if command.starts_with("x/") || command == "x" => {
+                let count = match command.strip_prefix("x/") {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("bad count `{}`", count))?,
+                    None => 16,
+                };
+                let address = self.address(target, rest)?;
+                self.dump(target, address, count)
+            }
+            _ => return Err(format!("unknown command `{}`, try `help`", command)),
+        };
+        Ok(Reply::Output(output))
+    }
+
+    fn trace<T: Target>(&mut self, target: &T, rest: &str) -> Result<String, String> {
+        let arguments: Vec<&str> = rest.split_whitespace().collect();
+        let open = |path: &str| -> Result<Box<dyn std::io::Write>, String> {
+            let file = std::fs::File::create(path)
+                .map_err(|error| format!("couldn't create {}: {}", path, error))?;
+            Ok(Box::new(std::io::BufWriter::new(file)))
+        };
+        let filter = self
+            .debugger
+            .tracer
+            .as_ref()
+            .map(|tracer| tracer.filter.clone());
+        let tracer = match arguments[..] {
+            [] => return Err("usage: trace <file> | ring <n> [file] | dump | off".to_string()),
+            ["off"] => {
+                if let Some(tracer) = &mut self.debugger.tracer {
+                    let _ = tracer.dump();
+                }
+                self.debugger.tracer = None;
+                return Ok("tracing off".to_string());
+            }
+            ["dump"] => {
+                let tracer = self.debugger.tracer.as_mut().ok_or("not tracing")?;
+                tracer.dump().map_err(|error| error.to_string())?;
+                return Ok("trace written".to_string());
+            }
+            ["ring", count, ..] => {
+                let count = self.debugger.evaluate(target.cpu(), count)?.max(1) as usize;
+                let sink = match arguments.get(2) {
+                    Some(path) => open(path)?,
+                    None => Box::new(std::io::stdout()),
+                };
+                Tracer::ring(count, sink)
+            }
+            [kind @ ("range" | "bank" | "frame" | "all"), ..] => {
+                let values = arguments[1..]
+                    .iter()
+                    .map(|value| self.debugger.evaluate(target.cpu(), value))
+                    .collect::<Result<Vec<i64>, String>>()?;
+                let tracer = self.debugger.tracer.as_mut().ok_or("not tracing")?;
+                let filter = &mut tracer.filter;
+                match (kind, &values[..]) {
+                    ("range", &[start, end]) => filter.ranges.push((start as u16, end as u16)),
+                    ("bank", &[bank]) => filter.banks.push(bank as u16),
+                    ("frame", &[frame]) => filter.from_frame = frame as u64,
+                    ("all", []) => *filter = Default::default(),
+                    _ => return Err(format!("bad trace {} arguments", kind)),
+                }
+                return Ok(format!("{:?}", filter));
+            }
+            [path] => Tracer::new(open(path)?),
+            _ => return Err(format!("bad trace command `{}`", rest)),
+        };
+        let mut tracer = tracer;
+        tracer.filter = filter.unwrap_or_default();
+        self.debugger.tracer = Some(tracer);
+        Ok("tracing".to_string())
+    }
+
+    fn profile(&mut self, rest: &str) -> Result<String, String> {
+        let arguments: Vec<&str> = rest.split_whitespace().collect();
+        match arguments[..] {
+            ["on"] => {
+                self.debugger.profiler.get_or_insert_with(Profiler::new);
+                return Ok("profiling".to_string());
+            }
+            ["reset"] => {
+                self.debugger.profiler = Some(Profiler::new());
+                return Ok("profiling from scratch".to_string());
+            }
+            ["off"] => {
+                self.debugger.profiler = None;
+                return Ok("profiling off".to_string());
+            }
+            _ => {}
+        }
+        let profiler = self.debugger.profiler.as_ref().ok_or("not profiling")?;
+        let symbols = &self.debugger.symbols;
+        let write = |path: &str, text: String| {
+            std::fs::write(path, text)
+                .map(|_| format!("wrote {}", path))
+                .map_err(|error| format!("couldn't write {}: {}", path, error))
+        };
+        // `total` anywhere picks the whole run over the last frame
+        let total = arguments.contains(&"total");
+        let arguments: Vec<&str> = arguments.into_iter().filter(|&a| a != "total").collect();
+        let profile = if total {
+            &profiler.total
+        } else {
+            profiler.last_frame()
+        };
+        match arguments[..] {
+            [] => Ok(profile.flat_report(symbols, Self::PROFILE_LIST_LIMIT)),
+            ["tree"] => Ok(profile.tree_report(symbols)),
+            ["json", path] => write(path, profiler.to_json(symbols).to_string()),
+            ["folded", path] => write(path, profiler.total.collapsed(symbols)),
+            [limit] => {
+                let limit = limit
+                    .parse()
+                    .map_err(|_| format!("bad profile command `{}`", rest))?;
+                Ok(profile.flat_report(symbols, limit))
+            }
+            _ => Err(format!("bad profile command `{}`", rest)),
+        }
+    }
+
+    fn search<T: Target>(&mut self, target: &T, rest: &str) -> Result<String, String> {
+        let bus = &target.cpu().bus;
+        if let Some(format) = Format::parse(rest) {
+            let search = RamSearch::new(bus, format);
+            let output = format!("{} candidates", search.len());
+            self.search = Some(search);
+            return Ok(output);
+        }
+        let Some(search) = &mut self.search else {
+            return Err("no search, start one with `search b`".to_string());
+        };
+        if rest.is_empty() {
+            let mut lines: Vec<String> = search
+                .results()
+                .iter()
+                .take(Self::SEARCH_LIST_LIMIT)
+                .map(|(address, value)| format!("${:04X} = {}", address, value))
+                .collect();
+            if search.len() > Self::SEARCH_LIST_LIMIT {
+                lines.push(format!(
+                    "... {} more",
+                    search.len() - Self::SEARCH_LIST_LIMIT
+                ));
+            }
+            return Ok(lines.join("\n"));
+        }
+        let (operator, value) = rest.split_once(' ').unwrap_or((rest, ""));
+        let ordering: &[Ordering] = match operator {
+            "==" => &[Ordering::Equal],
+            "!=" => &[Ordering::Less, Ordering::Greater],
+            "<" => &[Ordering::Less],
+            ">" => &[Ordering::Greater],
+            "<=" => &[Ordering::Less, Ordering::Equal],
+            ">=" => &[Ordering::Greater, Ordering::Equal],
+            _ => return Err(format!("bad comparison `{}`", operator)),
+        };
+        let value = value.trim();
+        let operand = if value.is_empty() {
+            Operand::Previous
+        } else if let Some(delta) = value.strip_prefix('+') {
+            Operand::Delta(self.debugger.evaluate(target.cpu(), delta)?)
+        } else if let Some(delta) = value.strip_prefix('-') {
+            Operand::Delta(-self.debugger.evaluate(target.cpu(), delta)?)
+        } else {
+            Operand::Value(self.debugger.evaluate(target.cpu(), value)?)
+        };
+        search.filter(bus, ordering, operand);
+        Ok(format!("{} candidates", search.len()))
+    }
+
+    /// what to print when the machine stops, either from a command or while running freely
+    pub fn stopped<T: Target>(&self, stop: Stop, target: &T) -> String {
+        let next = self.describe(target, target.cpu().program_counter);
+        match stop {
+            Stop::Stepped => next,
+            stop => format!("{}\n{}", stop, next),
+        }
+    }
+
+    /// the instruction at `address` with symbols, and the source line it came from
+    fn describe<T: Target>(&self, target: &T, address: u16) -> String {
+        let (bus, symbols) = (&target.cpu().bus, &self.debugger.symbols);
+        let (instruction, _) = disassembler::disassemble(bus, address, target.cpu().variant);
+        let line = format!(
+            "{:04X}  {}",
+            address,
+            instruction.to_string_on(bus, symbols)
+        );
+        match symbols.source_on(bus, address) {
+            Some((file, number)) => format!("{:<24}; {}:{}", line, file, number),
+            None => line,
+        }
+    }
+
+    fn registers<T: Target>(&self, target: &T) -> String {
+        let cpu = target.cpu();
+        let flags = cpu.flags;
+        let names: String = [
+            ('N', flags.negative),
+            ('V', flags.overflow),
+            ('D', flags.decimal_mode),
+            ('I', flags.interrupt_disable),
+            ('Z', flags.zero),
+            ('C', flags.carry),
+        ]
+        .iter()
+        .map(|&(name, set)| if set { name } else { '-' })
+        .collect();
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} [{}] SP:{:02X} CYC:{}",
+            cpu.program_counter,
+            cpu.accumulator,
+            cpu.idx,
+            cpu.idy,
+            flags.into_u8(),
+            names,
+            cpu.stack_pointer,
+            cpu.cycles
+        )
+    }
+
+    fn dump<T: Target>(&self, target: &T, address: u16, count: usize) -> String {
+        let cpu = target.cpu();
+        let bytes: Vec<u8> = (0..count)
+            .map(|offset| cpu.peek_memory(address.wrapping_add(offset as u16)))
+            .collect();
+        bytes
+            .chunks(16)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                format!(
+                    "{:04X}: {}",
+                    address.wrapping_add(row as u16 * 16),
+                    hex.join(" ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn list(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, breakpoint) in self.debugger.breakpoints.iter().enumerate() {
+            let mut line = format!("b{} ${:04X}", index, breakpoint.address);
+            if let Some(condition) = &breakpoint.condition {
+                line += &format!(" if {}", condition);
+            }
+            lines.push(line);
+        }
+        for (index, watchpoint) in self.debugger.watchpoints.iter().enumerate() {
+            let kind: String = [
+                ('r', watchpoint.read),
+                ('w', watchpoint.write),
+                ('x', watchpoint.execute),
+            ]
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| name)
+            .collect();
+            lines.push(format!(
+                "w{} {} ${:04X}-${:04X}",
+                index, kind, watchpoint.start, watchpoint.end
+            ));
+        }
+        if lines.is_empty() {
+            "no breakpoints or watchpoints".to_string()
+        } else {
+            lines.join("\n")
+        }
+    }
+
+    fn address<T: Target>(&self, target: &T, text: &str) -> Result<u16, String> {
+        if text.is_empty() {
+            return Err("expected an address".to_string());
+        }
+        let value = self.debugger.evaluate(target.cpu(), text)?;
+        u16::try_from(value).map_err(|_| format!("${:X} is not an address", value))
+    }
+
+    fn index(&self, text: &str, len: usize) -> Result<usize, String> {
+        match text.parse::<usize>() {
+            Ok(index) if index < len => Ok(index),
+            _ => Err(format!("no such index `{}`", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::cpu::Cpu;
+
+    #[test]
+    fn commands() {
+        let mut cpu = Cpu::with_program(asm!(
+            "
+            LDA #$AA
+            LDX #$10
+    loop:   STA $0200,X
+            DEX
+            BNE loop
+            BRK
+            "
+        ));
+        let mut console = Console::new();
+        let mut run = |line: &str| console.execute(line, &mut cpu);
+
+        assert_eq!(
+            run("b $0604 if x == 3"),
+            Reply::Output("breakpoint 0 at $0604".to_string())
+        );
+        assert_eq!(run("c"), Reply::Resume);
+        assert_eq!(run("s"), Reply::Output("0602  LDX #$10".to_string()));
+        assert_eq!(run(""), Reply::Output("0604  STA $0200,X".to_string()));
+        assert_eq!(run(""), Reply::Output("0607  DEX".to_string()));
+        assert_eq!(
+            run("x/4 $0210"),
+            Reply::Output("0210: AA 00 00 00".to_string())
+        );
+        assert!(matches!(run("frobnicate"), Reply::Output(text) if text.starts_with("error")));
+        assert_eq!(run("q"), Reply::Quit);
+    }
+
+    #[test]
+    fn ram_search() {
+        let mut cpu = Cpu::with_program(asm!("loop: INC $0300\nDEC $6010\nJMP loop"));
+        let mut console = Console::new();
+        let mut run = |line: &str| match console.execute(line, &mut cpu) {
+            Reply::Output(text) => text,
+            reply => panic!("{:?}", reply),
+        };
+        assert!(run("search ==").starts_with("error"));
+        assert_eq!(run("search b"), "10240 candidates");
+        run("s 3");
+        assert_eq!(run("search == +1"), "1 candidates");
+        assert_eq!(run("search"), "$0300 = 1");
+        assert_eq!(run("search sb"), "10240 candidates");
+        run("s 3");
+        assert_eq!(run("search <"), "1 candidates");
+        assert_eq!(run("search"), "$6010 = -2");
+
+        assert_eq!(run("mw $0300 lives"), "m0 lives $0300 = 2 $02");
+        assert_eq!(run("mw $6010 sb"), "m1 $6010 = -2 $FE");
+        assert_eq!(run("mw"), "m0 lives $0300 = 2 $02\nm1 $6010 = -2 $FE");
+        assert_eq!(run("dmw 0"), "deleted memory watch 0");
+    }
+
+    #[test]
+    fn resumes_to_breakpoint() {
+        let mut cpu = Cpu::with_program(asm!("LDX #$10\nloop: DEX\nBNE loop\nBRK"));
+        let mut console = Console::new();
+        let unknown = console.execute("b loop", &mut cpu);
+        assert!(matches!(unknown, Reply::Output(text) if text.starts_with("error")));
+        console.debugger.symbols.insert(0x0602, "loop");
+        console.execute("b loop if X == 3", &mut cpu);
+        let stop = console.debugger.resume(&mut cpu, 1000);
+        assert_eq!(stop, Stop::Breakpoint(0));
+        assert_eq!(
+            console.execute("regs", &mut cpu),
+            Reply::Output("PC:0602 A:00 X:03 Y:00 P:20 [------] SP:FF CYC:67".to_string())
+        );
+    }
+
+    #[test]
+    fn disassembles_with_sources() {
+        let mut cpu = Cpu::with_program(asm!("LDX #$10\nloop: DEX\nBNE loop\nBRK"));
+        let mut console = Console::new();
+        let symbols = &mut console.debugger.symbols;
+        symbols.insert(0x0602, "loop");
+        let file = symbols.add_file("main.s");
+        symbols.add_line(0x0602, None, file, 3);
+        let Reply::Output(listing) = console.execute("u", &mut cpu) else {
+            panic!("u resumed");
+        };
+        assert!(listing.starts_with(
+            "0600  LDX #$10\nloop:\n0602  DEX               ; main.s:3\n0603  BNE loop\n"
+        ));
+    }
+}
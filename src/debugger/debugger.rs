@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assembler::expression::Evaluator;
+use crate::cpu::cpu::Cpu;
+use crate::debugger::profiler::{CallStack, Profiler};
+use crate::debugger::tracer::Tracer;
+use crate::machine::bus::MachineBus;
+use crate::machine::machine::Machine;
+use crate::memory::bus::Bus;
+use crate::memory::recording::{AccessKind, BusAccess};
+use crate::nes::bus::NesBus;
+use crate::nes::nes::Nes;
+use crate::symbols::symbols::SymbolTable;
+
+/// Anything the debugger can drive one instruction at a time
+pub trait Target {
+    type Bus: Bus;
+    fn cpu(&self) -> &Cpu<Self::Bus>;
+    fn cpu_mut(&mut self) -> &mut Cpu<Self::Bus>;
+    /// runs one instruction, along with whatever else the machine does meanwhile
+    fn step(&mut self);
+    /// the frame, scanline and dot the PPU is at, on targets that have one
+    fn beam(&self) -> Option<(u64, u16, u16)> {
+        None
+    }
+    /// the PRG-ROM bank mapped at an address, on targets with banked cartridges
+    fn prg_bank(&self, _address: u16) -> Option<u16> {
+        None
+    }
+}
+
+impl<B: Bus> Target for Cpu<B> {
+    type Bus = B;
+    fn cpu(&self) -> &Cpu<B> {
+        self
+    }
+    fn cpu_mut(&mut self) -> &mut Cpu<B> {
+        self
+    }
+    fn step(&mut self) {
+        Cpu::step(self);
+    }
+}
+
+impl Target for Nes {
+    type Bus = NesBus;
+    fn cpu(&self) -> &Cpu<NesBus> {
+        &self.cpu
+    }
+    fn cpu_mut(&mut self) -> &mut Cpu<NesBus> {
+        &mut self.cpu
+    }
+    fn step(&mut self) {
+        Nes::step(self);
+    }
+    fn beam(&self) -> Option<(u64, u16, u16)> {
+        let ppu = &self.cpu.bus.ppu;
+        Some((ppu.frame, ppu.scanline, ppu.dot))
+    }
+    fn prg_bank(&self, address: u16) -> Option<u16> {
+        self.cpu.bus.mapper.prg_bank(address)
+    }
+}
+
+impl Target for Machine {
+    type Bus = MachineBus;
+    fn cpu(&self) -> &Cpu<MachineBus> {
+        &self.cpu
+    }
+    fn cpu_mut(&mut self) -> &mut Cpu<MachineBus> {
+        &mut self.cpu
+    }
+    fn step(&mut self) {
+        Machine::step(self);
+    }
+}
+
+/// Stops before the instruction at `address` runs, if its condition holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    /// an expression over the registers and symbols, e.g. `A == $40 && X > 3`
+    pub condition: Option<String>,
+    pub enabled: bool,
+}
+
+/// Stops after an instruction touches an address in `start..=end` the watched way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    /// stops before an instruction in the range runs, like a breakpoint over the whole range
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+    fn matches(&self, access: &BusAccess) -> bool {
+        let watched = match access.kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+        watched && self.contains(access.address)
+    }
+}
+
+/// Why execution stopped and handed control back to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// the step asked for finished
+    Stepped,
+    Breakpoint(usize),
+    /// a read or write watchpoint caught `access`
+    Watchpoint {
+        index: usize,
+        access: BusAccess,
+    },
+    /// an execute watchpoint caught the instruction at `address`
+    Executed {
+        index: usize,
+        address: u16,
+    },
+    Jammed,
+    /// the instruction budget ran out first
+    Limit,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stepped => write!(f, "stepped"),
+            Self::Breakpoint(index) => write!(f, "breakpoint {}", index),
+            Self::Watchpoint { index, access } => {
+                let kind = match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                write!(
+                    f,
+                    "watchpoint {}: {} ${:02X} at ${:04X}",
+                    index, kind, access.value, access.address
+                )
+            }
+            Self::Executed { index, address } => {
+                write!(f, "watchpoint {}: execute at ${:04X}", index, address)
+            }
+            Self::Jammed => write!(f, "CPU jammed"),
+            Self::Limit => write!(f, "instruction limit reached"),
+        }
+    }
+}
+
+/// Breakpoints, watchpoints and stepping on top of a `Target`.
+/// Stepping over and out follows the `CallStack` of the instructions run under it, interrupts
+/// included
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// labels usable in conditions and shown in disassembly
+    pub symbols: SymbolTable,
+    /// logs instructions as they're stepped, its ring buffer is dumped on breakpoints and jams
+    pub tracer: Option<Tracer>,
+    /// counts the cycles spent in each subroutine while it's set
+    pub profiler: Option<Profiler>,
+    calls: CallStack,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// adds a breakpoint and returns its index, the condition is checked up front
+    pub fn add_breakpoint<B: Bus>(
+        &mut self,
+        cpu: &Cpu<B>,
+        address: u16,
+        condition: Option<&str>,
+    ) -> Result<usize, String> {
+        if let Some(condition) = condition {
+            self.evaluate(cpu, condition)?;
+        }
+        self.breakpoints.push(Breakpoint {
+            address,
+            condition: condition.map(str::to_string),
+            enabled: true,
+        });
+        Ok(self.breakpoints.len() - 1)
+    }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    /// Evaluates an expression against the CPU: `A X Y P SP PC`, the flags `C Z I D V N`,
+    /// the cycle counter `CYC` and any label from the symbol table
+    pub fn evaluate<B: Bus>(&self, cpu: &Cpu<B>, text: &str) -> Result<i64, String> {
+        let mut values: HashMap<String, i64> = self
+            .symbols
+            .iter()
+            .map(|(name, address)| (name.to_string(), address as i64))
+            .collect();
+        let flags = cpu.flags;
+        let registers = [
+            ("A", cpu.accumulator as i64),
+            ("X", cpu.idx as i64),
+            ("Y", cpu.idy as i64),
+            ("P", flags.into_u8() as i64),
+            ("SP", cpu.stack_pointer as i64),
+            ("PC", cpu.program_counter as i64),
+            ("CYC", cpu.cycles as i64),
+            ("C", flags.carry as i64),
+            ("Z", flags.zero as i64),
+            ("I", flags.interrupt_disable as i64),
+            ("D", flags.decimal_mode as i64),
+            ("V", flags.overflow as i64),
+            ("N", flags.negative as i64),
+        ];
+        for (name, value) in registers {
+            values.insert(name.to_string(), value);
+            values.insert(name.to_lowercase(), value);
+        }
+        let evaluator = Evaluator {
+            symbols: &values,
+            scope: "",
+            program_counter: cpu.program_counter as i64,
+        };
+        evaluator
+            .evaluate(text)?
+            .ok_or_else(|| format!("unknown symbol in `{}`", text.trim()))
+    }
+
+    /// Runs one instruction, then reports what it tripped: a read or write watchpoint,
+    /// or a breakpoint or execute watchpoint on the instruction that comes next
+    pub fn step<T: Target>(&mut self, target: &mut T) -> Option<Stop> {
+        let stop = self.step_traced(target);
+        if let (Some(tracer), Some(Stop::Breakpoint(_) | Stop::Jammed)) = (&mut self.tracer, &stop)
+        {
+            // a trace that can't be written is no reason to stop debugging
+            let _ = tracer.dump();
+        }
+        stop
+    }
+    fn step_traced<T: Target>(&mut self, target: &mut T) -> Option<Stop> {
+        if target.cpu().jammed {
+            return Some(Stop::Jammed);
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(target, &self.symbols);
+        }
+        let cpu = target.cpu_mut();
+        let watching = self.watchpoints.iter().any(|w| w.read || w.write);
+        cpu.record_accesses(watching);
+        self.calls.before(cpu);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.before(target);
+        }
+        target.step();
+        self.calls.after(target.cpu());
+        if let Some(profiler) = &mut self.profiler {
+            profiler.after(target);
+        }
+        let cpu = target.cpu_mut();
+        let accesses = cpu.take_accesses();
+        cpu.record_accesses(false);
+        for access in accesses {
+            if let Some(index) = self.watchpoints.iter().position(|w| w.matches(&access)) {
+                return Some(Stop::Watchpoint { index, access });
+            }
+        }
+        self.check_next(target.cpu())
+    }
+    /// the breakpoint or execute watchpoint at the program counter, if one applies
+    fn check_next<B: Bus>(&self, cpu: &Cpu<B>) -> Option<Stop> {
+        if cpu.jammed {
+            return Some(Stop::Jammed);
+        }
+        let address = cpu.program_counter;
+        if let Some(index) = self
+            .watchpoints
+            .iter()
+            .position(|w| w.execute && w.contains(address))
+        {
+            return Some(Stop::Executed { index, address });
+        }
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| {
+                breakpoint.enabled
+                    && breakpoint.address == address
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|condition| self.evaluate(cpu, condition).unwrap_or(0) != 0)
+            })
+            .map(Stop::Breakpoint)
+    }
+
+    /// runs a single instruction
+    pub fn step_into<T: Target>(&mut self, target: &mut T) -> Stop {
+        self.step(target).unwrap_or(Stop::Stepped)
+    }
+    /// runs a single instruction, or a whole subroutine or interrupt handler when it enters one
+    pub fn step_over<T: Target>(&mut self, target: &mut T, limit: u64) -> Stop {
+        self.run_while(target, limit, |depth, start| depth > start)
+    }
+    /// runs until the current subroutine or interrupt handler has returned
+    pub fn step_out<T: Target>(&mut self, target: &mut T, limit: u64) -> Stop {
+        self.run_while(target, limit, |depth, start| depth >= start)
+    }
+    /// runs until something stops it, or `limit` instructions have gone by
+    pub fn resume<T: Target>(&mut self, target: &mut T, limit: u64) -> Stop {
+        self.run_while(target, limit, |_, _| true)
+    }
+    fn run_while<T: Target>(
+        &mut self,
+        target: &mut T,
+        limit: u64,
+        running: impl Fn(usize, usize) -> bool,
+    ) -> Stop {
+        let start = self.calls.len();
+        for _ in 0..limit {
+            if let Some(stop) = self.step(target) {
+                return stop;
+            }
+            if !running(self.calls.len(), start) {
+                return Stop::Stepped;
+            }
+        }
+        Stop::Limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::cpu::CpuVariant;
+    use crate::harness::klaus::klaus_cpu;
+
+    fn program() -> Cpu {
+        Cpu::with_program(asm!(
+            "
+            LDX #0
+    loop:   INX
+            STX $0200
+            JSR double
+            CPX #5
+            BNE loop
+            BRK
+    double: TXA
+            ASL A
+            STA $0201
+            RTS
+            "
+        ))
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut cpu = program();
+        let mut debugger = Debugger::new();
+        debugger.symbols.insert(0x0602, "loop");
+        let index = debugger
+            .add_breakpoint(&cpu, 0x0609, Some("A == $06 && X > 2"))
+            .unwrap();
+        assert_eq!(debugger.resume(&mut cpu, 1000), Stop::Breakpoint(index));
+        assert_eq!((cpu.accumulator, cpu.idx), (0x06, 3));
+        assert_eq!(debugger.evaluate(&cpu, "loop + x"), Ok(0x0605));
+        assert!(debugger.add_breakpoint(&cpu, 0, Some("Q > 1")).is_err());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut cpu = program();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint {
+            start: 0x0201,
+            end: 0x0201,
+            read: false,
+            write: true,
+            execute: false,
+        });
+        let stop = debugger.resume(&mut cpu, 1000);
+        assert_eq!(
+            stop,
+            Stop::Watchpoint {
+                index: 0,
+                access: BusAccess {
+                    address: 0x0201,
+                    value: 2,
+                    kind: AccessKind::Write,
+                },
+            }
+        );
+        debugger.watchpoints[0] = Watchpoint {
+            start: 0x060F,
+            end: 0x0614,
+            read: false,
+            write: false,
+            execute: true,
+        };
+        assert_eq!(
+            debugger.resume(&mut cpu, 1000),
+            Stop::Executed {
+                index: 0,
+                address: 0x060F
+            }
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let mut cpu = program();
+        let mut debugger = Debugger::new();
+        for _ in 0..3 {
+            assert_eq!(debugger.step_into(&mut cpu), Stop::Stepped);
+        }
+        assert_eq!(cpu.program_counter, 0x0606);
+        assert_eq!(debugger.step_over(&mut cpu, 100), Stop::Stepped);
+        assert_eq!((cpu.program_counter, cpu.bus[0x0201]), (0x0609, 2));
+
+        debugger.step_over(&mut cpu, 100);
+        debugger.step_over(&mut cpu, 100);
+        debugger.step_over(&mut cpu, 100);
+        debugger.step_over(&mut cpu, 100);
+        assert_eq!(debugger.step_into(&mut cpu), Stop::Stepped);
+        assert_eq!(cpu.program_counter, 0x060E);
+        debugger.step_into(&mut cpu);
+        assert_eq!(debugger.step_out(&mut cpu, 100), Stop::Stepped);
+        assert_eq!((cpu.program_counter, cpu.bus[0x0201]), (0x0609, 4));
+    }
+
+    #[test]
+    fn stepping_through_interrupts() {
+        let mut image = vec![0; 0x10000];
+        asm!(
+            "
+            .org $0400
+            LDA #$02
+            STA $BFFC       ; NMI, taken after the next instruction
+            NOP
+            LDX #1
+            LDA #0
+            STA $BFFC
+            LDA #$02
+            STA $BFFC
+            NOP
+    done:   JMP done
+    nmi:    INC $10
+            JSR sub
+            RTI
+    sub:    INC $11
+            RTS
+            .org $FFFA
+            .word nmi, $0400, nmi
+            "
+        )
+        .patch(&mut image, 0)
+        .unwrap();
+        let mut cpu = klaus_cpu(&image, CpuVariant::Nmos6502);
+        let mut debugger = Debugger::new();
+        debugger.step_into(&mut cpu);
+        debugger.step_into(&mut cpu);
+        // the NOP lets the NMI in, stepping over it runs the whole handler
+        assert_eq!(debugger.step_over(&mut cpu, 100), Stop::Stepped);
+        assert_eq!(cpu.program_counter, 0x0406);
+        assert_eq!((cpu.bus.memory[0x10], cpu.bus.memory[0x11]), (1, 1));
+
+        for _ in 0..6 {
+            debugger.step_into(&mut cpu);
+        }
+        assert_eq!(cpu.program_counter, 0x0416);
+        debugger.step_into(&mut cpu);
+        assert_eq!(debugger.step_out(&mut cpu, 100), Stop::Stepped);
+        assert_eq!(cpu.program_counter, 0x0413);
+        assert_eq!((cpu.bus.memory[0x10], cpu.bus.memory[0x11]), (2, 2));
+    }
+}
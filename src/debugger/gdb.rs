@@ -0,0 +1,476 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::cpu::flags::Flags;
+use crate::debugger::debugger::{Debugger, Stop, Target, Watchpoint};
+use crate::memory::bus::Bus;
+
+/// what `qXfer:features:read` hands out, so GDB knows the register layout of `g` packets
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>"#;
+
+/// how many instructions `c` runs between checks for a ^C from the client
+const INTERRUPT_CHECK: u64 = 10_000;
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+
+/// A GDB remote serial protocol server for one client at a time.
+/// Registers are numbered A, X, Y, P, SP, PC, the first five a byte each and PC two bytes
+/// little endian, which is also their order in `g` packets
+pub struct GdbServer<T: Target> {
+    pub target: T,
+    pub debugger: Debugger,
+    no_ack: bool,
+}
+
+impl<T: Target> GdbServer<T> {
+    pub fn new(target: T) -> Self {
+        Self {
+            target,
+            debugger: Debugger::new(),
+            no_ack: false,
+        }
+    }
+
+    /// waits for a client on `address` and serves it until it detaches or disconnects
+    pub fn listen(&mut self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.no_ack = false;
+        // packets are tiny and strictly request/response, batching them only adds latency
+        stream.set_nodelay(true)?;
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            match self.handle(&packet, &mut stream)? {
+                Some(reply) => self.write_packet(&mut stream, &reply)?,
+                None => break,
+            }
+            // the OK itself still gets acknowledged
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// the next packet's payload, acknowledging it. `None` once the client hangs up
+    fn read_packet(&mut self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                // acks, and ^C while already stopped
+                continue;
+            }
+            let mut payload = Vec::new();
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                payload.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            let valid = expected == Some(Self::checksum(&payload));
+            if !self.no_ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+    fn write_packet(&self, stream: &mut TcpStream, payload: &str) -> io::Result<()> {
+        let checksum = Self::checksum(payload.as_bytes());
+        write!(stream, "${}#{:02x}", payload, checksum)?;
+        stream.flush()
+    }
+    fn checksum(payload: &[u8]) -> u8 {
+        payload
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    /// answers one packet, `None` ends the session
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => SIGTRAP.to_string(),
+            "g" => self.registers(),
+            "G" => self.write_registers(arguments),
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) => self.register(register).unwrap_or_else(|| "E00".to_string()),
+                Err(_) => "E00".to_string(),
+            },
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "s" => {
+                let stop = self.debugger.step_into(&mut self.target);
+                self.stop_reply(stop)
+            }
+            "c" => self.resume(stream)?,
+            "Z" | "z" => self.breakpoint(command == "Z", arguments),
+            "H" | "T" => "OK".to_string(),
+            "q" => self.query(arguments),
+            "Q" if arguments == "StartNoAckMode" => "OK".to_string(),
+            "D" => {
+                self.write_packet(stream, "OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+".to_string()
+        } else if let Some(request) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = request.split_once(',').unwrap_or(("0", "0"));
+            let offset = usize::from_str_radix(offset, 16).unwrap_or(0);
+            let length = usize::from_str_radix(length, 16).unwrap_or(0);
+            let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+            if rest.len() > length {
+                format!("m{}", &rest[..length])
+            } else {
+                format!("l{}", rest)
+            }
+        } else {
+            match query {
+                "Attached" => "1".to_string(),
+                "C" => "QC1".to_string(),
+                "fThreadInfo" => "m1".to_string(),
+                "sThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    /// runs until something stops the CPU or the client sends ^C
+    fn resume(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        loop {
+            match self.debugger.resume(&mut self.target, INTERRUPT_CHECK) {
+                Stop::Limit => {
+                    if Self::interrupted(stream)? {
+                        return Ok(SIGINT.to_string());
+                    }
+                }
+                stop => return Ok(self.stop_reply(stop)),
+            }
+        }
+    }
+    fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+        stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let peeked = stream.peek(&mut byte);
+        stream.set_nonblocking(false)?;
+        match peeked {
+            Ok(1) if byte[0] == 0x03 => {
+                stream.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Jammed => SIGILL.to_string(),
+            Stop::Watchpoint { index, access } => {
+                let watchpoint = self.debugger.watchpoints[index];
+                let kind = match (watchpoint.read, watchpoint.write) {
+                    (true, true) => "awatch",
+                    (true, false) => "rwatch",
+                    _ => "watch",
+                };
+                format!("T05{}:{:04x};", kind, access.address)
+            }
+            _ => SIGTRAP.to_string(),
+        }
+    }
+
+    /// `Z`/`z` type,address,kind: 0 and 1 are breakpoints, 2 to 4 write, read and access watchpoints
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let fields: Vec<&str> = arguments.split(',').collect();
+        let (kind, address, length) = match fields[..] {
+            [kind, address, length] => (kind, address, length),
+            _ => return "E00".to_string(),
+        };
+        let (Ok(address), Ok(length)) = (
+            u16::from_str_radix(address, 16),
+            u16::from_str_radix(length, 16),
+        ) else {
+            return "E00".to_string();
+        };
+        match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.breakpoints.retain(|b| b.address != address);
+                    let cpu = self.target.cpu();
+                    if self.debugger.add_breakpoint(cpu, address, None).is_err() {
+                        return "E00".to_string();
+                    }
+                } else {
+                    self.debugger.breakpoints.retain(|b| b.address != address);
+                }
+            }
+            "2" | "3" | "4" => {
+                let watchpoint = Watchpoint {
+                    start: address,
+                    end: address.wrapping_add(length.max(1) - 1),
+                    read: kind != "2",
+                    write: kind != "3",
+                    execute: false,
+                };
+                if insert {
+                    self.debugger.add_watchpoint(watchpoint);
+                } else {
+                    self.debugger.watchpoints.retain(|w| *w != watchpoint);
+                }
+            }
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    fn register_bytes(&self) -> [u8; 7] {
+        let cpu = self.target.cpu();
+        let [lo, hi] = cpu.program_counter.to_le_bytes();
+        [
+            cpu.accumulator,
+            cpu.idx,
+            cpu.idy,
+            cpu.flags.into_u8(),
+            cpu.stack_pointer,
+            lo,
+            hi,
+        ]
+    }
+    fn registers(&self) -> String {
+        hex(&self.register_bytes())
+    }
+    fn register(&self, register: usize) -> Option<String> {
+        let bytes = self.register_bytes();
+        match register {
+            0..=4 => Some(hex(&bytes[register..=register])),
+            5 => Some(hex(&bytes[5..])),
+            _ => None,
+        }
+    }
+    fn write_registers(&mut self, arguments: &str) -> String {
+        match unhex(arguments) {
+            Some(bytes) if bytes.len() == 7 => {
+                for register in 0..5 {
+                    self.set_register(register, &bytes[register..=register]);
+                }
+                self.set_register(5, &bytes[5..]);
+                "OK".to_string()
+            }
+            _ => "E00".to_string(),
+        }
+    }
+    fn write_register(&mut self, arguments: &str) -> String {
+        let Some((register, value)) = arguments.split_once('=') else {
+            return "E00".to_string();
+        };
+        match (usize::from_str_radix(register, 16), unhex(value)) {
+            (Ok(register @ 0..=5), Some(bytes)) if !bytes.is_empty() => {
+                self.set_register(register, &bytes);
+                "OK".to_string()
+            }
+            _ => "E00".to_string(),
+        }
+    }
+    fn set_register(&mut self, register: usize, bytes: &[u8]) {
+        let cpu = self.target.cpu_mut();
+        let value = bytes[0];
+        match register {
+            0 => cpu.accumulator = value,
+            1 => cpu.idx = value,
+            2 => cpu.idy = value,
+            3 => cpu.flags = Flags::from_u8(value),
+            4 => cpu.stack_pointer = value,
+            _ => {
+                let hi = bytes.get(1).copied().unwrap_or(0);
+                cpu.program_counter = u16::from_le_bytes([value, hi]);
+            }
+        }
+    }
+
+    /// `m address,length`, peeked so reading a PPU register doesn't disturb it
+    fn read_memory(&self, arguments: &str) -> String {
+        let Some((address, length)) = parse_range(arguments) else {
+            return "E00".to_string();
+        };
+        let bus = &self.target.cpu().bus;
+        let bytes: Vec<u8> = (0..length)
+            .map(|offset| bus.peek(address.wrapping_add(offset)))
+            .collect();
+        hex(&bytes)
+    }
+    /// `M address,length:bytes`
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let Some((range, data)) = arguments.split_once(':') else {
+            return "E00".to_string();
+        };
+        match (parse_range(range), unhex(data)) {
+            (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                let cpu = self.target.cpu_mut();
+                for (offset, byte) in bytes.into_iter().enumerate() {
+                    cpu.bus.write(address.wrapping_add(offset as u16), byte);
+                }
+                "OK".to_string()
+            }
+            _ => "E00".to_string(),
+        }
+    }
+}
+
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::cpu::Cpu;
+
+    /// a scripted client: sends each packet and returns the replies it got
+    fn client(stream: &mut TcpStream, packets: &[&str]) -> Vec<String> {
+        let mut replies = Vec::new();
+        for packet in packets {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            write!(stream, "${}#{:02x}", packet, checksum).unwrap();
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'+', "{} wasn't acknowledged", packet);
+            if *packet == "k" {
+                break;
+            }
+            let mut reply = Vec::new();
+            stream.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'$');
+            loop {
+                stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum).unwrap();
+            stream.write_all(b"+").unwrap();
+            replies.push(String::from_utf8(reply).unwrap());
+        }
+        replies
+    }
+
+    #[test]
+    fn scripted_session() {
+        let cpu = Cpu::with_program(asm!(
+            "
+            LDX #3
+    loop:   DEX
+            STX $0200
+            BNE loop
+            BRK
+            "
+        ));
+        let mut server = GdbServer::new(cpu);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let script = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            client(
+                &mut stream,
+                &[
+                    "qSupported:multiprocess+",
+                    "?",
+                    "Z0,0602,1",
+                    "c",
+                    "p5",
+                    "g",
+                    "P0=42",
+                    "s",
+                    "z0,0602,1",
+                    "Z2,0200,1",
+                    "c",
+                    "m0200,2",
+                    "M0300,2:abcd",
+                    "m0300,2",
+                    "vMustReplyEmpty",
+                    "D",
+                ],
+            )
+        });
+        let (stream, _) = listener.accept().unwrap();
+        server.serve(stream).unwrap();
+        let replies = script.join().unwrap();
+        let expected = [
+            "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+",
+            "S05",
+            "OK",
+            "S05",
+            "0206",
+            "00030020ff0206",
+            "OK",
+            "S05",
+            "OK",
+            "OK",
+            "T05watch:0200;",
+            "0200",
+            "OK",
+            "abcd",
+            "",
+            "OK",
+        ];
+        assert_eq!(replies, expected);
+        assert_eq!(server.target.accumulator, 0x42);
+        assert_eq!(server.target.idx, 2);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod console;
+#[allow(clippy::module_inception)]
+pub mod debugger;
+pub mod gdb;
+pub mod profiler;
+pub mod search;
+pub mod tracer;
@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use crate::cpu::cpu::Cpu;
+use crate::cpu::opcodes::Mnemonic;
+use crate::debugger::debugger::Target;
+use crate::memory::bus::Bus;
+use crate::symbols::symbols::SymbolTable;
+
+/// A level of the call stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Node {
+    /// a subroutine called with JSR, by its address
+    Subroutine(u16),
+    Nmi,
+    /// the IRQ handler, entered through the IRQ line or a BRK
+    Irq,
+}
+
+impl Node {
+    pub fn name(&self, symbols: &SymbolTable) -> String {
+        match self {
+            Node::Subroutine(address) => symbols
+                .label(*address)
+                .map_or_else(|| format!("${:04X}", address), str::to_string),
+            Node::Nmi => "NMI".to_string(),
+            Node::Irq => "IRQ".to_string(),
+        }
+    }
+}
+
+/// The calls the CPU is in, followed through JSR, BRK and interrupts. A level ends once the
+/// stack pointer has moved back above its return address, so RTS jump tables and code that
+/// drops its return address are handled
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    /// each level with the stack pointer just below its return address
+    levels: Vec<(Node, u8)>,
+    /// the level the instruction about to run enters, if it's a JSR or BRK
+    pending: Option<Node>,
+}
+
+impl CallStack {
+    /// Call before every instruction
+    pub fn before<B: Bus>(&mut self, cpu: &Cpu<B>) {
+        let address = cpu.program_counter;
+        let operand = |offset| cpu.peek_memory(address.wrapping_add(offset));
+        self.pending = match cpu.decode(operand(0)).mnemonic {
+            Mnemonic::Jsr => Some(Node::Subroutine(u16::from_le_bytes([
+                operand(1),
+                operand(2),
+            ]))),
+            Mnemonic::Brk => Some(Node::Irq),
+            _ => None,
+        };
+    }
+    /// Call after every instruction, with any interrupt it let in
+    pub fn after<B: Bus>(&mut self, cpu: &Cpu<B>) {
+        let mut stack_pointer = cpu.stack_pointer;
+        if cpu.interrupt.is_some() {
+            stack_pointer = stack_pointer.wrapping_add(3);
+        }
+        while self
+            .levels
+            .last()
+            .is_some_and(|&(_, level)| level < stack_pointer)
+        {
+            self.levels.pop();
+        }
+        if let Some(node) = self.pending.take() {
+            self.levels.push((node, stack_pointer));
+        }
+        if let Some(vector) = cpu.interrupt {
+            let node = if vector == Cpu::<B>::NMI_VECTOR {
+                Node::Nmi
+            } else {
+                Node::Irq
+            };
+            self.levels.push((node, cpu.stack_pointer));
+        }
+    }
+    /// the levels, outermost first
+    pub fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        self.levels.iter().map(|&(node, _)| node)
+    }
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+/// Where the cycles of a frame went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameProfile {
+    pub frame: u64,
+    pub cycles: u64,
+    /// cycles spent on the instruction at each address
+    pub flat: BTreeMap<u16, u64>,
+    /// cycles spent in the body of each call path, outermost first, the main loop is the
+    /// empty path. Entering an interrupt takes 7 cycles that count towards its handler only
+    pub calls: BTreeMap<Vec<Node>, u64>,
+}
+
+impl FrameProfile {
+    pub fn new(frame: u64) -> Self {
+        Self {
+            frame,
+            ..Self::default()
+        }
+    }
+    fn add(&mut self, address: Option<u16>, path: &[Node], cycles: u64) {
+        self.cycles += cycles;
+        if let Some(address) = address {
+            *self.flat.entry(address).or_default() += cycles;
+        }
+        *self.calls.entry(path.to_vec()).or_default() += cycles;
+    }
+
+    /// the cycles spent with `node` anywhere on the stack, like the whole NMI handler's time
+    pub fn inclusive(&self, node: Node) -> u64 {
+        self.calls
+            .iter()
+            .filter(|(path, _)| path.contains(&node))
+            .map(|(_, cycles)| cycles)
+            .sum()
+    }
+
+    /// The `limit` addresses that took the most cycles, busiest first
+    pub fn flat_report(&self, symbols: &SymbolTable, limit: usize) -> String {
+        let mut report = format!(
+            "frame {}: {} cycles, NMI {}, IRQ {}\n",
+            self.frame,
+            self.cycles,
+            self.inclusive(Node::Nmi),
+            self.inclusive(Node::Irq)
+        );
+        let mut flat: Vec<(u16, u64)> = self.flat.iter().map(|(&a, &c)| (a, c)).collect();
+        flat.sort_by_key(|&(address, cycles)| (std::cmp::Reverse(cycles), address));
+        for (address, cycles) in flat.into_iter().take(limit) {
+            let _ = writeln!(
+                report,
+                "{:>8} {:>5.1}%  ${:04X} {}",
+                cycles,
+                100.0 * cycles as f64 / self.cycles.max(1) as f64,
+                address,
+                symbols.label(address).unwrap_or("")
+            );
+        }
+        report
+    }
+
+    /// The call tree, each level indented under its caller with the cycles spent inside it
+    /// and in its own body
+    pub fn tree_report(&self, symbols: &SymbolTable) -> String {
+        let mut inclusive: BTreeMap<&[Node], u64> = BTreeMap::new();
+        for (path, &cycles) in &self.calls {
+            for depth in 0..=path.len() {
+                *inclusive.entry(&path[..depth]).or_default() += cycles;
+            }
+        }
+        // sorting the paths puts every caller right before its callees
+        let mut report = String::new();
+        for (path, cycles) in inclusive {
+            let name = path
+                .last()
+                .map_or("main".to_string(), |node| node.name(symbols));
+            let _ = writeln!(
+                report,
+                "{:indent$}{} {} ({} self)",
+                "",
+                name,
+                cycles,
+                self.calls.get(path).copied().unwrap_or(0),
+                indent = 2 * path.len()
+            );
+        }
+        report
+    }
+
+    /// One `main;caller;callee cycles` line per call path, what flamegraph tools read
+    pub fn collapsed(&self, symbols: &SymbolTable) -> String {
+        let mut lines = String::new();
+        for (path, cycles) in &self.calls {
+            let mut stack = "main".to_string();
+            for node in path {
+                stack += ";";
+                stack += &node.name(symbols);
+            }
+            let _ = writeln!(lines, "{} {}", stack, cycles);
+        }
+        lines
+    }
+
+    pub fn to_json(&self, symbols: &SymbolTable) -> Value {
+        let flat: Vec<Value> = self
+            .flat
+            .iter()
+            .map(|(&address, &cycles)| {
+                json!({
+                    "address": address,
+                    "label": symbols.label(address),
+                    "cycles": cycles,
+                })
+            })
+            .collect();
+        let calls: Vec<Value> = self
+            .calls
+            .iter()
+            .map(|(path, &cycles)| {
+                let stack: Vec<String> = path.iter().map(|node| node.name(symbols)).collect();
+                json!({ "stack": stack, "cycles": cycles })
+            })
+            .collect();
+        json!({
+            "frame": self.frame,
+            "cycles": self.cycles,
+            "nmi": self.inclusive(Node::Nmi),
+            "irq": self.inclusive(Node::Irq),
+            "flat": flat,
+            "calls": calls,
+        })
+    }
+}
+
+/// The instruction about to run
+struct Pending {
+    address: u16,
+    cycles: u64,
+}
+
+/// Counts the cycles spent at each address and in each subroutine, frame by frame, along the
+/// `CallStack`
+pub struct Profiler {
+    /// the frame being run
+    pub current: FrameProfile,
+    /// the frames before it, oldest first
+    pub frames: VecDeque<FrameProfile>,
+    /// everything since profiling started, its frame number is the first one's
+    pub total: FrameProfile,
+    stack: CallStack,
+    pending: Option<Pending>,
+}
+
+impl Profiler {
+    /// how many finished frames are kept, a minute's worth
+    pub const FRAME_HISTORY: usize = 3600;
+
+    pub fn new() -> Self {
+        Self {
+            current: FrameProfile::default(),
+            frames: VecDeque::new(),
+            total: FrameProfile::default(),
+            stack: CallStack::default(),
+            pending: None,
+        }
+    }
+
+    /// Call before every instruction
+    pub fn before<T: Target>(&mut self, target: &T) {
+        let frame = target.beam().map_or(0, |(frame, _, _)| frame);
+        if self.total.cycles == 0 {
+            self.total.frame = frame;
+        }
+        if frame != self.current.frame {
+            let finished = std::mem::replace(&mut self.current, FrameProfile::new(frame));
+            if finished.cycles > 0 {
+                if self.frames.len() == Self::FRAME_HISTORY {
+                    self.frames.pop_front();
+                }
+                self.frames.push_back(finished);
+            }
+        }
+        let cpu = target.cpu();
+        self.stack.before(cpu);
+        self.pending = Some(Pending {
+            address: cpu.program_counter,
+            cycles: cpu.cycles,
+        });
+    }
+
+    /// Call after every instruction, with any interrupt it let in
+    pub fn after<T: Target>(&mut self, target: &T) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let cpu = target.cpu();
+        let mut cycles = cpu.cycles - pending.cycles;
+        if cpu.interrupt.is_some() {
+            cycles = cycles.saturating_sub(7);
+        }
+        self.add(Some(pending.address), cycles);
+        self.stack.after(cpu);
+        if cpu.interrupt.is_some() {
+            self.add(None, 7);
+        }
+    }
+    fn add(&mut self, address: Option<u16>, cycles: u64) {
+        let path: Vec<Node> = self.stack.nodes().collect();
+        self.current.add(address, &path, cycles);
+        self.total.add(address, &path, cycles);
+    }
+
+    /// the last finished frame, or the one being run before any has finished
+    pub fn last_frame(&self) -> &FrameProfile {
+        self.frames.back().unwrap_or(&self.current)
+    }
+
+    /// the total and every kept frame
+    pub fn to_json(&self, symbols: &SymbolTable) -> Value {
+        let frames: Vec<Value> = self
+            .frames
+            .iter()
+            .map(|frame| frame.to_json(symbols))
+            .collect();
+        json!({ "total": self.total.to_json(symbols), "frames": frames })
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::debugger::debugger::Debugger;
+
+    #[test]
+    fn follows_calls_and_interrupts() {
+        let mut cpu = Cpu::with_program(asm!(
+            "
+            LDX #2
+            JSR sub
+            JSR sub
+            NOP
+    sub:    NOP
+            RTS
+    nmi:    NOP
+            RTI
+            "
+        ));
+        cpu.bus[0xFFFA] = 0x0B;
+        cpu.bus[0xFFFB] = 0x06;
+        let mut debugger = Debugger::new();
+        debugger.symbols.insert(0x0609, "sub");
+        debugger.profiler = Some(Profiler::new());
+        for _ in 0..7 {
+            debugger.step(&mut cpu);
+        }
+        // the NOP after the calls, with an NMI coming in at its end
+        let profiler = debugger.profiler.as_mut().unwrap();
+        profiler.before(&cpu);
+        cpu.step();
+        cpu.nmi();
+        profiler.after(&cpu);
+        debugger.step(&mut cpu);
+        debugger.step(&mut cpu);
+
+        let profile = &debugger.profiler.as_ref().unwrap().current;
+        assert_eq!(profile.cycles, 2 + 2 * 6 + 2 * (2 + 6) + 2 + 7 + 2 + 6);
+        assert_eq!(profile.flat[&0x0602], 6);
+        assert_eq!(profile.flat[&0x060A], 12);
+        assert_eq!(profile.inclusive(Node::Nmi), 15);
+        let symbols = &debugger.symbols;
+        assert_eq!(
+            profile.collapsed(symbols),
+            "main 16\nmain;sub 16\nmain;NMI 15\n"
+        );
+        assert_eq!(
+            profile.tree_report(symbols),
+            "main 47 (16 self)\n  sub 16 (16 self)\n  NMI 15 (15 self)\n"
+        );
+        assert!(profile
+            .flat_report(symbols, 1)
+            .starts_with("frame 0: 47 cycles, NMI 15, IRQ 0\n      12  25.5%  $060A"));
+        assert_eq!(profile.to_json(symbols)["calls"][1]["stack"][0], "sub");
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+
+use crate::memory::bus::Bus;
+use crate::symbols::symbols::SymbolTable;
+
+/// Where games keep their variables: the 2K of CPU RAM and the cartridge's PRG-RAM
+pub const REGIONS: [(u16, u16); 2] = [(0x0000, 0x07FF), (0x6000, 0x7FFF)];
+
+/// How the bytes at an address are read as a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// two bytes, little endian, rather than one
+    pub word: bool,
+    pub signed: bool,
+}
+
+impl Format {
+    pub const BYTE: Self = Self {
+        word: false,
+        signed: false,
+    };
+
+    /// `b`, `w`, `sb` or `sw`
+    pub fn parse(text: &str) -> Option<Self> {
+        let (signed, size) = match text.strip_prefix('s') {
+            Some(size) => (true, size),
+            None => (false, text),
+        };
+        let word = match size {
+            "b" => false,
+            "w" => true,
+            _ => return None,
+        };
+        Some(Self { word, signed })
+    }
+
+    pub fn bytes(&self) -> u16 {
+        1 + self.word as u16
+    }
+
+    pub fn read(&self, bus: &impl Bus, address: u16) -> i64 {
+        let lo = bus.peek(address);
+        match (self.word, self.signed) {
+            (false, false) => lo as i64,
+            (false, true) => lo as i8 as i64,
+            (true, signed) => {
+                let value = u16::from_le_bytes([lo, bus.peek(address.wrapping_add(1))]);
+                if signed {
+                    value as i16 as i64
+                } else {
+                    value as i64
+                }
+            }
+        }
+    }
+}
+
+/// What a search step compares each candidate's current value against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// its value at the previous step
+    Previous,
+    /// its value at the previous step plus this much, for "went up by one"
+    Delta(i64),
+    Value(i64),
+}
+
+/// Narrows down the addresses holding a game variable: every step keeps the candidates whose
+/// value compares the asked way against their value at the step before, or against a constant
+pub struct RamSearch {
+    pub format: Format,
+    /// the addresses still in the running with their values at the last step
+    candidates: Vec<(u16, i64)>,
+}
+
+impl RamSearch {
+    /// starts with every address in `REGIONS` as a candidate
+    pub fn new(bus: &impl Bus, format: Format) -> Self {
+        let candidates = REGIONS
+            .iter()
+            .flat_map(|&(start, end)| start..=end + 1 - format.bytes())
+            .map(|address| (address, format.read(bus, address)))
+            .collect();
+        Self { format, candidates }
+    }
+
+    /// Keeps the candidates whose value is `ordering` against `operand`, so `Equal` with
+    /// `Previous` keeps what didn't change and `Greater` with `Delta(0)` what went up
+    pub fn filter(&mut self, bus: &impl Bus, ordering: &[Ordering], operand: Operand) {
+        let format = self.format;
+        self.candidates.retain_mut(|(address, previous)| {
+            let current = format.read(bus, *address);
+            let target = match operand {
+                Operand::Previous => *previous,
+                Operand::Delta(delta) => *previous + delta,
+                Operand::Value(value) => value,
+            };
+            *previous = current;
+            ordering.contains(&current.cmp(&target))
+        });
+    }
+
+    /// the remaining candidates and the values they had at the last step
+    pub fn results(&self) -> &[(u16, i64)] {
+        &self.candidates
+    }
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+/// An address worth keeping an eye on, with a name for it, its symbol when that's left empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub address: u16,
+    pub format: Format,
+    pub label: String,
+}
+
+impl Watch {
+    pub fn show(&self, bus: &impl Bus, symbols: &SymbolTable) -> String {
+        let value = self.format.read(bus, self.address);
+        let width = 2 * self.format.bytes() as usize;
+        let hex = value as u64 & ((1 << (8 * self.format.bytes())) - 1);
+        let label = match self.label.as_str() {
+            "" => symbols
+                .label_on(bus, self.address)
+                .map_or_else(String::new, |label| format!("{} ", label)),
+            label => format!("{} ", label),
+        };
+        format!(
+            "{}${:04X} = {} ${:0width$X}",
+            label,
+            self.address,
+            value,
+            hex,
+            width = width
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::memory::Memory;
+
+    #[test]
+    fn narrows_down() {
+        let mut memory = Memory::new();
+        memory[0x0010] = 3;
+        memory[0x0400] = 3;
+        memory[0x6123] = 3;
+        let mut search = RamSearch::new(&memory, Format::BYTE);
+        assert_eq!(search.len(), 0x800 + 0x2000);
+
+        search.filter(&memory, &[Ordering::Equal], Operand::Value(3));
+        assert_eq!(search.len(), 3);
+        memory[0x0010] = 2;
+        memory[0x0400] = 4;
+        search.filter(&memory, &[Ordering::Less], Operand::Previous);
+        assert_eq!(search.results(), [(0x0010, 2)]);
+
+        let mut search = RamSearch::new(&memory, Format::parse("sw").unwrap());
+        assert_eq!(search.len(), 0x7FF + 0x1FFF);
+        memory[0x0020] = 0xFE;
+        memory[0x0021] = 0xFF;
+        search.filter(&memory, &[Ordering::Equal], Operand::Delta(-2));
+        assert_eq!(search.results(), [(0x0020, -2)]);
+
+        let watch = Watch {
+            address: 0x0020,
+            format: Format::parse("sw").unwrap(),
+            label: "speed".to_string(),
+        };
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0020, "player_speed");
+        assert_eq!(watch.show(&memory, &symbols), "speed $0020 = -2 $FFFE");
+        let watch = Watch {
+            label: String::new(),
+            ..watch
+        };
+        assert_eq!(
+            watch.show(&memory, &symbols),
+            "player_speed $0020 = -2 $FFFE"
+        );
+    }
+}
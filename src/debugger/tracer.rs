@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::cpu::disassembler;
+use crate::debugger::debugger::Target;
+use crate::symbols::symbols::SymbolTable;
+
+/// Which instructions make it into the trace, every condition that's set has to hold
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// inclusive program counter ranges, any address when empty
+    pub ranges: Vec<(u16, u16)>,
+    /// PRG-ROM banks, code outside ROM never matches once this is set
+    pub banks: Vec<u16>,
+    /// the first PPU frame to trace
+    pub from_frame: u64,
+}
+
+impl TraceFilter {
+    pub fn accepts<T: Target>(&self, target: &T) -> bool {
+        let address = target.cpu().program_counter;
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&address))
+        {
+            return false;
+        }
+        if !self.banks.is_empty()
+            && !target
+                .prg_bank(address)
+                .is_some_and(|bank| self.banks.contains(&bank))
+        {
+            return false;
+        }
+        target
+            .beam()
+            .is_none_or(|(frame, _, _)| frame >= self.from_frame)
+    }
+}
+
+/// Logs every instruction before it runs, either straight into a sink or into a ring buffer
+/// of the last few that's only written out by `dump`, which the debugger does when it hits a
+/// breakpoint or the CPU jams. Nothing is traced unless a `Tracer` is installed
+pub struct Tracer {
+    pub filter: TraceFilter,
+    /// how many lines the ring keeps, `None` writes every line as it comes
+    capacity: Option<usize>,
+    ring: VecDeque<String>,
+    sink: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn Write>) -> Self {
+        Self {
+            filter: TraceFilter::default(),
+            capacity: None,
+            ring: VecDeque::new(),
+            sink,
+        }
+    }
+    pub fn ring(capacity: usize, sink: Box<dyn Write>) -> Self {
+        Self {
+            capacity: Some(capacity.max(1)),
+            ..Self::new(sink)
+        }
+    }
+
+    /// Call before every instruction
+    pub fn record<T: Target>(&mut self, target: &T, symbols: &SymbolTable) {
+        if !self.filter.accepts(target) {
+            return;
+        }
+        let line = Self::line(target, symbols);
+        match self.capacity {
+            Some(capacity) => {
+                if self.ring.len() == capacity {
+                    self.ring.pop_front();
+                }
+                self.ring.push_back(line);
+            }
+            // like the CPU's own trace, write errors are dropped rather than stopping the run
+            None => {
+                let _ = writeln!(self.sink, "{}", line);
+            }
+        }
+    }
+
+    /// Writes out and empties the ring buffer
+    pub fn dump(&mut self) -> io::Result<()> {
+        for line in self.ring.drain(..) {
+            writeln!(self.sink, "{}", line)?;
+        }
+        self.sink.flush()
+    }
+    /// the lines waiting in the ring buffer, oldest first
+    pub fn buffered(&self) -> impl Iterator<Item = &String> {
+        self.ring.iter()
+    }
+
+    /// `PC  bytes  disassembly  registers  flags  cycle  scanline/dot  ; label source`, e.g.
+    /// `C000  4C F5 C5  JMP Main        A:00 X:00 Y:00 SP:FD P:nv-bdIzc CYC:7 SL:0 DOT:21 ; Reset main.s:12`
+    pub fn line<T: Target>(target: &T, symbols: &SymbolTable) -> String {
+        let cpu = target.cpu();
+        let (instruction, _) =
+            disassembler::disassemble(&cpu.bus, cpu.program_counter, cpu.variant);
+        let disassembly = instruction.to_string_on(&cpu.bus, symbols);
+        let bytes: Vec<String> = instruction
+            .bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let mut line = format!(
+            "{:04X}  {:<8}  {:<14}  A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{} CYC:{}",
+            instruction.address,
+            bytes.join(" "),
+            disassembly,
+            cpu.accumulator,
+            cpu.idx,
+            cpu.idy,
+            cpu.stack_pointer,
+            cpu.flags,
+            cpu.cycles
+        );
+        if let Some((_, scanline, dot)) = target.beam() {
+            line += &format!(" SL:{} DOT:{}", scanline, dot);
+        }
+        let label = symbols.label_on(&cpu.bus, cpu.program_counter);
+        let source = symbols.source_on(&cpu.bus, cpu.program_counter);
+        let notes: Vec<String> = label
+            .map(str::to_string)
+            .into_iter()
+            .chain(source.map(|(file, number)| format!("{}:{}", file, number)))
+            .collect();
+        if !notes.is_empty() {
+            line += &format!(" ; {}", notes.join(" "));
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::cpu::Cpu;
+    use crate::debugger::debugger::{Debugger, Stop};
+    use std::sync::{Arc, Mutex};
+
+    /// a sink the test can still read after handing it to the tracer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock().unwrap();
+            String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn streams_filtered_lines() {
+        let mut cpu = Cpu::with_program(asm!("LDX #3\nloop: DEX\nBNE loop\nBRK"));
+        let sink = Shared::default();
+        let mut debugger = Debugger::new();
+        debugger.symbols.insert(0x0602, "loop");
+        let mut tracer = Tracer::new(Box::new(sink.clone()));
+        tracer.filter.ranges.push((0x0602, 0x0603));
+        debugger.tracer = Some(tracer);
+        for _ in 0..7 {
+            debugger.step(&mut cpu);
+        }
+        let lines = sink.lines();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "0602  CA        DEX             A:00 X:03 Y:00 SP:FF P:nv-bdizc CYC:2 ; loop"
+        );
+        assert!(lines[1].starts_with("0603  D0 FD     BNE loop  "));
+    }
+
+    #[test]
+    fn ring_dumps_on_breakpoint() {
+        let mut cpu = Cpu::with_program(asm!("LDX #10\nloop: DEX\nBNE loop\nBRK"));
+        let sink = Shared::default();
+        let mut debugger = Debugger::new();
+        debugger.tracer = Some(Tracer::ring(4, Box::new(sink.clone())));
+        debugger.add_breakpoint(&cpu, 0x0605, None).unwrap();
+        assert_eq!(debugger.resume(&mut cpu, 1000), Stop::Breakpoint(0));
+        let lines = sink.lines();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[3].starts_with("0603  D0 FD     BNE $0602"));
+        assert!(lines[3].contains("P:nv-bdiZc"));
+        assert_eq!(debugger.tracer.as_ref().unwrap().buffered().count(), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::cartridge::cartridge::CartridgeError;
+use crate::memory::bus::Bus;
+use crate::nes::nes::Nes;
+
+/// What a test ROM reported through the $6000 protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRomResult {
+    /// the final status byte, 0 means every test passed
+    pub status: u8,
+    pub message: String,
+}
+
+impl TestRomResult {
+    pub fn passed(&self) -> bool {
+        self.status == 0
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TestRomError {
+    Cartridge(CartridgeError),
+    /// the ROM was still running after the frame budget ran out
+    Timeout {
+        frames: u64,
+    },
+    /// the CPU hit a JAM opcode before the ROM reported anything
+    Jammed {
+        program_counter: u16,
+    },
+}
+
+impl fmt::Display for TestRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cartridge(error) => write!(f, "{}", error),
+            Self::Timeout { frames } => write!(f, "test ROM still running after {} frames", frames),
+            Self::Jammed { program_counter } => {
+                write!(f, "CPU jammed at ${:04X}", program_counter)
+            }
+        }
+    }
+}
+
+impl Error for TestRomError {}
+
+impl From<CartridgeError> for TestRomError {
+    fn from(error: CartridgeError) -> Self {
+        Self::Cartridge(error)
+    }
+}
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const MESSAGE: u16 = 0x6004;
+const MAGIC: [u8; 3] = [0xDE, 0xB0, 0x61];
+const RUNNING: u8 = 0x80;
+const RESET_REQUESTED: u8 = 0x81;
+/// blargg asks for at least 100ms between a reset request and the reset itself
+const RESET_DELAY_FRAMES: u64 = 6;
+
+/// Runs a ROM that follows blargg's result protocol until it reports a final status.
+/// Until $6001-$6003 hold the DE B0 61 signature $6000 means nothing, after that it reads
+/// $80 while running, $81 when the ROM wants the reset button pressed, and the result otherwise.
+/// Only NROM ROMs load, so of blargg's suites just the instr_test-v5 singles are exercised
+pub fn run_test_rom(rom: &[u8], max_frames: u64) -> Result<TestRomResult, TestRomError> {
+    let mut nes = Nes::from_ines(rom)?;
+    let mut reset_at = None;
+    for frame in 0..max_frames {
+        nes.run_frame();
+        if nes.cpu.jammed {
+            return Err(TestRomError::Jammed {
+                program_counter: nes.cpu.program_counter,
+            });
+        }
+        match status(&nes) {
+            None | Some(RUNNING) => reset_at = None,
+            Some(RESET_REQUESTED) => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    nes.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            Some(status) => {
+                return Ok(TestRomResult {
+                    status,
+                    message: message(&nes),
+                })
+            }
+        }
+    }
+    Err(TestRomError::Timeout { frames: max_frames })
+}
+
+fn status(nes: &Nes) -> Option<u8> {
+    let bus = &nes.cpu.bus;
+    let signed = (0..3).all(|offset| bus.peek(SIGNATURE + offset) == MAGIC[offset as usize]);
+    signed.then(|| bus.peek(STATUS))
+}
+
+/// the null terminated text at $6004
+fn message(nes: &Nes) -> String {
+    let bytes: Vec<u8> = (MESSAGE..0x8000)
+        .map(|address| nes.cpu.bus.peek(address))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an NROM image running `program` from $8000
+    fn rom_with(program: &[u8]) -> Vec<u8> {
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        rom
+    }
+
+    /// signs $6001-$6003, marks the test as running and copies a message from $8030
+    const PREAMBLE: [u8; 33] = [
+        0xA9, 0xDE, 0x8D, 0x01, 0x60, // LDA #$DE, STA $6001
+        0xA9, 0xB0, 0x8D, 0x02, 0x60, // LDA #$B0, STA $6002
+        0xA9, 0x61, 0x8D, 0x03, 0x60, // LDA #$61, STA $6003
+        0xA9, 0x80, 0x8D, 0x00, 0x60, // LDA #$80, STA $6000
+        0xA2, 0x00, // LDX #$00
+        0xBD, 0x30, 0x80, // LDA $8030,X
+        0x9D, 0x04, 0x60, // STA $6004,X
+        0xF0, 0x03, // BEQ +3
+        0xE8, // INX
+        0xD0, 0xF5, // BNE -11
+    ];
+
+    fn reporting(status: u8, message: &str) -> Vec<u8> {
+        let mut program = PREAMBLE.to_vec();
+        program.extend([0xA9, status, 0x8D, 0x00, 0x60]); // LDA #status, STA $6000
+        program.extend([0x4C, 0x26, 0x80]); // JMP $8026
+        program.resize(0x30, 0xEA);
+        program.extend(message.bytes());
+        program.push(0);
+        rom_with(&program)
+    }
+
+    #[test]
+    fn reports_pass() {
+        let result = run_test_rom(&reporting(0, "Passed\n"), 10).unwrap();
+        assert!(result.passed());
+        assert_eq!(result.message, "Passed\n");
+    }
+
+    #[test]
+    fn reports_failure_code() {
+        let result = run_test_rom(&reporting(3, "BCC failed"), 10).unwrap();
+        assert!(!result.passed());
+        assert_eq!(result.status, 3);
+        assert_eq!(result.message, "BCC failed");
+    }
+
+    #[test]
+    fn presses_reset_on_request() {
+        let program = [
+            0xAD, 0x10, 0x60, // LDA $6010
+            0xD0, 0x1A, // BNE +26, second boot
+            0xEE, 0x10, 0x60, // INC $6010
+            0xA9, 0xDE, 0x8D, 0x01, 0x60, // LDA #$DE, STA $6001
+            0xA9, 0xB0, 0x8D, 0x02, 0x60, // LDA #$B0, STA $6002
+            0xA9, 0x61, 0x8D, 0x03, 0x60, // LDA #$61, STA $6003
+            0xA9, 0x81, 0x8D, 0x00, 0x60, // LDA #$81, STA $6000
+            0x4C, 0x1C, 0x80, // JMP $801C
+            0xA9, 0x00, 0x8D, 0x00, 0x60, // LDA #$00, STA $6000
+            0x4C, 0x24, 0x80, // JMP $8024
+        ];
+        let result = run_test_rom(&rom_with(&program), 20).unwrap();
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn times_out() {
+        let rom = rom_with(&[0x4C, 0x00, 0x80]); // JMP $8000
+        assert_eq!(
+            run_test_rom(&rom, 3),
+            Err(TestRomError::Timeout { frames: 3 })
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the suites roms/fetch.sh downloads, run with `cargo test -- --ignored`"]
+    fn test_rom_matrix() {
+        let roms = crate::harness::suite_file("blargg");
+        let mut failures = Vec::new();
+        for entry in std::fs::read_dir(roms).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("nes".as_ref()) {
+                continue;
+            }
+            let rom = std::fs::read(&path).unwrap();
+            match run_test_rom(&rom, 60 * 60) {
+                Ok(result) if result.passed() => {}
+                Ok(result) => failures.push(format!("{}: {}", path.display(), result.message)),
+                Err(error) => failures.push(format!("{}: {}", path.display(), error)),
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
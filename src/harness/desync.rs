@@ -0,0 +1,330 @@
+use std::fmt;
+
+use crate::memory::recording::AccessKind;
+use crate::movie::movie::{FrameInput, Movie, MovieError};
+use crate::nes::nes::Nes;
+use crate::ppu::ppu::Ppu;
+use crate::state::state::StateWriter;
+
+/// The first thing found to differ between the two machines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Register {
+        name: &'static str,
+        left: u64,
+        right: u64,
+    },
+    /// internal RAM the game has written, or cartridge RAM from $6000
+    Ram {
+        address: u16,
+        left: u8,
+        right: u8,
+    },
+    /// the mapper's registers, CHR-RAM or anything else it keeps besides $6000-$7FFF
+    Mapper,
+    Ppu {
+        field: &'static str,
+    },
+}
+
+/// Where two machines fed the same input stopped agreeing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Desync {
+    /// the left machine's CPU cycle count after the instruction that diverged
+    pub cycle: u64,
+    /// frames run by the checker before the one that diverged
+    pub frame: usize,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "desync in frame {} at cycle {}: ",
+            self.frame, self.cycle
+        )?;
+        match &self.difference {
+            Difference::Register { name, left, right } => {
+                write!(
+                    f,
+                    "{} is {:X} on the left, {:X} on the right",
+                    name, left, right
+                )
+            }
+            Difference::Ram {
+                address,
+                left,
+                right,
+            } => write!(
+                f,
+                "${:04X} is {:02X} on the left, {:02X} on the right",
+                address, left, right
+            ),
+            Difference::Mapper => write!(f, "the mapper's state differs"),
+            Difference::Ppu { field } => write!(f, "the PPU's {} differs", field),
+        }
+    }
+}
+
+/// Runs two machines in lockstep on the same input and compares them after every instruction,
+/// to catch hidden state such as uninitialised memory or host dependent behaviour.
+/// Internal RAM only counts once it has been written since power-on, so machines powered on
+/// with different `RamPattern`s only desync when the game reads memory it never set
+pub struct DesyncChecker {
+    pub left: Nes,
+    pub right: Nes,
+    /// frames run so far
+    pub frame: usize,
+    /// the internal RAM bytes written since power-on
+    written: Vec<bool>,
+}
+
+impl DesyncChecker {
+    /// `left` and `right` should be freshly powered on, nothing in their RAM counts as set yet
+    pub fn new(mut left: Nes, right: Nes) -> Self {
+        left.cpu.record_accesses(true);
+        Self {
+            left,
+            right,
+            frame: 0,
+            written: vec![false; 0x800],
+        }
+    }
+
+    /// Runs one frame of the left machine with `input` held on both
+    pub fn run_frame(&mut self, input: &FrameInput) -> Result<(), Desync> {
+        if input.commands & FrameInput::POWER != 0 {
+            self.written.fill(false);
+        }
+        input.apply(&mut self.left);
+        input.apply(&mut self.right);
+        self.check()?;
+        let frame = self.left.cpu.bus.ppu.frame;
+        while self.left.cpu.bus.ppu.frame == frame && !self.left.cpu.jammed {
+            self.left.step();
+            self.right.step();
+            // a write going elsewhere on the right would have shown up in its registers first
+            for access in self.left.cpu.take_accesses() {
+                if access.kind == AccessKind::Write && access.address < 0x2000 {
+                    self.written[access.address as usize & 0x7FF] = true;
+                }
+            }
+            self.check()?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Plays a movie on both machines from its start
+    pub fn run_movie(&mut self, movie: &Movie) -> Result<Result<(), Desync>, MovieError> {
+        movie.begin(&mut self.left)?;
+        movie.begin(&mut self.right)?;
+        // a saved state sets all of RAM
+        self.written.fill(movie.start.is_some());
+        self.frame = 0;
+        for input in &movie.frames {
+            if let Err(desync) = self.run_frame(input) {
+                return Ok(Err(desync));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Compares the machines as they are now
+    pub fn check(&self) -> Result<(), Desync> {
+        match compare(&self.left, &self.right, &self.written) {
+            Some(difference) => Err(Desync {
+                cycle: self.left.cpu.cycles,
+                frame: self.frame,
+                difference,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The first difference between two machines, registers first, then RAM, then the mapper, then
+/// the PPU. Internal RAM is only compared where `written` is set
+pub fn compare(left: &Nes, right: &Nes, written: &[bool]) -> Option<Difference> {
+    let registers = |nes: &Nes| {
+        let cpu = &nes.cpu;
+        [
+            ("PC", cpu.program_counter as u64),
+            ("A", cpu.accumulator as u64),
+            ("X", cpu.idx as u64),
+            ("Y", cpu.idy as u64),
+            ("SP", cpu.stack_pointer as u64),
+            ("P", cpu.flags.into_u8() as u64),
+            ("CYC", cpu.cycles),
+        ]
+    };
+    for ((name, left), (_, right)) in registers(left).into_iter().zip(registers(right)) {
+        if left != right {
+            return Some(Difference::Register { name, left, right });
+        }
+    }
+
+    let (left_bus, right_bus) = (&left.cpu.bus, &right.cpu.bus);
+    let ram = left_bus.ram.iter().zip(&right_bus.ram).enumerate();
+    for (address, (&left, &right)) in ram {
+        if left != right && written[address] {
+            return Some(Difference::Ram {
+                address: address as u16,
+                left,
+                right,
+            });
+        }
+    }
+    // cheaper than peeking every address, and the mapper's state is mostly its RAM
+    let mapper_state = |nes: &Nes| {
+        let mut state = StateWriter::new();
+        nes.cpu.bus.mapper.save_state(&mut state);
+        state.bytes
+    };
+    if mapper_state(left) != mapper_state(right) {
+        for address in 0x6000..0x8000 {
+            let left = left_bus.mapper.peek_prg(address);
+            let right = right_bus.mapper.peek_prg(address);
+            if left != right {
+                return Some(Difference::Ram {
+                    address,
+                    left,
+                    right,
+                });
+            }
+        }
+        return Some(Difference::Mapper);
+    }
+
+    compare_ppu(&left_bus.ppu, &right_bus.ppu).map(|field| Difference::Ppu { field })
+}
+
+fn compare_ppu(left: &Ppu, right: &Ppu) -> Option<&'static str> {
+    let fields: [(&'static str, bool); 15] = [
+        ("ctrl", left.ctrl == right.ctrl),
+        ("mask", left.mask == right.mask),
+        ("status", left.status == right.status),
+        ("OAM address", left.oam_address == right.oam_address),
+        ("OAM", left.oam == right.oam),
+        ("VRAM", left.vram == right.vram),
+        ("palette", left.palette == right.palette),
+        ("v", left.v == right.v),
+        ("t", left.t == right.t),
+        ("x", left.x == right.x),
+        ("w", left.w == right.w),
+        ("read buffer", left.read_buffer == right.read_buffer),
+        ("scanline", left.scanline == right.scanline),
+        ("dot", left.dot == right.dot),
+        ("frame", left.frame == right.frame),
+    ];
+    fields
+        .iter()
+        .find(|(_, same)| !same)
+        .map(|(field, _)| *field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::cartridge::tests::nrom;
+    use crate::memory::memory::RamPattern;
+    use crate::movie::movie::tests::{joypad_nes, sample_movie};
+
+    /// adds the byte at $6000 into $10 every NMI
+    fn prg_ram_nes() -> Nes {
+        let program = asm!(
+            "
+            .org $8000
+    reset:  LDA #$80
+            STA $2000
+    idle:   JMP idle
+    nmi:    CLC
+            LDA $6000
+            ADC $10
+            STA $10
+            RTI
+            .org $FFFA
+            .word nmi, reset, reset
+            "
+        );
+        Nes::from_ines(&nrom(&program)).unwrap()
+    }
+
+    #[test]
+    fn identical_machines_agree() {
+        let mut checker = DesyncChecker::new(
+            joypad_nes().with_ram_pattern(RamPattern::Random(7)),
+            joypad_nes().with_ram_pattern(RamPattern::Random(7)),
+        );
+        let mut movie = sample_movie(&checker.left);
+        movie.frames.truncate(8);
+        assert_eq!(checker.run_movie(&movie).unwrap(), Ok(()));
+        assert_eq!(checker.frame, movie.frames.len());
+
+        // RAM nothing has written yet may differ
+        let program = asm!(
+            "
+            .org $8000
+    reset:  LDA #7
+            STA $10
+    idle:   JMP idle
+            .org $FFFA
+            .word reset, reset, reset
+            "
+        );
+        let mut checker = DesyncChecker::new(
+            Nes::from_ines(&nrom(&program)).unwrap(),
+            Nes::from_ines(&nrom(&program))
+                .unwrap()
+                .with_ram_pattern(RamPattern::Ones),
+        );
+        for _ in 0..3 {
+            checker.run_frame(&FrameInput::default()).unwrap();
+        }
+    }
+
+    #[test]
+    fn finds_first_difference() {
+        let mut checker = DesyncChecker::new(
+            joypad_nes(),
+            joypad_nes().with_ram_pattern(RamPattern::Ones),
+        );
+        // the NMI's `INC $02` reads a byte nothing set, 0 + 1 on the left and $FF + 1 on the right
+        let desync = checker.run_frame(&FrameInput::default()).unwrap_err();
+        assert_eq!(
+            desync.difference,
+            Difference::Register {
+                name: "P",
+                left: 0x24,
+                right: 0x26
+            }
+        );
+
+        let mut checker = DesyncChecker::new(prg_ram_nes(), prg_ram_nes());
+        for _ in 0..3 {
+            checker.run_frame(&FrameInput::default()).unwrap();
+        }
+        checker.right.cpu.bus.mapper.write_prg(0x6000, 5);
+        let desync = checker.run_frame(&FrameInput::default()).unwrap_err();
+        assert_eq!(
+            desync.difference,
+            Difference::Ram {
+                address: 0x6000,
+                left: 0,
+                right: 5
+            }
+        );
+        assert_eq!(desync.frame, 3);
+        // the sum in $10 stays in step once both carts hold the same value again
+        checker.left.cpu.bus.mapper.write_prg(0x6000, 5);
+        checker.run_frame(&FrameInput::default()).unwrap();
+        assert!(desync.to_string().starts_with("desync in frame 3 at cycle"));
+
+        // the carts' CHR-RAM isn't visible from the CPU but still counts
+        checker.right.cpu.bus.mapper.write_chr(0x0010, 1);
+        let desync = checker.run_frame(&FrameInput::default()).unwrap_err();
+        assert_eq!(desync.difference, Difference::Mapper);
+    }
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::cpu::cpu::{Cpu, CpuVariant};
+use crate::memory::bus::Bus;
+use crate::memory::memory::Memory;
+
+/// where the test binaries start running
+pub const START: u16 = 0x0400;
+/// where the default builds of `6502_functional_test.bin` and `6502_interrupt_test.bin` loop
+/// once every test passed
+pub const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+pub const INTERRUPT_SUCCESS: u16 = 0x06F5;
+/// the functional test's `test_case` variable, the number of the test that's running
+pub const TEST_CASE: u16 = 0x0200;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KlausError {
+    /// the program jumped to itself somewhere other than the success address, which is
+    /// how the tests stop on a failure
+    Trapped {
+        address: u16,
+        test_case: u8,
+    },
+    /// the program was still running when the cycle budget ran out
+    Timeout {
+        cycles: u64,
+    },
+    Jammed {
+        program_counter: u16,
+    },
+}
+
+impl fmt::Display for KlausError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trapped { address, test_case } => write!(
+                f,
+                "trapped at ${:04X} in test case ${:02X}",
+                address, test_case
+            ),
+            Self::Timeout { cycles } => write!(f, "still running after {} cycles", cycles),
+            Self::Jammed { program_counter } => {
+                write!(f, "CPU jammed at ${:04X}", program_counter)
+            }
+        }
+    }
+}
+
+impl Error for KlausError {}
+
+/// Flat memory with the interrupt test's feedback register: the program drives IRQ with
+/// bit 0 of $BFFC, held as long as the bit is set, and NMI with a rising edge on bit 1
+pub struct FeedbackBus {
+    pub memory: Memory,
+    nmi: bool,
+}
+
+impl FeedbackBus {
+    pub const PORT: u16 = 0xBFFC;
+    pub const IRQ: u8 = 0x01;
+    pub const NMI: u8 = 0x02;
+
+    pub fn new(memory: Memory) -> Self {
+        Self { memory, nmi: false }
+    }
+}
+
+impl Bus for FeedbackBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address]
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        if address == Self::PORT && value & !self.memory[address] & Self::NMI != 0 {
+            self.nmi = true;
+        }
+        self.memory[address] = value;
+    }
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address]
+    }
+    fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+    fn irq(&self) -> bool {
+        self.memory[Self::PORT] & Self::IRQ != 0
+    }
+}
+
+/// Builds a CPU with one of Klaus Dormann's test binaries, a 64K image, loaded at $0000 and
+/// about to run from $0400. The tests check decimal mode, which needs the NMOS 6502
+pub fn klaus_cpu(image: &[u8], variant: CpuVariant) -> Cpu<FeedbackBus> {
+    let mut memory = Memory::new();
+    memory.load(0, &image[..image.len().min(0x10000)]);
+    let mut cpu = Cpu::with_bus(FeedbackBus::new(memory));
+    cpu.variant = variant;
+    cpu.reset();
+    cpu.program_counter = START;
+    cpu
+}
+
+/// Runs until the program jumps to itself, passing when that happens at `success`
+pub fn run(cpu: &mut Cpu<FeedbackBus>, success: u16, max_cycles: u64) -> Result<(), KlausError> {
+    let end = cpu.cycles + max_cycles;
+    while cpu.cycles < end {
+        let program_counter = cpu.program_counter;
+        cpu.step();
+        if cpu.jammed {
+            return Err(KlausError::Jammed {
+                program_counter: cpu.program_counter,
+            });
+        }
+        if cpu.interrupt.is_none() && cpu.program_counter == program_counter {
+            return match program_counter {
+                address if address == success => Ok(()),
+                address => Err(KlausError::Trapped {
+                    address,
+                    test_case: cpu.peek_memory(TEST_CASE),
+                }),
+            };
+        }
+    }
+    Err(KlausError::Timeout { cycles: max_cycles })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn image(source: &str) -> Vec<u8> {
+        let mut image = vec![0; 0x10000];
+        asm!(source).patch(&mut image, 0).unwrap();
+        image
+    }
+
+    #[test]
+    fn reports_traps() {
+        let source = "
+            .org $0400
+            LDA #7
+            STA $0200
+            SEC
+    fail:   BCS fail
+    done:   JMP done
+        ";
+        let mut cpu = klaus_cpu(&image(source), CpuVariant::Nmos6502);
+        assert_eq!(
+            run(&mut cpu, 0x0408, 1000),
+            Err(KlausError::Trapped {
+                address: 0x0406,
+                test_case: 7
+            })
+        );
+        let mut cpu = klaus_cpu(&image(source), CpuVariant::Nmos6502);
+        cpu.bus.memory[0x0406] = 0x90; // BCC fail
+        assert_eq!(run(&mut cpu, 0x0408, 1000), Ok(()));
+
+        cpu.program_counter = 0x0400;
+        cpu.bus.memory.load(0x0400, &[0xEA, 0x4C, 0x00, 0x04]); // NOP, JMP $0400
+        assert_eq!(
+            run(&mut cpu, 0x0408, 50),
+            Err(KlausError::Timeout { cycles: 50 })
+        );
+    }
+
+    #[test]
+    fn drives_interrupts_through_the_feedback_port() {
+        let image = image(
+            "
+            .org $0400
+            CLI
+            LDA #$01
+            STA $BFFC       ; IRQ, taken after the next instruction
+            NOP
+            LDA #$02
+            STA $BFFC       ; NMI
+            STA $BFFC       ; no edge, no NMI
+    done:   JMP done
+    irq:    INC $10
+            LDA #0
+            STA $BFFC
+            RTI
+    nmi:    INC $11
+            RTI
+            .org $FFFA
+            .word nmi, $0400, irq
+            ",
+        );
+        let mut cpu = klaus_cpu(&image, CpuVariant::Nmos6502);
+        assert_eq!(run(&mut cpu, 0x040F, 1000), Ok(()));
+        assert_eq!(cpu.peek_memory(0x0010), 1);
+        assert_eq!(cpu.peek_memory(0x0011), 1);
+    }
+
+    fn binary(name: &str) -> Vec<u8> {
+        std::fs::read(crate::harness::suite_file(&format!("klaus/{}", name))).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs the suites roms/fetch.sh downloads, run with `cargo test -- --ignored`"]
+    fn functional_test() {
+        let mut cpu = klaus_cpu(&binary("6502_functional_test.bin"), CpuVariant::Nmos6502);
+        let result = run(&mut cpu, FUNCTIONAL_SUCCESS, 200_000_000);
+        assert_eq!(result, Ok(()), "{}", result.as_ref().unwrap_err());
+    }
+
+    #[test]
+    #[ignore = "needs the suites roms/fetch.sh downloads, run with `cargo test -- --ignored`"]
+    fn interrupt_test() {
+        let mut cpu = klaus_cpu(&binary("6502_interrupt_test.bin"), CpuVariant::Nmos6502);
+        let result = run(&mut cpu, INTERRUPT_SUCCESS, 10_000_000);
+        assert_eq!(result, Ok(()), "{}", result.as_ref().unwrap_err());
+    }
+}
@@ -0,0 +1,21 @@
+pub mod blargg;
+pub mod desync;
+pub mod klaus;
+pub mod nestest;
+pub mod single_step;
+
+/// A file of one of the conformance suites `roms/fetch.sh` downloads, by its path in `roms/`.
+/// The tests running the suites are ignored unless asked for with `cargo test -- --ignored`,
+/// and then a missing file fails them rather than letting them pass without running
+#[cfg(test)]
+pub(crate) fn suite_file(name: &str) -> std::path::PathBuf {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("roms")
+        .join(name);
+    assert!(
+        path.exists(),
+        "{} is missing, run roms/fetch.sh",
+        path.display()
+    );
+    path
+}
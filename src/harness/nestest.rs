@@ -0,0 +1,126 @@
+use std::fmt;
+
+use crate::cartridge::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::cpu::Cpu;
+
+/// The first line where our trace and the golden log disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// 1-based line number in the golden log
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "trace diverged from the golden log at line {}",
+            self.line
+        )?;
+        writeln!(f, "expected: {}", self.expected)?;
+        write!(f, "  actual: {}", self.actual)
+    }
+}
+
+/// Builds a CPU ready to run nestest in automation mode: the PRG-ROM is mapped at $8000
+/// (mirrored when it's a single 16K bank) and execution starts at $C000 instead of the reset vector
+pub fn nestest_cpu(rom: &[u8]) -> Result<Cpu, CartridgeError> {
+    let cartridge = Cartridge::from_ines(rom)?;
+    let mut cpu = Cpu::new();
+    cpu.bus.load(0x8000, &cartridge.prg_rom);
+    if cartridge.prg_rom.len() == Cartridge::PRG_BANK_LEN {
+        cpu.bus.load(0xC000, &cartridge.prg_rom);
+    }
+    cpu.reset();
+    cpu.program_counter = 0xC000;
+    Ok(cpu)
+}
+
+/// Steps `cpu` once per line of `golden_log`, comparing our trace line against it before each
+/// instruction. Returns how many lines matched, or the first one that didn't
+pub fn compare_with_log(cpu: &mut Cpu, golden_log: &str) -> Result<usize, Divergence> {
+    let mut matched = 0;
+    for (index, expected) in golden_log.lines().enumerate() {
+        let expected = expected.trim_end();
+        if expected.is_empty() {
+            continue;
+        }
+        let actual = cpu.trace_line();
+        if actual != expected {
+            return Err(Divergence {
+                line: index + 1,
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        cpu.step();
+        matched += 1;
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a single 16K bank NROM image with `program` at $C000 and an RTS at $C100
+    fn rom_with(program: &[u8]) -> Vec<u8> {
+        let mut rom = b"NES\x1A\x01\x00\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prg = vec![0xEA; Cartridge::PRG_BANK_LEN];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x100] = 0x60;
+        rom.extend(prg);
+        rom
+    }
+
+    const PROGRAM: [u8; 15] = [
+        0xA2, 0x05, // LDX #$05
+        0x86, 0x10, // STX $10
+        0xA9, 0x80, // LDA #$80
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0xA7, 0x10, // LAX $10
+        0x20, 0x00, 0xC1, // JSR $C100
+        0xEA, // NOP
+    ];
+
+    const GOLDEN_LOG: &str = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  86 10     STX $10 = 00                    A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C004  A9 80     LDA #$80                        A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12
+C006  9D 00 02  STA $0200,X @ 0205 = 00         A:80 X:05 Y:00 P:A4 SP:FD PPU:  0, 42 CYC:14
+C009  A7 10    *LAX $10 = 05                    A:80 X:05 Y:00 P:A4 SP:FD PPU:  0, 57 CYC:19
+C00B  20 00 C1  JSR $C100                       A:05 X:05 Y:00 P:24 SP:FD PPU:  0, 66 CYC:22
+C100  60        RTS                             A:05 X:05 Y:00 P:24 SP:FB PPU:  0, 84 CYC:28
+C00E  EA        NOP                             A:05 X:05 Y:00 P:24 SP:FD PPU:  0,102 CYC:34
+";
+
+    #[test]
+    fn matches_golden_log() {
+        let mut cpu = nestest_cpu(&rom_with(&PROGRAM)).unwrap();
+        assert_eq!(compare_with_log(&mut cpu, GOLDEN_LOG), Ok(8));
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        let mut cpu = nestest_cpu(&rom_with(&PROGRAM)).unwrap();
+        let golden = GOLDEN_LOG.replace("SP:FB", "SP:FA");
+        let divergence = compare_with_log(&mut cpu, &golden).unwrap_err();
+        assert_eq!(divergence.line, 7);
+        assert!(divergence.expected.contains("SP:FA"));
+        assert!(divergence.actual.contains("SP:FB"));
+    }
+
+    #[test]
+    #[ignore = "needs the suites roms/fetch.sh downloads, run with `cargo test -- --ignored`"]
+    fn nestest() {
+        let rom = std::fs::read(crate::harness::suite_file("nestest.nes")).unwrap();
+        let log = std::fs::read_to_string(crate::harness::suite_file("nestest.log")).unwrap();
+        let mut cpu = nestest_cpu(&rom).unwrap();
+        if let Err(divergence) = compare_with_log(&mut cpu, &log) {
+            panic!("{}", divergence);
+        }
+    }
+}
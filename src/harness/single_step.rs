@@ -0,0 +1,292 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::cpu::cpu::{Cpu, CpuVariant};
+use crate::cpu::flags::Flags;
+use crate::memory::bus::Bus;
+use crate::memory::memory::Memory;
+use crate::memory::recording::{AccessKind, BusAccess, RecordingBus};
+
+/// One case from the SingleStepTests (ProcessorTests) suites: a single instruction
+/// executed from a known state, with the expected final state and every bus cycle in between
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: CpuState,
+    #[serde(rename = "final")]
+    pub expected: CpuState,
+    /// `[address, value, "read" | "write"]` for each cycle
+    pub cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    /// sparse memory contents as `[address, value]` pairs
+    pub ram: Vec<(u16, u8)>,
+}
+
+/// How a case's outcome differed from what the suite expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Register {
+        name: &'static str,
+        expected: u16,
+        actual: u16,
+    },
+    Memory {
+        address: u16,
+        expected: u8,
+        actual: u8,
+    },
+    Cycles {
+        expected: Vec<BusAccess>,
+        actual: Vec<BusAccess>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{} is ${:02X}, expected ${:02X}", name, actual, expected),
+            Self::Memory {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "${:04X} holds ${:02X}, expected ${:02X}",
+                address, actual, expected
+            ),
+            Self::Cycles { expected, actual } => {
+                writeln!(f, "bus activity differs")?;
+                writeln!(f, "expected: {}", format_cycles(expected))?;
+                write!(f, "  actual: {}", format_cycles(actual))
+            }
+        }
+    }
+}
+
+fn format_cycles(cycles: &[BusAccess]) -> String {
+    let cycles: Vec<String> = cycles
+        .iter()
+        .map(|access| {
+            let kind = match access.kind {
+                AccessKind::Read => 'R',
+                AccessKind::Write => 'W',
+            };
+            format!("{}:{:04X}={:02X}", kind, access.address, access.value)
+        })
+        .collect();
+    cycles.join(" ")
+}
+
+pub fn load_tests(json: &str) -> serde_json::Result<Vec<TestCase>> {
+    serde_json::from_str(json)
+}
+
+/// Runs a single case against a flat 64K bus, checking registers, memory and then the
+/// cycle by cycle bus log. The `6502` suite wants `CpuVariant::Nmos6502`, `nes6502` the 2A03
+pub fn run_test(case: &TestCase, variant: CpuVariant) -> Result<(), Mismatch> {
+    let initial = &case.initial;
+    let mut memory = Memory::new();
+    for &(address, value) in &initial.ram {
+        memory[address] = value;
+    }
+    let mut cpu = Cpu::with_bus(RecordingBus::new(memory));
+    cpu.variant = variant;
+    cpu.program_counter = initial.pc;
+    cpu.stack_pointer = initial.s;
+    cpu.accumulator = initial.a;
+    cpu.idx = initial.x;
+    cpu.idy = initial.y;
+    cpu.flags = Flags::from_u8(initial.p);
+
+    cpu.step();
+
+    let expected = &case.expected;
+    // B and bit 5 only exist on the stack, the suites disagree on what the register holds
+    let status_bits = !0x30;
+    let registers = [
+        ("PC", expected.pc, cpu.program_counter),
+        ("S", expected.s as u16, cpu.stack_pointer as u16),
+        ("A", expected.a as u16, cpu.accumulator as u16),
+        ("X", expected.x as u16, cpu.idx as u16),
+        ("Y", expected.y as u16, cpu.idy as u16),
+        (
+            "P",
+            (expected.p & status_bits) as u16,
+            (cpu.flags.into_u8() & status_bits) as u16,
+        ),
+    ];
+    for (name, expected, actual) in registers {
+        if expected != actual {
+            return Err(Mismatch::Register {
+                name,
+                expected,
+                actual,
+            });
+        }
+    }
+    for &(address, expected) in &expected.ram {
+        let actual = cpu.bus.peek(address);
+        if expected != actual {
+            return Err(Mismatch::Memory {
+                address,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let expected: Vec<BusAccess> = case
+        .cycles
+        .iter()
+        .map(|(address, value, kind)| BusAccess {
+            address: *address,
+            value: *value,
+            kind: if kind == "write" {
+                AccessKind::Write
+            } else {
+                AccessKind::Read
+            },
+        })
+        .collect();
+    if expected != cpu.bus.accesses {
+        return Err(Mismatch::Cycles {
+            expected,
+            actual: cpu.bus.accesses,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASES: &str = r#"[
+        {
+            "name": "a9 42",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38, "ram": [[1024, 169], [1025, 66]] },
+            "final": { "pc": 1026, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36, "ram": [[1024, 169], [1025, 66]] },
+            "cycles": [[1024, 169, "read"], [1025, 66, "read"]]
+        },
+        {
+            "name": "8d 00 02",
+            "initial": { "pc": 1024, "s": 253, "a": 128, "x": 0, "y": 0, "p": 164, "ram": [[1024, 141], [1025, 0], [1026, 2], [512, 7]] },
+            "final": { "pc": 1027, "s": 253, "a": 128, "x": 0, "y": 0, "p": 164, "ram": [[512, 128]] },
+            "cycles": [[1024, 141, "read"], [1025, 0, "read"], [1026, 2, "read"], [512, 128, "write"]]
+        },
+        {
+            "name": "6c ff 10",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 108], [1025, 255], [1026, 16], [4351, 52], [4352, 18], [4096, 86]] },
+            "final": { "pc": 22068, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [] },
+            "cycles": [[1024, 108, "read"], [1025, 255, "read"], [1026, 16, "read"], [4351, 52, "read"], [4096, 86, "read"]]
+        },
+        {
+            "name": "ee 00 02",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 238], [1025, 0], [1026, 2], [512, 7]] },
+            "final": { "pc": 1027, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[512, 8]] },
+            "cycles": [[1024, 238, "read"], [1025, 0, "read"], [1026, 2, "read"], [512, 7, "read"], [512, 7, "write"], [512, 8, "write"]]
+        },
+        {
+            "name": "bd ff 02",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 1, "y": 0, "p": 36, "ram": [[1024, 189], [1025, 255], [1026, 2], [512, 17], [768, 34]] },
+            "final": { "pc": 1027, "s": 253, "a": 34, "x": 1, "y": 0, "p": 36, "ram": [] },
+            "cycles": [[1024, 189, "read"], [1025, 255, "read"], [1026, 2, "read"], [512, 17, "read"], [768, 34, "read"]]
+        },
+        {
+            "name": "20 00 05",
+            "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 32], [1025, 0], [1026, 5], [509, 9]] },
+            "final": { "pc": 1280, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[509, 4], [508, 2]] },
+            "cycles": [[1024, 32, "read"], [1025, 0, "read"], [509, 9, "read"], [509, 4, "write"], [508, 2, "write"], [1026, 5, "read"]]
+        },
+        {
+            "name": "60",
+            "initial": { "pc": 1024, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 96], [1025, 1], [508, 2], [509, 4], [1026, 3]] },
+            "final": { "pc": 1027, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [] },
+            "cycles": [[1024, 96, "read"], [1025, 1, "read"], [507, 0, "read"], [508, 2, "read"], [509, 4, "read"], [1026, 3, "read"]]
+        },
+        {
+            "name": "d0 20",
+            "initial": { "pc": 1264, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1264, 208], [1265, 32], [1266, 234], [1042, 0], [1298, 0]] },
+            "final": { "pc": 1298, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [] },
+            "cycles": [[1264, 208, "read"], [1265, 32, "read"], [1266, 234, "read"], [1042, 0, "read"]]
+        }
+    ]"#;
+
+    #[test]
+    fn passes_matching_cases() {
+        for case in load_tests(CASES).unwrap() {
+            assert_eq!(
+                run_test(&case, CpuVariant::Ricoh2A03),
+                Ok(()),
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let mut cases = load_tests(CASES).unwrap();
+        cases[0].expected.a = 0x43;
+        assert_eq!(
+            run_test(&cases[0], CpuVariant::Ricoh2A03),
+            Err(Mismatch::Register {
+                name: "A",
+                expected: 0x43,
+                actual: 0x42
+            })
+        );
+
+        cases[1].expected.ram[0].1 = 0x81;
+        assert!(matches!(
+            run_test(&cases[1], CpuVariant::Ricoh2A03),
+            Err(Mismatch::Memory {
+                address: 0x0200,
+                ..
+            })
+        ));
+
+        cases[2].cycles.pop();
+        assert!(matches!(
+            run_test(&cases[2], CpuVariant::Ricoh2A03),
+            Err(Mismatch::Cycles { .. })
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs the suites roms/fetch.sh downloads, run with `cargo test -- --ignored`"]
+    fn single_step_suite() {
+        let directory = crate::harness::suite_file("single-step");
+        let mut failures = Vec::new();
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("json".as_ref()) {
+                continue;
+            }
+            let cases = load_tests(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            // one failure per opcode is plenty to go on
+            if let Some((case, mismatch)) = cases.iter().find_map(|case| {
+                run_test(case, CpuVariant::Nmos6502)
+                    .err()
+                    .map(|mismatch| (case, mismatch))
+            }) {
+                failures.push(format!("{}: {}", case.name, mismatch));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
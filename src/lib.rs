@@ -0,0 +1,16 @@
+pub mod assembler;
+pub mod cartridge;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod harness;
+pub mod library;
+pub mod machine;
+pub mod memory;
+pub mod movie;
+pub mod nes;
+pub mod ppu;
+pub mod state;
+pub mod symbols;
+
+pub use library::isolate_bit_u8;
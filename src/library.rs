@@ -16,6 +16,19 @@ pub fn isolate_bit_u8(val: u8, offset: u8) -> u8 {
     (val >> offset) & 0x1
 }
 
+/// The CRC-32 used by zip and No-Intro to identify ROM images
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -33,6 +46,11 @@ mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    pub fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
     #[test]
     pub fn rotate_left() {
         let value: u8 = 0b10000000_u8.rotate_left(1);
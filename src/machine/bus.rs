@@ -0,0 +1,120 @@
+use crate::machine::timer::Timer;
+use crate::machine::uart::Uart;
+use crate::memory::bus::Bus;
+use crate::memory::memory::Memory;
+
+/// What an address is wired to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Unmapped,
+    Ram,
+    Rom,
+}
+
+/// The bus of a generic 6502 machine: RAM and ROM wherever the configuration put them, with
+/// the UART and timer registers taking precedence over both
+pub struct MachineBus {
+    pub memory: Memory,
+    map: Vec<Region>,
+    pub uart: Option<Uart>,
+    pub timer: Option<Timer>,
+    /// the last value read, which is what unmapped addresses give back
+    open_bus: u8,
+}
+
+impl MachineBus {
+    /// a bus with nothing on it
+    pub fn new() -> Self {
+        Self {
+            memory: Memory::new(),
+            map: vec![Region::Unmapped; 0x10000],
+            uart: None,
+            timer: None,
+            open_bus: 0,
+        }
+    }
+
+    pub fn region(&self, address: u16) -> Region {
+        self.map[address as usize]
+    }
+    pub fn map_ram(&mut self, start: u16, end: u16) {
+        self.map[start as usize..=end as usize].fill(Region::Ram);
+    }
+    /// puts `image` at `start`, as RAM when it's `writable`
+    pub fn map_rom(&mut self, start: u16, image: &[u8], writable: bool) -> Result<(), String> {
+        let end = start as usize + image.len();
+        if end > 0x10000 {
+            return Err(format!(
+                "a {} byte image at ${:04X} runs past $FFFF",
+                image.len(),
+                start
+            ));
+        }
+        self.memory.load(start, image);
+        let region = if writable { Region::Ram } else { Region::Rom };
+        self.map[start as usize..end].fill(region);
+        Ok(())
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(timer) = &mut self.timer {
+            timer.tick(cycles);
+        }
+    }
+}
+
+impl Default for MachineBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for MachineBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let device = match (&mut self.uart, &mut self.timer) {
+            (Some(uart), _) if uart.peek(address).is_some() => uart.read(address),
+            (_, Some(timer)) => timer.read(address),
+            _ => None,
+        };
+        let value = match (device, self.region(address)) {
+            (Some(value), _) => value,
+            (None, Region::Unmapped) => self.open_bus,
+            (None, Region::Ram | Region::Rom) => self.memory[address],
+        };
+        self.open_bus = value;
+        value
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        if let Some(uart) = &mut self.uart {
+            if uart.write(address, value) {
+                return;
+            }
+        }
+        if let Some(timer) = &mut self.timer {
+            if timer.write(address, value) {
+                return;
+            }
+        }
+        if self.region(address) == Region::Ram {
+            self.memory[address] = value;
+        }
+    }
+    fn on_cycle(&mut self) {
+        self.tick(1);
+    }
+    fn irq(&self) -> bool {
+        self.timer.as_ref().is_some_and(|timer| timer.pending)
+    }
+    fn peek(&self, address: u16) -> u8 {
+        let device = self
+            .uart
+            .as_ref()
+            .and_then(|uart| uart.peek(address))
+            .or_else(|| self.timer.as_ref().and_then(|timer| timer.peek(address)));
+        match (device, self.region(address)) {
+            (Some(value), _) => value,
+            (None, Region::Unmapped) => self.open_bus,
+            (None, Region::Ram | Region::Rom) => self.memory[address],
+        }
+    }
+}
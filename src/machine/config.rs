@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::cpu::cpu::CpuVariant;
+
+/// A 6502 single-board computer described in TOML, e.g.
+///
+/// ```toml
+/// cpu = "65c02"
+///
+/// [[ram]]
+/// start = 0x0000
+/// end = 0xBFFF
+///
+/// [[rom]]
+/// start = 0xC000
+/// file = "ehbasic.bin"
+///
+/// [uart]
+/// data = 0xF001
+/// status = 0xF004
+///
+/// [timer]
+/// address = 0xF010
+/// period = 20000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    /// `6502`, `2a03` or `65c02`
+    #[serde(default = "nmos", deserialize_with = "variant")]
+    pub cpu: CpuVariant,
+    /// where to start running instead of going through the reset vector
+    pub start: Option<u16>,
+    #[serde(default)]
+    pub ram: Vec<RamConfig>,
+    #[serde(default)]
+    pub rom: Vec<RomConfig>,
+    pub uart: Option<UartConfig>,
+    pub timer: Option<TimerConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RamConfig {
+    pub start: u16,
+    /// the last address, included
+    pub end: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomConfig {
+    pub start: u16,
+    /// relative to the configuration file
+    pub file: PathBuf,
+    /// loads the image into RAM instead, for test binaries that cover all 64K
+    #[serde(default)]
+    pub writable: bool,
+}
+
+/// A serial port with a data register, and optionally a status register with bit 0 set
+/// while input is waiting and bit 1 always set as the transmitter is never busy. Reading
+/// the data register with no input waiting gives 0
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UartConfig {
+    pub data: u16,
+    pub status: Option<u16>,
+    /// turns the LF that ends a line typed on stdin into the CR most 6502 monitors want
+    #[serde(default)]
+    pub input_cr: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimerConfig {
+    /// the control register, followed by the period's low and high bytes
+    pub address: u16,
+    /// cycles between interrupts
+    pub period: u16,
+}
+
+fn nmos() -> CpuVariant {
+    CpuVariant::Nmos6502
+}
+
+fn variant<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CpuVariant, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+impl MachineConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_machine() {
+        let config = MachineConfig::parse(
+            r#"
+            cpu = "65C02"
+            [[ram]]
+            start = 0x0000
+            end = 0x7FFF
+            [[rom]]
+            start = 0xC000
+            file = "basic.bin"
+            [uart]
+            data = 0xF001
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.cpu, CpuVariant::Cmos65C02);
+        assert_eq!(config.ram[0].end, 0x7FFF);
+        assert_eq!(config.rom[0].file, PathBuf::from("basic.bin"));
+        assert!(!config.rom[0].writable);
+        assert_eq!(config.uart.unwrap().status, None);
+        assert_eq!(config.timer, None);
+
+        assert_eq!(MachineConfig::parse("").unwrap().cpu, CpuVariant::Nmos6502);
+        assert!(MachineConfig::parse("cpu = \"z80\"").is_err());
+        assert!(MachineConfig::parse("[[ram]]\nstart = 0\nend = 0x10000").is_err());
+        assert!(MachineConfig::parse("[disk]").is_err());
+    }
+}
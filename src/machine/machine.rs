@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::cpu::Cpu;
+use crate::machine::bus::MachineBus;
+use crate::machine::config::MachineConfig;
+use crate::machine::timer::Timer;
+use crate::machine::uart::Uart;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MachineError {
+    /// the configuration doesn't parse, or describes a machine that can't be built
+    Config(String),
+    /// a file couldn't be read
+    Io { path: PathBuf, message: String },
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(message) => write!(f, "bad machine configuration: {}", message),
+            Self::Io { path, message } => {
+                write!(f, "couldn't read {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl Error for MachineError {}
+
+fn read(path: &Path) -> Result<Vec<u8>, MachineError> {
+    std::fs::read(path).map_err(|error| MachineError::Io {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    })
+}
+
+/// A generic 6502 computer built from a [`MachineConfig`], to run software that isn't for
+/// the NES headlessly, with its UART on the terminal
+pub struct Machine {
+    pub cpu: Cpu<MachineBus>,
+}
+
+impl Machine {
+    /// Builds the machine `config` describes, reading ROM images relative to `directory`,
+    /// and resets it
+    pub fn new(config: &MachineConfig, directory: &Path) -> Result<Self, MachineError> {
+        let mut bus = MachineBus::new();
+        for ram in &config.ram {
+            if ram.end < ram.start {
+                return Err(MachineError::Config(format!(
+                    "RAM at ${:04X} ends before it starts",
+                    ram.start
+                )));
+            }
+            bus.map_ram(ram.start, ram.end);
+        }
+        for rom in &config.rom {
+            let image = read(&directory.join(&rom.file))?;
+            bus.map_rom(rom.start, &image, rom.writable)
+                .map_err(MachineError::Config)?;
+        }
+        bus.uart = config.uart.clone().map(Uart::stdio);
+        bus.timer = config
+            .timer
+            .as_ref()
+            .map(|timer| Timer::new(timer.address, timer.period));
+
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.variant = config.cpu;
+        cpu.reset();
+        if let Some(start) = config.start {
+            cpu.program_counter = start;
+        }
+        Ok(Self { cpu })
+    }
+    /// reads a TOML configuration file, its ROM paths are relative to where it is
+    pub fn load(path: &Path) -> Result<Self, MachineError> {
+        let text = String::from_utf8_lossy(&read(path)?).into_owned();
+        let config = MachineConfig::parse(&text).map_err(MachineError::Config)?;
+        Self::new(&config, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Runs one instruction, then the interrupt it let in, and returns the cycles that took
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step() as u32
+    }
+    /// runs for `cycles` cycles or until the CPU stops with a JAM or STP
+    pub fn run(&mut self, cycles: u64) {
+        let end = self.cpu.cycles + cycles;
+        while self.cpu.cycles < end && !self.cpu.jammed {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use crate::asm;
+    use crate::cpu::cpu::CpuVariant;
+    use crate::machine::bus::Region;
+    use crate::memory::bus::Bus;
+
+    /// what the program sent to the UART
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_a_configured_machine() {
+        let directory = std::env::temp_dir().join(format!("machine-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let program = asm!(
+            CpuVariant::Cmos65C02,
+            "
+            .org $E000
+    reset:  LDX #0
+    print:  LDA hello,X
+            BEQ echo
+            STA $F001
+            INX
+            BNE print
+    echo:   LDA $F004
+            AND #1
+            BEQ wait
+            LDA $F001
+            STA $F001
+            JMP echo
+    wait:   CLI
+            LDA $02
+            CMP #3
+            BNE echo
+            STP
+    irq:    BIT $F010
+            INC $02
+            RTI
+    hello:  .byte \"hi\", 0
+            .org $FFFC
+            .word reset, irq
+            "
+        );
+        let mut rom = vec![0; 0x2000];
+        program.patch(&mut rom, 0xE000).unwrap();
+        std::fs::write(directory.join("rom.bin"), rom).unwrap();
+        let config = MachineConfig::parse(
+            r#"
+            cpu = "65c02"
+            [[ram]]
+            start = 0x0000
+            end = 0x7FFF
+            [[rom]]
+            start = 0xE000
+            file = "rom.bin"
+            [uart]
+            data = 0xF001
+            status = 0xF004
+            input_cr = true
+            [timer]
+            address = 0xF010
+            period = 1000
+            "#,
+        )
+        .unwrap();
+
+        let mut machine = Machine::new(&config, &directory).unwrap();
+        let output = Output::default();
+        let mut uart = Uart::new(config.uart.clone().unwrap(), Box::new(output.clone()));
+        uart.input(b"ok\n");
+        machine.cpu.bus.uart = Some(uart);
+        machine.run(10_000);
+
+        assert!(machine.cpu.jammed);
+        assert_eq!(machine.cpu.variant, CpuVariant::Cmos65C02);
+        assert_eq!(output.0.borrow().as_slice(), b"hiok\r");
+        assert_eq!(machine.cpu.peek_memory(0x0002), 3);
+        assert!(machine.cpu.cycles > 3000);
+
+        let bus = &mut machine.cpu.bus;
+        assert_eq!(bus.region(0x9000), Region::Unmapped);
+        bus.write(0xE000, 0x12);
+        assert_ne!(bus.peek(0xE000), 0x12);
+        assert_eq!(bus.peek(0xF004), Uart::TRANSMIT_READY);
+
+        let mut config = config;
+        config.rom[0].start = 0xF000;
+        assert!(matches!(
+            Machine::new(&config, &directory),
+            Err(MachineError::Config(_))
+        ));
+        config.rom[0].file = "missing.bin".into();
+        assert!(matches!(
+            Machine::new(&config, &directory),
+            Err(MachineError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod bus;
+pub mod config;
+#[allow(clippy::module_inception)]
+pub mod machine;
+pub mod timer;
+pub mod uart;
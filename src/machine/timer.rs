@@ -0,0 +1,71 @@
+/// A countdown that raises IRQ every `period` cycles. It has a control register, whose
+/// bit 0 runs the timer, and which reads back with bit 7 set while an interrupt is pending,
+/// acknowledging it. The two registers after it hold the period, low byte first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timer {
+    pub address: u16,
+    pub period: u16,
+    pub running: bool,
+    /// the IRQ line, held until the control register is read
+    pub pending: bool,
+    /// cycles since the last interrupt
+    counter: u32,
+}
+
+impl Timer {
+    pub const RUNNING: u8 = 0x01;
+    pub const PENDING: u8 = 0x80;
+
+    /// a timer that starts running at power-on
+    pub fn new(address: u16, period: u16) -> Self {
+        Self {
+            address,
+            period,
+            running: true,
+            pending: false,
+            counter: 0,
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.running || self.period == 0 {
+            return;
+        }
+        self.counter += cycles;
+        if self.counter >= self.period as u32 {
+            self.counter %= self.period as u32;
+            self.pending = true;
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> Option<u8> {
+        let value = self.peek(address)?;
+        if address == self.address {
+            self.pending = false;
+        }
+        Some(value)
+    }
+    /// false when `address` isn't one of the timer's
+    pub fn write(&mut self, address: u16, value: u8) -> bool {
+        let [lo, hi] = self.period.to_le_bytes();
+        match address.wrapping_sub(self.address) {
+            0 => {
+                self.running = value & Self::RUNNING != 0;
+                self.counter = 0;
+            }
+            1 => self.period = u16::from_le_bytes([value, hi]),
+            2 => self.period = u16::from_le_bytes([lo, value]),
+            _ => return false,
+        }
+        true
+    }
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        let [lo, hi] = self.period.to_le_bytes();
+        match address.wrapping_sub(self.address) {
+            0 => Some((self.pending as u8 * Self::PENDING) | (self.running as u8 * Self::RUNNING)),
+            1 => Some(lo),
+            2 => Some(hi),
+            _ => None,
+        }
+    }
+}
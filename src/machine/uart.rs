@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::machine::config::UartConfig;
+
+/// A memory-mapped serial port, see [`UartConfig`] for its registers
+pub struct Uart {
+    pub config: UartConfig,
+    /// bytes received and not read yet
+    input: VecDeque<u8>,
+    /// what a thread reading stdin sends, once the program first looks for input
+    stdin: Option<Receiver<u8>>,
+    reads_stdin: bool,
+    output: Box<dyn Write>,
+}
+
+impl Uart {
+    pub const RECEIVED: u8 = 0x01;
+    pub const TRANSMIT_READY: u8 = 0x02;
+
+    /// a port fed with `input` calls and writing to `output`
+    pub fn new(config: UartConfig, output: Box<dyn Write>) -> Self {
+        Self {
+            config,
+            input: VecDeque::new(),
+            stdin: None,
+            reads_stdin: false,
+            output,
+        }
+    }
+    /// a port wired to the terminal
+    pub fn stdio(config: UartConfig) -> Self {
+        let mut uart = Self::new(config, Box::new(io::stdout()));
+        uart.reads_stdin = true;
+        uart
+    }
+
+    /// queues bytes for the program to read
+    pub fn input(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let byte = if self.config.input_cr && byte == b'\n' {
+                b'\r'
+            } else {
+                byte
+            };
+            self.input.push_back(byte);
+        }
+    }
+    /// collects what was typed since the last look, stdin is read on a thread so the
+    /// program keeps running while nothing is
+    fn poll(&mut self) {
+        if self.reads_stdin && self.stdin.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    let sent = byte.ok().map(|byte| sender.send(byte));
+                    if !matches!(sent, Some(Ok(()))) {
+                        break;
+                    }
+                }
+            });
+            self.stdin = Some(receiver);
+        }
+        let received: Vec<u8> = match &self.stdin {
+            Some(receiver) => receiver.try_iter().collect(),
+            None => return,
+        };
+        self.input(&received);
+    }
+
+    pub fn read(&mut self, address: u16) -> Option<u8> {
+        if address == self.config.data {
+            self.poll();
+            Some(self.input.pop_front().unwrap_or(0))
+        } else if Some(address) == self.config.status {
+            self.poll();
+            self.peek(address)
+        } else {
+            None
+        }
+    }
+    /// false when `address` isn't one of the port's
+    pub fn write(&mut self, address: u16, value: u8) -> bool {
+        if address != self.config.data {
+            return Some(address) == self.config.status;
+        }
+        // a terminal that went away shouldn't stop the program
+        let _ = self.output.write_all(&[value]);
+        let _ = self.output.flush();
+        true
+    }
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        if address == self.config.data {
+            Some(self.input.front().copied().unwrap_or(0))
+        } else if Some(address) == self.config.status {
+            let received = if self.input.is_empty() {
+                0
+            } else {
+                Self::RECEIVED
+            };
+            Some(received | Self::TRANSMIT_READY)
+        } else {
+            None
+        }
+    }
+}
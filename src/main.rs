@@ -1,14 +1,320 @@
-mod cpu;
-mod library;
-mod memory;
-use cpu::cpu::Cpu;
-
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::io::{self, BufRead, Write};
 use std::time::Duration;
 
+use iron_cartridge::cartridge::battery::Battery;
+use iron_cartridge::cheats::cheats::Cheats;
+use iron_cartridge::debugger::console::{Console, Reply};
+use iron_cartridge::debugger::debugger::Stop;
+use iron_cartridge::debugger::gdb::GdbServer;
+use iron_cartridge::debugger::profiler::Profiler;
+use iron_cartridge::debugger::tracer::Tracer;
+use iron_cartridge::harness::desync::DesyncChecker;
+use iron_cartridge::machine::machine::Machine;
+use iron_cartridge::memory::memory::RamPattern;
+use iron_cartridge::movie::movie::{FrameInput, Movie};
+use iron_cartridge::nes::cdl::CodeDataLog;
+use iron_cartridge::nes::controller::Controller;
+use iron_cartridge::nes::nes::Nes;
+use iron_cartridge::ppu::viewer::Image;
+use iron_cartridge::state::rewind::Rewind;
+use iron_cartridge::symbols::symbols::SymbolTable;
+
+const SLOT_KEYS: [Keycode; 10] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+    Keycode::F10,
+];
+
+/// the keyboard layout of controller 1
+const PAD_KEYS: [(Scancode, u8); 8] = [
+    (Scancode::X, Controller::A),
+    (Scancode::Z, Controller::B),
+    (Scancode::RShift, Controller::SELECT),
+    (Scancode::Return, Controller::START),
+    (Scancode::Up, Controller::UP),
+    (Scancode::Down, Controller::DOWN),
+    (Scancode::Left, Controller::LEFT),
+    (Scancode::Right, Controller::RIGHT),
+];
+
+/// The PPU debug views, number keys 1-4 open them in windows of their own
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Patterns,
+    Nametables,
+    Sprites,
+    Palettes,
+}
+
+const VIEW_KEYS: [(Keycode, View); 4] = [
+    (Keycode::Num1, View::Patterns),
+    (Keycode::Num2, View::Nametables),
+    (Keycode::Num3, View::Sprites),
+    (Keycode::Num4, View::Palettes),
+];
+
+impl View {
+    fn name(self) -> &'static str {
+        match self {
+            View::Patterns => "patterns",
+            View::Nametables => "nametables",
+            View::Sprites => "sprites",
+            View::Palettes => "palettes",
+        }
+    }
+    fn render(self, nes: &Nes) -> Image {
+        let (ppu, mapper) = (&nes.cpu.bus.ppu, nes.cpu.bus.mapper.as_ref());
+        match self {
+            View::Patterns => ppu.pattern_tables(0, mapper),
+            View::Nametables => ppu.nametables(mapper),
+            View::Sprites => ppu.sprites(mapper),
+            View::Palettes => ppu.palettes(mapper),
+        }
+    }
+}
+
+fn show_view(canvas: &mut Canvas<Window>, image: &Image) {
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_static(
+            PixelFormatEnum::RGBA32,
+            image.width as u32,
+            image.height as u32,
+        )
+        .unwrap();
+    texture
+        .update(None, &image.pixels, image.width * 4)
+        .unwrap();
+    canvas.set_draw_color(Color::RGB(32, 32, 32));
+    canvas.clear();
+    canvas.copy(&texture, None, None).unwrap();
+    canvas.present();
+}
+
+/// `.fm2` files are FCEUX movies, anything else is our binary format
+fn load_movie(path: &str) -> Movie {
+    if path.ends_with(".fm2") {
+        Movie::parse_fm2(&std::fs::read_to_string(path).unwrap()).unwrap()
+    } else {
+        Movie::from_binary(&std::fs::read(path).unwrap()).unwrap()
+    }
+}
+fn save_movie(movie: &Movie, path: &str) {
+    let written = if path.ends_with(".fm2") {
+        std::fs::write(path, movie.to_fm2())
+    } else {
+        std::fs::write(path, movie.to_binary())
+    };
+    match written {
+        Ok(()) => println!("saved movie to {}", path),
+        Err(error) => println!("couldn't save {}: {}", path, error),
+    }
+}
+
+/// F1-F10 load a save state slot, with shift held they save to it instead
+fn save_slot(nes: &mut Nes, rom_path: &str, slot: usize, save: bool) {
+    let path = format!("{}.ss{}", rom_path, slot);
+    if save {
+        match std::fs::write(&path, nes.save_state()) {
+            Ok(()) => println!("saved state to {}", path),
+            Err(error) => println!("couldn't save {}: {}", path, error),
+        }
+    } else {
+        match std::fs::read(&path) {
+            Ok(state) => match nes.load_state(&state) {
+                Ok(()) => println!("loaded state from {}", path),
+                Err(error) => println!("couldn't load {}: {}", path, error),
+            },
+            Err(error) => println!("couldn't read {}: {}", path, error),
+        }
+    }
+}
+
+/// Reads debugger commands from stdin until one resumes emulation, false means quit
+fn debug_console(console: &mut Console, nes: &mut Nes) -> bool {
+    let stdin = io::stdin();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            return false;
+        }
+        match console.execute(&line, nes) {
+            Reply::Output(text) => println!("{}", text),
+            Reply::Resume => return true,
+            Reply::Quit => return false,
+        }
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|index| args.get(index + 1))
+    };
+    // `--ram random:1234` picks what RAM holds at power-on, zeros by default
+    let ram_pattern: RamPattern =
+        option("--ram").map_or(RamPattern::Zeros, |pattern| pattern.parse().unwrap());
+    let load_nes = |ram_pattern: RamPattern| {
+        args.get(1).map(|path| {
+            let rom = std::fs::read(path).unwrap();
+            Nes::from_ines(&rom).unwrap().with_ram_pattern(ram_pattern)
+        })
+    };
+    // `--machine board.toml` runs a generic 6502 machine headless, for `--cycles` or until it stops
+    if let Some(path) = option("--machine") {
+        let mut machine = Machine::load(std::path::Path::new(path)).unwrap();
+        let cycles: u64 = option("--cycles").map_or(u64::MAX, |cycles| cycles.parse().unwrap());
+        machine.run(cycles);
+        if machine.cpu.jammed {
+            println!("\nstopped at ${:04X}", machine.cpu.program_counter);
+        }
+        return;
+    }
+    let mut nes = load_nes(ram_pattern);
+    // `--dump-ppu dir` runs `--frames` frames without a window and saves the PPU views as PNGs
+    if let Some(directory) = option("--dump-ppu") {
+        let mut nes = nes.take().expect("--dump-ppu needs a ROM");
+        let frames: u64 = option("--frames").map_or(60, |frames| frames.parse().unwrap());
+        for _ in 0..frames {
+            nes.run_frame();
+        }
+        std::fs::create_dir_all(directory).unwrap();
+        for (_, view) in VIEW_KEYS {
+            let path = std::path::Path::new(directory).join(format!("{}.png", view.name()));
+            std::fs::write(&path, view.render(&nes).to_png()).unwrap();
+            println!("wrote {}", path.display());
+        }
+        return;
+    }
+    // `--desync movie.fm2` plays a movie on two machines at once and reports where they part,
+    // `--desync-ram ones` powers the right one on with other RAM than `--ram` gives the left
+    if let Some(path) = option("--desync") {
+        let right_pattern =
+            option("--desync-ram").map_or(ram_pattern, |pattern| pattern.parse().unwrap());
+        let mut checker = DesyncChecker::new(
+            nes.take().expect("--desync needs a ROM"),
+            load_nes(right_pattern).unwrap(),
+        );
+        match checker.run_movie(&load_movie(path)).unwrap() {
+            Ok(()) => println!("no desync in {} frames", checker.frame),
+            Err(desync) => println!("{}", desync),
+        }
+        return;
+    }
+    // `iron_cartridge game.nes --gdb 2345` waits for a GDB client instead of opening a window
+    if let Some(port) = option("--gdb") {
+        let port: u16 = port.parse().unwrap_or(2345);
+        let nes = nes.take().expect("--gdb needs a ROM");
+        println!("waiting for GDB on 127.0.0.1:{}", port);
+        GdbServer::new(nes).listen(("127.0.0.1", port)).unwrap();
+        return;
+    }
+    // `--record movie.fm2` records from power-on, `--play movie.fm2` plays one back
+    let recording = option("--record");
+    let mut movie = None;
+    if let Some(nes) = &mut nes {
+        if let Some(path) = option("--play") {
+            let played = load_movie(path);
+            played.begin(nes).unwrap();
+            movie = Some(played);
+        } else if recording.is_some() {
+            movie = Some(Movie::from_power_on(
+                nes,
+                args[1].rsplit('/').next().unwrap(),
+            ));
+        }
+    }
+    let mut movie_frame = 0;
+    let mut console = Console::new();
+    // labels come from `<rom>.dbg`, `<rom>.mlb` and `<rom>.nes.*.nl` next to the ROM, and `--symbols file`
+    if let (Some(nes), Some(rom)) = (&nes, args.get(1)) {
+        let prg_len = nes.cpu.bus.mapper.prg_rom().len();
+        let symbols = &mut console.debugger.symbols;
+        match SymbolTable::for_rom(std::path::Path::new(rom), prg_len) {
+            Ok(loaded) => symbols.merge(loaded),
+            Err(error) => println!("couldn't load symbols: {}", error),
+        }
+        if let Some(path) = option("--symbols") {
+            match SymbolTable::load(std::path::Path::new(path), prg_len) {
+                Ok(loaded) => symbols.merge(loaded),
+                Err(error) => println!("couldn't load {}: {}", path, error),
+            }
+        }
+        if !symbols.is_empty() {
+            println!("{} symbols", symbols.len());
+        }
+    }
+    if let Some(path) = option("--trace") {
+        let file = std::fs::File::create(path).unwrap();
+        console.debugger.tracer = Some(Tracer::new(Box::new(std::io::BufWriter::new(file))));
+    }
+    // `--profile file.json` also writes collapsed stacks next to it, as `file.folded`
+    let profile = option("--profile");
+    if profile.is_some() {
+        console.debugger.profiler = Some(Profiler::new());
+    }
+    // F12 pauses into the debugger console, which reads commands from the terminal
+    let mut paused = false;
+    // the frame a debugger stop broke into, resuming finishes it before taking new input
+    let mut mid_frame = None;
+    // holding backspace rewinds, a snapshot every 10 frames within 256 MiB
+    let mut rewind = Rewind::new(10, 256 << 20);
+    // battery saves live in `<rom>.sav`, movies start from a blank cartridge so they stay in sync
+    let mut battery = args.get(1).map(Battery::for_rom);
+    if let (Some(nes), Some(battery)) = (&mut nes, &mut battery) {
+        if movie.is_none() {
+            match battery.load(nes) {
+                Ok(true) => println!("loaded {}", battery.path.display()),
+                Ok(false) => {}
+                Err(error) => println!("couldn't load {}: {}", battery.path.display(), error),
+            }
+        }
+    }
+    // cheats come from `<rom>.cht`, F11 switches them all on and off
+    let cheats = args.get(1).map_or_else(Cheats::new, |path| {
+        let path = std::path::Path::new(path).with_extension("cht");
+        match std::fs::read_to_string(&path).map(|text| Cheats::parse(&text)) {
+            Ok(Ok(cheats)) => {
+                println!(
+                    "loaded {} cheats from {}",
+                    cheats.cheats.len(),
+                    path.display()
+                );
+                cheats
+            }
+            Ok(Err(error)) => {
+                println!("couldn't load {}: {}", path.display(), error);
+                Cheats::new()
+            }
+            Err(_) => Cheats::new(),
+        }
+    });
+    let mut cheats_enabled = true;
+    // `--cdl file` logs which ROM bytes are code and data, adding to the file if it's there
+    let cdl = option("--cdl");
+    if let (Some(nes), Some(path)) = (&mut nes, cdl) {
+        let bus = &mut nes.cpu.bus;
+        bus.cdl = Some(match std::fs::read(path) {
+            Ok(bytes) => CodeDataLog::from_bytes(&bytes, bus.mapper.as_ref()).unwrap(),
+            Err(_) => CodeDataLog::new(bus.mapper.as_ref()),
+        });
+    }
  let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -17,7 +323,9 @@ fn main() {
         .build()
         .unwrap();
 
+    let main_window = window.id();
     let mut canvas = window.into_canvas().build().unwrap();
+    let mut views: Vec<(View, Canvas<Window>)> = Vec::new();
 
     canvas.set_draw_color(Color::RGB(0, 255, 255));
     canvas.clear();
@@ -34,12 +342,164 @@ fn main() {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running
                 },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => paused = true,
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    if window_id == main_window {
+                        break 'running;
+                    }
+                    views.retain(|(_, canvas)| canvas.window().id() != window_id);
+                }
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } if VIEW_KEYS.iter().any(|(view_key, _)| *view_key == key) => {
+                    let view = VIEW_KEYS
+                        .iter()
+                        .find(|(view_key, _)| *view_key == key)
+                        .unwrap()
+                        .1;
+                    if let Some(index) = views.iter().position(|(open, _)| *open == view) {
+                        views.remove(index);
+                    } else if let Some(nes) = &nes {
+                        let image = view.render(nes);
+                        let window = video_subsystem
+                            .window(view.name(), image.width as u32 * 2, image.height as u32 * 2)
+                            .build()
+                            .unwrap();
+                        views.push((view, window.into_canvas().build().unwrap()));
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    cheats_enabled = !cheats_enabled;
+                    println!("cheats {}", if cheats_enabled { "on" } else { "off" });
+                }
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    ..
+                } if SLOT_KEYS.contains(&key) => {
+                    if let (Some(nes), Some(path)) = (&mut nes, args.get(1)) {
+                        let slot = SLOT_KEYS.iter().position(|slot| *slot == key).unwrap() + 1;
+                        save_slot(
+                            nes,
+                            path,
+                            slot,
+                            keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+                        );
+                    }
+                }
                 _ => {}
             }
         }
-        // The rest of the game loop goes here...
+        if let Some(nes) = &mut nes {
+            if paused {
+                println!("{}", console.stopped(Stop::Stepped, nes));
+                if !debug_console(&mut console, nes) {
+                    break 'running;
+                }
+                paused = false;
+            } else if event_pump
+                .keyboard_state()
+                .is_scancode_pressed(Scancode::Backspace)
+            {
+                mid_frame = None;
+                // a movie rewinds with the machine, recording drops the frames undone
+                if rewind.step_back(nes) {
+                    movie_frame = rewind.frame() as usize;
+                    if let (Some(movie), Some(_)) = (&mut movie, recording) {
+                        movie.frames.truncate(movie_frame);
+                    }
+                }
+            } else {
+                let frame = match mid_frame.take() {
+                    Some(frame) => frame,
+                    None => {
+                        let keyboard = event_pump.keyboard_state();
+                        let mut input = FrameInput::default();
+                        for (key, button) in PAD_KEYS {
+                            if keyboard.is_scancode_pressed(key) {
+                                input.pads[0] |= button;
+                            }
+                        }
+                        if let Some(movie) = &mut movie {
+                            if recording.is_some() {
+                                movie.frames.push(input);
+                            } else if let Some(played) = movie.frames.get(movie_frame) {
+                                input = *played;
+                            }
+                            movie_frame += 1;
+                        }
+                        rewind.record(nes, &input);
+                        input.apply(nes);
+                        if cheats_enabled {
+                            cheats.apply(nes);
+                        } else {
+                            nes.cpu.bus.patches.clear();
+                        }
+                        nes.cpu.bus.ppu.frame
+                    }
+                };
+                while nes.cpu.bus.ppu.frame == frame {
+                    if let Some(stop) = console.debugger.step(nes) {
+                        println!("{}", stop);
+                        paused = true;
+                        mid_frame = Some(frame);
+                        break;
+                    }
+                }
+                // a played movie with checkpoints tells when playback stops matching the recording
+                if let (Some(movie), None) = (&movie, recording) {
+                    if mid_frame.is_none() && movie_frame <= movie.frames.len() {
+                        if let Err(error) = movie.check(movie_frame - 1, nes) {
+                            println!("movie: {}", error);
+                        }
+                    }
+                }
+                if let Some(battery) = &mut battery {
+                    if let Err(error) = battery.tick(nes) {
+                        println!("couldn't save {}: {}", battery.path.display(), error);
+                    }
+                }
+            }
+        }
 
         canvas.present();
+        if let Some(nes) = &nes {
+            for (view, canvas) in &mut views {
+                show_view(canvas, &view.render(nes));
+            }
+        }
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
+    if let (Some(movie), Some(path)) = (&movie, recording) {
+        save_movie(movie, path);
+    }
+    if let (Some(nes), Some(battery)) = (&nes, &mut battery) {
+        if let Err(error) = battery.flush(nes) {
+            println!("couldn't save {}: {}", battery.path.display(), error);
+        }
+    }
+    if let (Some(profiler), Some(path)) = (&console.debugger.profiler, profile) {
+        let symbols = &console.debugger.symbols;
+        std::fs::write(path, profiler.to_json(symbols).to_string()).unwrap();
+        std::fs::write(
+            std::path::Path::new(path).with_extension("folded"),
+            profiler.total.collapsed(symbols),
+        )
+        .unwrap();
+        print!("{}", profiler.total.flat_report(symbols, 16));
+    }
+    if let (Some(log), Some(path)) = (nes.as_ref().and_then(|nes| nes.cpu.bus.cdl.as_ref()), cdl) {
+        std::fs::write(path, log.to_bytes()).unwrap();
+        println!("{}", log);
+    }
 }
@@ -0,0 +1,41 @@
+use crate::memory::memory::Memory;
+
+/// Everything the CPU can be wired to, from flat test memory to a whole console
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// reads an address without any side effects, for tracing and debugging
+    fn peek(&self, address: u16) -> u8;
+    /// a read the CPU makes while busy with something else and throws the value of away. It's
+    /// still a real access with all of a read's side effects
+    fn dummy_read(&mut self, address: u16) -> u8 {
+        self.read(address)
+    }
+    /// called after every CPU cycle, each of which is exactly one read or write, so the rest
+    /// of the system can run alongside the CPU
+    fn on_cycle(&mut self) {}
+    /// whether the NMI line went active since the last call, NMI being edge triggered
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+    /// whether a device is holding the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+    /// where in cartridge PRG-ROM an address is mapped right now, for symbols of banked code
+    fn prg_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: u16) -> u8 {
+        self[address]
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        self[address] = value;
+    }
+    fn peek(&self, address: u16) -> u8 {
+        self[address]
+    }
+}
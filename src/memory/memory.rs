@@ -1,11 +1,78 @@
 use std::ops::{Index, IndexMut};
+use std::str::FromStr;
 
 pub struct Memory(Box<[u8; 65536]>);
 
+/// What RAM holds at power-on. Real consoles land somewhere between these, so games that
+/// read RAM before writing it need one picked to behave the same on every run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamPattern {
+    #[default]
+    Zeros,
+    Ones,
+    /// noise from a seeded generator, the same seed always gives the same bytes
+    Random(u64),
+}
+
+impl FromStr for RamPattern {
+    type Err = String;
+
+    /// `zeros`, `ones` or `random:<seed>`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "zeros" => Ok(Self::Zeros),
+            "ones" => Ok(Self::Ones),
+            _ => text
+                .strip_prefix("random:")
+                .and_then(|seed| seed.parse().ok())
+                .map(Self::Random)
+                .ok_or_else(|| format!("expected zeros, ones or random:<seed>, got `{}`", text)),
+        }
+    }
+}
+
+impl RamPattern {
+    pub fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            Self::Zeros => ram.fill(0),
+            Self::Ones => ram.fill(0xFF),
+            Self::Random(seed) => {
+                // splitmix64
+                let mut state = seed;
+                for chunk in ram.chunks_mut(8) {
+                    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut value = state;
+                    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    value ^= value >> 31;
+                    chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
 impl Memory {
     pub fn new() -> Self {
         Self(Box::new([0; 65536]))
     }
+    pub fn with_pattern(pattern: RamPattern) -> Self {
+        let mut memory = Self::new();
+        pattern.fill(memory.0.as_mut_slice());
+        memory
+    }
+    /// copies `bytes` into memory starting at `start`, wrapping around the end of the address space
+    pub fn load(&mut self, start: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self[start.wrapping_add(offset as u16)] = *byte;
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Index<u16> for Memory {
@@ -20,3 +87,24 @@ impl IndexMut<u16> for Memory {
         &mut self.0[index as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let mut ram = [0x55; 11];
+        RamPattern::Ones.fill(&mut ram);
+        assert_eq!(ram, [0xFF; 11]);
+        RamPattern::Random(42).fill(&mut ram);
+        let first = ram;
+        RamPattern::Random(42).fill(&mut ram);
+        assert_eq!(ram, first);
+        RamPattern::Random(43).fill(&mut ram);
+        assert_ne!(ram, first);
+        assert_eq!(Memory::with_pattern(RamPattern::Ones)[0xFFFF], 0xFF);
+        assert_eq!("random:42".parse(), Ok(RamPattern::Random(42)));
+        assert!("random".parse::<RamPattern>().is_err());
+    }
+}
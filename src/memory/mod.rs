@@ -1 +1,4 @@
+pub mod bus;
+#[allow(clippy::module_inception)]
 pub mod memory;
+pub mod recording;
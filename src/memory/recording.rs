@@ -0,0 +1,75 @@
+use crate::memory::bus::Bus;
+use crate::memory::memory::Memory;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// One bus cycle as seen from outside the CPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+/// Wraps another bus and logs every read and write going through it, peeks aren't logged
+pub struct RecordingBus<B: Bus = Memory> {
+    pub inner: B,
+    pub accesses: Vec<BusAccess>,
+}
+
+impl<B: Bus> RecordingBus<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            accesses: Vec::new(),
+        }
+    }
+}
+
+impl<B: Bus> Bus for RecordingBus<B> {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.inner.read(address);
+        self.accesses.push(BusAccess {
+            address,
+            value,
+            kind: AccessKind::Read,
+        });
+        value
+    }
+    fn dummy_read(&mut self, address: u16) -> u8 {
+        let value = self.inner.dummy_read(address);
+        self.accesses.push(BusAccess {
+            address,
+            value,
+            kind: AccessKind::Read,
+        });
+        value
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        self.inner.write(address, value);
+        self.accesses.push(BusAccess {
+            address,
+            value,
+            kind: AccessKind::Write,
+        });
+    }
+    fn peek(&self, address: u16) -> u8 {
+        self.inner.peek(address)
+    }
+    fn on_cycle(&mut self) {
+        self.inner.on_cycle();
+    }
+    fn take_nmi(&mut self) -> bool {
+        self.inner.take_nmi()
+    }
+    fn irq(&self) -> bool {
+        self.inner.irq()
+    }
+    fn prg_offset(&self, address: u16) -> Option<usize> {
+        self.inner.prg_offset(address)
+    }
+}
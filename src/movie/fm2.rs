@@ -0,0 +1,223 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::movie::movie::{FrameInput, Movie, MovieError};
+
+/// the buttons of a gamepad field from its leftmost character to its rightmost, as bit numbers
+/// of `Controller::buttons`: `RLDUTSBA`
+const BUTTON_BITS: [u8; 8] = [7, 6, 5, 4, 3, 2, 1, 0];
+const BUTTON_NAMES: &str = "RLDUTSBA";
+/// what a `portN` header says is plugged in
+const PORT_NONE: u8 = 0;
+const PORT_GAMEPAD: u8 = 1;
+
+impl Movie {
+    /// Writes the movie as an FCEUX FM2 file. A movie that starts from a save state gets a
+    /// `savestate` line holding our own state format, which FCEUX can't load
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+        let mut header = |key: &str, value: String| {
+            text.push_str(&format!("{} {}\n", key, value));
+        };
+        header("version", "3".to_string());
+        header("emuVersion", "22020".to_string());
+        header("rerecordCount", self.rerecords.to_string());
+        header("palFlag", "0".to_string());
+        header("romFilename", self.rom_filename.clone());
+        header(
+            "romChecksum",
+            format!("base64:{}", STANDARD.encode(self.rom_md5)),
+        );
+        header("guid", guid(&self.rom_md5, self.frames.len()));
+        header("fourscore", "0".to_string());
+        header("microphone", "0".to_string());
+        header("port0", PORT_GAMEPAD.to_string());
+        header("port1", PORT_GAMEPAD.to_string());
+        header("port2", "0".to_string());
+        header("FDS", "0".to_string());
+        header("NewPPU", "0".to_string());
+        if let Some(state) = &self.start {
+            header("savestate", format!("base64:{}", STANDARD.encode(state)));
+        }
+        for input in &self.frames {
+            text.push_str(&format!(
+                "|{}|{}|{}||\n",
+                input.commands,
+                gamepad(input.pads[0]),
+                gamepad(input.pads[1])
+            ));
+        }
+        text
+    }
+
+    /// Reads an FM2 file, only standard gamepads are supported in the ports
+    pub fn parse_fm2(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Self::default();
+        let mut ports = [PORT_GAMEPAD, PORT_GAMEPAD];
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| MovieError::Parse {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('|') {
+                movie.frames.push(parse_input(line, ports).map_err(error)?);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" if value != "3" => {
+                    return Err(error(format!("unsupported FM2 version {}", value)))
+                }
+                "rerecordCount" => {
+                    movie.rerecords = value
+                        .parse()
+                        .map_err(|_| error(format!("bad rerecord count `{}`", value)))?
+                }
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let md5 = decode_base64(value).map_err(error)?;
+                    movie.rom_md5 = md5
+                        .try_into()
+                        .map_err(|_| error("the ROM checksum isn't an MD5".to_string()))?;
+                }
+                "savestate" => movie.start = Some(decode_base64(value).map_err(error)?),
+                "port0" | "port1" => {
+                    let port = match value {
+                        "0" => PORT_NONE,
+                        "1" => PORT_GAMEPAD,
+                        _ => return Err(error(format!("unsupported {} device {}", key, value))),
+                    };
+                    ports[(key == "port1") as usize] = port;
+                }
+                "fourscore" if value != "0" => {
+                    return Err(error("four score movies aren't supported".to_string()))
+                }
+                "binary" if value != "0" => {
+                    return Err(error("binary FM2 input isn't supported".to_string()))
+                }
+                "palFlag" if value != "0" => {
+                    return Err(error("PAL movies aren't supported".to_string()))
+                }
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+}
+
+fn gamepad(buttons: u8) -> String {
+    BUTTON_NAMES
+        .chars()
+        .zip(BUTTON_BITS)
+        .map(|(name, bit)| if buttons & 1 << bit != 0 { name } else { '.' })
+        .collect()
+}
+
+/// `|commands|port0|port1|port2|`, a port without a device has an empty field
+fn parse_input(line: &str, ports: [u8; 2]) -> Result<FrameInput, String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 5 {
+        return Err(format!(
+            "expected `|commands|port0|port1|port2|`, got `{}`",
+            line
+        ));
+    }
+    let commands = fields[1]
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad commands `{}`", fields[1]))?;
+    let mut input = FrameInput {
+        commands,
+        pads: [0; 2],
+    };
+    for (port, field) in fields[2..4].iter().enumerate() {
+        if ports[port] == PORT_NONE {
+            continue;
+        }
+        if field.chars().count() != 8 {
+            return Err(format!("bad gamepad `{}`", field));
+        }
+        for (name, bit) in field.chars().zip(BUTTON_BITS) {
+            if name != '.' && name != ' ' {
+                input.pads[port] |= 1 << bit;
+            }
+        }
+    }
+    Ok(input)
+}
+
+/// FM2 binary values are `base64:` prefixed, FCEUX also takes plain hex with a `0x` prefix
+fn decode_base64(value: &str) -> Result<Vec<u8>, String> {
+    if let Some(encoded) = value.strip_prefix("base64:") {
+        STANDARD
+            .decode(encoded)
+            .map_err(|error| format!("bad base64: {}", error))
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| {
+                hex.get(index..index + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| format!("bad hex `{}`", value))
+            })
+            .collect()
+    } else {
+        Err(format!("expected a base64: value, got `{}`", value))
+    }
+}
+
+/// FCEUX wants a GUID per movie, ours is derived from the ROM and length so output is stable
+fn guid(md5: &[u8; 16], frames: usize) -> String {
+    let mut bytes = *md5;
+    for (byte, length) in bytes.iter_mut().zip((frames as u64).to_le_bytes()) {
+        *byte ^= length;
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movie::movie::tests::{joypad_nes, sample_movie};
+
+    #[test]
+    fn fm2_round_trip() {
+        let nes = joypad_nes();
+        let mut movie = sample_movie(&nes);
+        movie.rerecords = 12;
+        let text = movie.to_fm2();
+        assert!(text.contains("\n|0|R......A|....T...||\n"));
+        assert!(text.contains("\n|1|........|........||\n"));
+        assert_eq!(Movie::parse_fm2(&text), Ok(movie));
+    }
+
+    #[test]
+    fn parses_fceux_movies() {
+        let text = "version 3\nemuVersion 22020\nrerecordCount 3\npalFlag 0\n\
+                    romFilename smb\nromChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+                    port0 1\nport1 0\nport2 0\n\
+                    |2|........|||\n|0|RL..TSBA|||\n|0|...U....|||\n";
+        let movie = Movie::parse_fm2(text).unwrap();
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.rom_md5[0], 0x8E);
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[0].commands, FrameInput::POWER);
+        assert_eq!(movie.frames[1].pads, [0b1100_1111, 0]);
+        assert_eq!(movie.frames[2].pads, [0b0001_0000, 0]);
+
+        let error = Movie::parse_fm2("version 3\n|0|bad|||\n").unwrap_err();
+        assert!(matches!(error, MovieError::Parse { line: 2, .. }));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod fm2;
+#[allow(clippy::module_inception)]
+pub mod movie;
@@ -0,0 +1,434 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::library;
+use crate::nes::nes::Nes;
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// a line of an FM2 file that couldn't be understood
+    Parse { line: usize, message: String },
+    /// not a binary movie
+    BadMagic,
+    /// a binary movie from another version of the format
+    UnsupportedVersion { found: u16, expected: u16 },
+    /// the binary movie or the save state it starts from is unreadable
+    State(StateError),
+    /// a binary movie claims more frames than `Movie::MAX_FRAMES`
+    TooLong { frames: u64 },
+    /// the movie was made with another game, by the MD5s of the two ROMs
+    RomMismatch {
+        expected: [u8; 16],
+        actual: [u8; 16],
+    },
+    /// playback no longer matches the checkpoint taken after `frame` when the movie was made
+    Desync {
+        frame: usize,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Self::BadMagic => write!(f, "not a movie"),
+            Self::UnsupportedVersion { found, expected } => write!(
+                f,
+                "movie version {} is not supported, expected version {}",
+                found, expected
+            ),
+            Self::State(error) => write!(f, "{}", error),
+            Self::TooLong { frames } => write!(
+                f,
+                "movie has {} frames, more than the {} supported",
+                frames,
+                Movie::MAX_FRAMES
+            ),
+            Self::RomMismatch { expected, actual } => {
+                let hex = |md5: &[u8; 16]| -> String {
+                    md5.iter().map(|byte| format!("{:02x}", byte)).collect()
+                };
+                write!(
+                    f,
+                    "movie is for the ROM with MD5 {}, not {}",
+                    hex(expected),
+                    hex(actual)
+                )
+            }
+            Self::Desync {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "desync after frame {}: expected checkpoint {:08X}, got {:08X}",
+                frame, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> Self {
+        Self::State(error)
+    }
+}
+
+/// What the player did during one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInput {
+    /// `SOFT_RESET` and `POWER`, pressed before the frame runs
+    pub commands: u8,
+    /// the buttons held on each controller, laid out like `Controller::buttons`
+    pub pads: [u8; 2],
+}
+
+impl FrameInput {
+    pub const SOFT_RESET: u8 = 1 << 0;
+    pub const POWER: u8 = 1 << 1;
+
+    /// presses the console buttons and holds the controller buttons for the coming frame
+    pub fn apply(&self, nes: &mut Nes) {
+        if self.commands & Self::POWER != 0 {
+            nes.power();
+        } else if self.commands & Self::SOFT_RESET != 0 {
+            nes.reset();
+        }
+        for (controller, buttons) in nes.cpu.bus.controllers.iter_mut().zip(self.pads) {
+            controller.buttons = buttons;
+        }
+    }
+}
+
+/// Recorded input, frame by frame, from power-on or from a save state.
+/// Stored as FCEUX's FM2 text or as our compact binary format, which also keeps checkpoints:
+/// hashes of the machine taken every `checkpoint_interval` frames, so replaying it doubles as
+/// a regression test. The PPU doesn't render yet, so they hash the memories a frame is drawn
+/// from, and the RAM the game keeps its state in, rather than a framebuffer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_filename: String,
+    /// MD5 of the ROM's PRG and CHR data
+    pub rom_md5: [u8; 16],
+    /// the save state the movie starts from, it starts from power-on without one
+    pub start: Option<Vec<u8>>,
+    pub frames: Vec<FrameInput>,
+    pub rerecords: u32,
+    pub checkpoint_interval: usize,
+    /// the hash after every `checkpoint_interval`th frame
+    pub checkpoints: Vec<u32>,
+}
+
+impl Movie {
+    const MAGIC: [u8; 4] = *b"ICMV";
+    const VERSION: u16 = 1;
+    /// a day at 60 frames a second, binary movies claiming more are rejected
+    pub const MAX_FRAMES: u64 = 60 * 60 * 60 * 24;
+
+    /// an empty movie to record into, starting from power-on
+    pub fn from_power_on(nes: &Nes, rom_filename: &str) -> Self {
+        Self {
+            rom_filename: rom_filename.to_string(),
+            rom_md5: nes.rom_md5,
+            ..Self::default()
+        }
+    }
+    /// an empty movie to record into, starting from wherever the machine is now
+    pub fn from_state(nes: &Nes, rom_filename: &str) -> Self {
+        Self {
+            start: Some(nes.save_state()),
+            ..Self::from_power_on(nes, rom_filename)
+        }
+    }
+
+    /// the hash checkpoints are made of: CPU RAM, cartridge RAM, VRAM, palette and OAM, which
+    /// unlike a save state don't change when the state format does
+    pub fn checkpoint(nes: &Nes) -> u32 {
+        let bus = &nes.cpu.bus;
+        let mut bytes = bus.ram.to_vec();
+        bytes.extend_from_slice(bus.mapper.prg_ram().unwrap_or_default());
+        bytes.extend_from_slice(&bus.ppu.vram);
+        bytes.extend_from_slice(&bus.ppu.palette);
+        bytes.extend_from_slice(&bus.ppu.oam);
+        library::crc32(&bytes)
+    }
+    /// Compares the machine with the checkpoint due after `frame`, if there is one
+    pub fn check(&self, frame: usize, nes: &Nes) -> Result<(), MovieError> {
+        if self.checkpoint_interval == 0 || !(frame + 1).is_multiple_of(self.checkpoint_interval) {
+            return Ok(());
+        }
+        let index = (frame + 1) / self.checkpoint_interval - 1;
+        let actual = Self::checkpoint(nes);
+        match self.checkpoints.get(index) {
+            Some(&expected) if expected != actual => Err(MovieError::Desync {
+                frame,
+                expected,
+                actual,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts the machine where the movie starts, it has to be running the movie's game
+    pub fn begin(&self, nes: &mut Nes) -> Result<(), MovieError> {
+        if self.rom_md5 != nes.rom_md5 {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_md5,
+                actual: nes.rom_md5,
+            });
+        }
+        match &self.start {
+            Some(state) => nes.load_state(state)?,
+            None => nes.power(),
+        }
+        Ok(())
+    }
+    /// Plays the whole movie from its start, calling `each` with the index of every frame once it ran
+    pub fn play(&self, nes: &mut Nes, mut each: impl FnMut(usize, &Nes)) -> Result<(), MovieError> {
+        self.begin(nes)?;
+        for (frame, input) in self.frames.iter().enumerate() {
+            input.apply(nes);
+            nes.run_frame();
+            each(frame, nes);
+        }
+        Ok(())
+    }
+    /// Plays the movie and stores a checkpoint every `interval` frames
+    pub fn add_checkpoints(&mut self, nes: &mut Nes, interval: usize) -> Result<(), MovieError> {
+        let interval = interval.max(1);
+        let mut checkpoints = Vec::new();
+        self.play(nes, |frame, nes| {
+            if (frame + 1).is_multiple_of(interval) {
+                checkpoints.push(Self::checkpoint(nes));
+            }
+        })?;
+        self.checkpoint_interval = interval;
+        self.checkpoints = checkpoints;
+        Ok(())
+    }
+    /// Plays the movie and fails at the first checkpoint that doesn't match
+    pub fn verify(&self, nes: &mut Nes) -> Result<(), MovieError> {
+        let mut desync = None;
+        self.play(nes, |frame, nes| {
+            if desync.is_none() {
+                desync = self.check(frame, nes).err();
+            }
+        })?;
+        desync.map_or(Ok(()), Err)
+    }
+
+    /// The compact binary format: a header, then the input as runs of identical frames
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes.extend_from_slice(&Self::MAGIC);
+        writer.u16(Self::VERSION);
+        writer.bytes.extend_from_slice(&self.rom_md5);
+        writer.block(self.rom_filename.as_bytes());
+        writer.u32(self.rerecords);
+        writer.bool(self.start.is_some());
+        if let Some(state) = &self.start {
+            writer.block(state);
+        }
+        writer.u32(self.checkpoint_interval as u32);
+        writer.u32(self.checkpoints.len() as u32);
+        for checkpoint in &self.checkpoints {
+            writer.u32(*checkpoint);
+        }
+        let runs: Vec<(u32, FrameInput)> =
+            self.frames.iter().fold(Vec::new(), |mut runs, input| {
+                match runs.last_mut() {
+                    Some((count, last)) if last == input => *count += 1,
+                    _ => runs.push((1, *input)),
+                }
+                runs
+            });
+        writer.u32(runs.len() as u32);
+        for (count, input) in runs {
+            writer.u32(count);
+            writer.u8(input.commands);
+            writer.u8(input.pads[0]);
+            writer.u8(input.pads[1]);
+        }
+        writer.bytes
+    }
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = StateReader::new(bytes);
+        for byte in Self::MAGIC {
+            if reader.u8().map_err(|_| MovieError::BadMagic)? != byte {
+                return Err(MovieError::BadMagic);
+            }
+        }
+        let version = reader.u16()?;
+        if version != Self::VERSION {
+            return Err(MovieError::UnsupportedVersion {
+                found: version,
+                expected: Self::VERSION,
+            });
+        }
+        let mut movie = Self::default();
+        for byte in &mut movie.rom_md5 {
+            *byte = reader.u8()?;
+        }
+        movie.rom_filename = String::from_utf8_lossy(reader.block()?).into_owned();
+        movie.rerecords = reader.u32()?;
+        if reader.bool()? {
+            movie.start = Some(reader.block()?.to_vec());
+        }
+        movie.checkpoint_interval = reader.u32()? as usize;
+        for _ in 0..reader.u32()? {
+            movie.checkpoints.push(reader.u32()?);
+        }
+        let mut frames = 0;
+        for _ in 0..reader.u32()? {
+            let count = reader.u32()?;
+            frames += count as u64;
+            if frames > Self::MAX_FRAMES {
+                return Err(MovieError::TooLong { frames });
+            }
+            let input = FrameInput {
+                commands: reader.u8()?,
+                pads: [reader.u8()?, reader.u8()?],
+            };
+            movie
+                .frames
+                .extend(std::iter::repeat_n(input, count as usize));
+        }
+        Ok(movie)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::cartridge::tests::nrom;
+    use crate::nes::controller::Controller;
+
+    /// reads controller 1 every NMI and adds the buttons into $00-$01,
+    /// the frame count since reset goes to $02
+    pub(crate) fn joypad_nes() -> Nes {
+        let program = asm!(
+            "
+            .org $8000
+    reset:  LDA #$80
+            STA $2000
+    idle:   JMP idle
+    nmi:    INC $02
+            LDA #1
+            STA $4016
+            LDA #0
+            STA $4016
+            LDX #8
+    read:   LDA $4016
+            LSR A
+            ROL $03
+            DEX
+            BNE read
+            CLC
+            LDA $03
+            ADC $00
+            STA $00
+            LDA #0
+            ADC $01
+            STA $01
+            RTI
+            .org $FFFA
+            .word nmi, reset, reset
+            "
+        );
+        Nes::from_ines(&nrom(&program)).unwrap()
+    }
+
+    pub(crate) fn sample_movie(nes: &Nes) -> Movie {
+        let mut movie = Movie::from_power_on(nes, "joypad.nes");
+        for frame in 0..40u8 {
+            let mut input = FrameInput::default();
+            if frame % 3 == 0 {
+                input.pads[0] = Controller::A | Controller::RIGHT;
+            }
+            if frame % 7 == 0 {
+                input.pads[1] = Controller::START;
+            }
+            if frame == 20 {
+                input.commands = FrameInput::SOFT_RESET;
+            }
+            movie.frames.push(input);
+        }
+        movie
+    }
+
+    #[test]
+    fn playback_is_deterministic() {
+        let mut nes = joypad_nes();
+        let mut movie = sample_movie(&nes);
+        movie.add_checkpoints(&mut nes, 10).unwrap();
+        assert_eq!(movie.checkpoints.len(), 4);
+        let sum = u16::from_le_bytes([nes.cpu.bus.ram[0], nes.cpu.bus.ram[1]]);
+        assert!(sum > 0);
+
+        let mut other = joypad_nes();
+        other.run_frame();
+        movie.verify(&mut other).unwrap();
+        // only memory counts, not how the emulator got there
+        let checkpoint = Movie::checkpoint(&other);
+        other.cpu.cycles += 1;
+        assert_eq!(Movie::checkpoint(&other), checkpoint);
+        other.cpu.bus.ppu.oam[0] ^= 1;
+        assert_ne!(Movie::checkpoint(&other), checkpoint);
+
+        movie.frames[25].pads[0] = Controller::B;
+        assert!(matches!(
+            movie.verify(&mut other),
+            Err(MovieError::Desync { frame: 29, .. })
+        ));
+
+        movie.rom_md5[0] ^= 1;
+        assert!(matches!(
+            movie.begin(&mut other),
+            Err(MovieError::RomMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut nes = joypad_nes();
+        nes.run_frame();
+        let mut movie = sample_movie(&nes);
+        movie.start = Some(nes.save_state());
+        movie.add_checkpoints(&mut nes, 8).unwrap();
+        let bytes = movie.to_binary();
+        assert_eq!(Movie::from_binary(&bytes), Ok(movie));
+        let idle = Movie {
+            frames: vec![FrameInput::default(); 1000],
+            ..Movie::default()
+        };
+        let mut bytes = idle.to_binary();
+        assert!(bytes.len() < 64);
+        // a run count that would take gigabytes to expand
+        let count = bytes.len() - 7;
+        bytes[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Movie::from_binary(&bytes),
+            Err(MovieError::TooLong {
+                frames: u32::MAX as u64
+            })
+        );
+        assert_eq!(
+            Movie::from_binary(b"ICMV\x09\x00"),
+            Err(MovieError::UnsupportedVersion {
+                found: 9,
+                expected: Movie::VERSION
+            })
+        );
+        assert_eq!(Movie::from_binary(b"ICM"), Err(MovieError::BadMagic));
+        assert_eq!(
+            Movie::from_binary(b"FCMV\x01\x00"),
+            Err(MovieError::BadMagic)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::cartridge::mapper::Mapper;
+use crate::cheats::game_genie::Patch;
+use crate::memory::bus::Bus;
+use crate::nes::cdl::CodeDataLog;
+use crate::nes::controller::Controller;
+use crate::ppu::ppu::Ppu;
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+/// The NES CPU memory map: 2K of RAM, the PPU registers, the APU/IO registers and the cartridge
+pub struct NesBus {
+    pub ram: [u8; 0x800],
+    pub ppu: Ppu,
+    pub mapper: Box<dyn Mapper>,
+    /// APU and IO registers, latched on write but not emulated yet
+    pub io: [u8; 0x18],
+    /// the joypads in ports 1 and 2, read through $4016 and $4017
+    pub controllers: [Controller; 2],
+    /// the page an OAM DMA was just triggered for, `Nes::step` copies it once the instruction
+    /// that wrote $4014 is done
+    pub dma_page: Option<u8>,
+    /// Game Genie codes overriding cartridge reads, set by `Cheats::apply`
+    pub patches: Vec<Patch>,
+    /// marks the ROM bytes the console uses while it's set
+    pub cdl: Option<CodeDataLog>,
+}
+
+impl NesBus {
+    const OAM_DMA: u16 = 0x4014;
+    const APU_STATUS: u16 = 0x4015;
+    /// the $4015 bit that starts DMC sample playback
+    const APU_STATUS_DMC: u8 = 1 << 4;
+    const JOYPAD_1: u16 = 0x4016;
+    const JOYPAD_2: u16 = 0x4017;
+    /// the upper bits of a joypad read are open bus, which is almost always $40 from the address
+    const JOYPAD_OPEN_BUS: u8 = 0x40;
+
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
+        Self {
+            ram: [0; 0x800],
+            ppu: Ppu::new(),
+            mapper,
+            io: [0; 0x18],
+            controllers: [Controller::new(); 2],
+            dma_page: None,
+            patches: Vec::new(),
+            cdl: None,
+        }
+    }
+    /// lets the rest of the console catch up with `cycles` CPU cycles
+    pub fn tick(&mut self, cycles: u32) {
+        let frame = self.ppu.frame;
+        self.ppu.tick(cycles * 3);
+        self.mapper.tick(cycles);
+        if let Some(cdl) = &mut self.cdl {
+            if self.ppu.frame != frame {
+                cdl.render(&self.ppu, self.mapper.as_ref());
+            }
+        }
+    }
+    /// RAM, the latched APU and IO registers, then the PPU and the mapper
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.block(&self.ram);
+        state.block(&self.io);
+        for controller in &self.controllers {
+            let (shift, strobe) = controller.latch();
+            state.u8(controller.buttons);
+            state.u8(shift);
+            state.bool(strobe);
+        }
+        self.ppu.save_state(state);
+        self.mapper.save_state(state);
+    }
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.block_into(&mut self.ram, "RAM")?;
+        state.block_into(&mut self.io, "APU")?;
+        for controller in &mut self.controllers {
+            controller.buttons = state.u8()?;
+            let shift = state.u8()?;
+            controller.set_latch(shift, state.bool()?);
+        }
+        self.ppu.load_state(state)?;
+        self.mapper.load_state(state)
+    }
+    fn patched(&self, address: u16, value: u8) -> u8 {
+        self.patches
+            .iter()
+            .fold(value, |value, patch| patch.apply(address, value))
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x7FF],
+            0x2000..=0x3FFF => {
+                if let Some(cdl) = &mut self.cdl {
+                    cdl.read_ppu_register(address, &self.ppu, self.mapper.as_ref());
+                }
+                self.ppu.read_register(address, self.mapper.as_ref())
+            }
+            Self::JOYPAD_1 => self.controllers[0].read() | Self::JOYPAD_OPEN_BUS,
+            Self::JOYPAD_2 => self.controllers[1].read() | Self::JOYPAD_OPEN_BUS,
+            0x4000..=0x401F => 0,
+            _ => {
+                let value = self.mapper.read_prg(address);
+                if let Some(cdl) = &mut self.cdl {
+                    cdl.read_prg(address, self.mapper.as_ref());
+                }
+                self.patched(address, value)
+            }
+        }
+    }
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x7FF] = value,
+            0x2000..=0x3FFF => self
+                .ppu
+                .write_register(address, value, self.mapper.as_mut()),
+            Self::OAM_DMA => self.dma_page = Some(value),
+            Self::JOYPAD_1 => {
+                self.io[address as usize - 0x4000] = value;
+                for controller in &mut self.controllers {
+                    controller.write(value);
+                }
+            }
+            Self::APU_STATUS => {
+                self.io[address as usize - 0x4000] = value;
+                if let Some(cdl) = &mut self.cdl {
+                    if value & Self::APU_STATUS_DMC != 0 {
+                        cdl.play_samples(self.io[0x12], self.io[0x13], self.mapper.as_ref());
+                    }
+                }
+            }
+            0x4000..=0x4017 => self.io[address as usize - 0x4000] = value,
+            0x4018..=0x401F => {}
+            _ => self.mapper.write_prg(address, value),
+        }
+    }
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x7FF],
+            0x2000..=0x3FFF => self.ppu.peek_register(address, self.mapper.as_ref()),
+            Self::JOYPAD_1 => self.controllers[0].peek() | Self::JOYPAD_OPEN_BUS,
+            Self::JOYPAD_2 => self.controllers[1].peek() | Self::JOYPAD_OPEN_BUS,
+            0x4000..=0x401F => 0,
+            _ => self.patched(address, self.mapper.peek_prg(address)),
+        }
+    }
+    fn dummy_read(&mut self, address: u16) -> u8 {
+        // the CPU doesn't use the byte, so it's neither code nor data to the log
+        let cdl = self.cdl.take();
+        let value = self.read(address);
+        self.cdl = cdl;
+        value
+    }
+    fn on_cycle(&mut self) {
+        self.tick(1);
+    }
+    fn take_nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+    fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+    fn prg_offset(&self, address: u16) -> Option<usize> {
+        self.mapper.prg_offset(address)
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use crate::cartridge::mapper::Mapper;
+use crate::cpu::opcodes::AddressingMode;
+use crate::ppu::ppu::Ppu;
+
+/// A code/data log: a flag byte for every byte of PRG-ROM and CHR-ROM saying how the console
+/// used it, in the `.cdl` layout FCEUX reads and writes, PRG first and then CHR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    /// the bytes of the instruction the CPU is fetching, reads of anything else are data
+    fetching: (u16, u16),
+}
+
+impl CodeDataLog {
+    pub const CODE: u8 = 0x01;
+    pub const DATA: u8 = 0x02;
+    /// which 8K window of $8000-$FFFF the byte was last seen in, two bits shifted by 2
+    pub const WINDOW: u8 = 0x0C;
+    /// the target of a `JMP ($xxxx)`
+    pub const INDIRECT_CODE: u8 = 0x10;
+    /// read through a `($xx,X)` or `($xx),Y` pointer
+    pub const INDIRECT_DATA: u8 = 0x20;
+    /// DMC sample bytes
+    pub const PCM: u8 = 0x40;
+
+    pub const RENDERED: u8 = 0x01;
+    /// read by the CPU through $2007
+    pub const READ: u8 = 0x02;
+
+    /// an empty log sized for the cartridge's ROMs
+    pub fn new(mapper: &dyn Mapper) -> Self {
+        Self {
+            prg: vec![0; mapper.prg_rom().len()],
+            chr: vec![0; mapper.chr_rom().len()],
+            fetching: (0, 0),
+        }
+    }
+    /// Reads a `.cdl` file to carry on logging into, it has to be for a ROM of the same size
+    pub fn from_bytes(bytes: &[u8], mapper: &dyn Mapper) -> Result<Self, String> {
+        let mut log = Self::new(mapper);
+        if bytes.len() != log.prg.len() + log.chr.len() {
+            return Err(format!(
+                "{} bytes is the wrong size for {}K of PRG-ROM and {}K of CHR-ROM",
+                bytes.len(),
+                log.prg.len() / 1024,
+                log.chr.len() / 1024
+            ));
+        }
+        let (prg, chr) = bytes.split_at(log.prg.len());
+        log.prg.copy_from_slice(prg);
+        log.chr.copy_from_slice(chr);
+        Ok(log)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    fn mark_prg(&mut self, address: u16, flags: u8, mapper: &dyn Mapper) {
+        if let Some(offset) = mapper.prg_offset(address) {
+            self.prg[offset] |= flags | ((address >> 11) as u8 & Self::WINDOW);
+        }
+    }
+    fn mark_chr(&mut self, address: u16, len: u16, flags: u8, mapper: &dyn Mapper) {
+        for address in address..address + len {
+            if let Some(offset) = mapper.chr_offset(address & 0x1FFF) {
+                self.chr[offset] |= flags;
+            }
+        }
+    }
+
+    /// Call before the CPU runs the `len` byte instruction at `address`
+    pub fn fetching(&mut self, address: u16, len: u16) {
+        self.fetching = (address, len);
+    }
+    /// Call after the instruction ran, with the address its operand resolved to and where it
+    /// left the program counter, to catch indirect jumps and pointer reads
+    pub fn executed(&mut self, mode: AddressingMode, operand: u16, pc: u16, mapper: &dyn Mapper) {
+        match mode {
+            AddressingMode::Indirect => self.mark_prg(pc, Self::INDIRECT_CODE, mapper),
+            AddressingMode::IndirectX | AddressingMode::IndirectY => {
+                self.mark_prg(operand, Self::INDIRECT_DATA, mapper)
+            }
+            _ => {}
+        }
+        self.fetching = (0, 0);
+    }
+    /// Call on every CPU read of cartridge space
+    pub fn read_prg(&mut self, address: u16, mapper: &dyn Mapper) {
+        let (start, len) = self.fetching;
+        let flags = if address.wrapping_sub(start) < len {
+            Self::CODE
+        } else {
+            Self::DATA
+        };
+        self.mark_prg(address, flags, mapper);
+    }
+    /// Call before every CPU read of a PPU register, reads of $2007 are CHR fetches at `v`
+    pub fn read_ppu_register(&mut self, address: u16, ppu: &Ppu, mapper: &dyn Mapper) {
+        let v = ppu.v & 0x3FFF;
+        if address & 7 == 7 && v < 0x2000 {
+            self.mark_chr(v, 1, Self::READ, mapper);
+        }
+    }
+    /// Call when the DMC starts playing, with its $4012 address and $4013 length registers.
+    /// The samples wrap around from $FFFF to $8000 like the DMC's reads do
+    pub fn play_samples(&mut self, address: u8, length: u8, mapper: &dyn Mapper) {
+        let start = 0xC000 + address as u16 * 64;
+        for offset in 0..=length as u16 * 16 {
+            self.mark_prg(start.wrapping_add(offset) | 0x8000, Self::PCM, mapper);
+        }
+    }
+    /// Call at the end of every frame. The PPU doesn't render yet, so the tiles the background
+    /// and sprites would show with the scroll, nametables and OAM as they are now count as drawn
+    pub fn render(&mut self, ppu: &Ppu, mapper: &dyn Mapper) {
+        if ppu.mask & Ppu::MASK_BACKGROUND != 0 {
+            let table = if ppu.ctrl & Ppu::CTRL_BACKGROUND_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            let (left, top) = ppu.scroll();
+            // one row and column more than fits, for fine scrolling
+            for row in 0..=30 {
+                for column in 0..=32 {
+                    let (x, y) = ((left + column * 8) % 512, (top + row * 8) % 480);
+                    let base = 0x2000 + (y / 240 * 2 + x / 256) as u16 * 0x400;
+                    let index = (y % 240 / 8 * 32 + x % 256 / 8) as u16;
+                    let tile = ppu.read(base + index, mapper) as u16;
+                    self.mark_chr(table + tile * 16, 16, Self::RENDERED, mapper);
+                }
+            }
+        }
+        if ppu.mask & Ppu::MASK_SPRITES != 0 {
+            let tall = ppu.ctrl & Ppu::CTRL_TALL_SPRITES != 0;
+            // sprites at $EF or below the screen aren't drawn
+            for sprite in ppu.oam.chunks(4).filter(|sprite| sprite[0] < 0xEF) {
+                let tile = sprite[1] as u16;
+                if tall {
+                    let address = (tile & 1) * 0x1000 + (tile & 0xFE) * 16;
+                    self.mark_chr(address, 32, Self::RENDERED, mapper);
+                } else if ppu.ctrl & Ppu::CTRL_SPRITE_TABLE != 0 {
+                    self.mark_chr(0x1000 + tile * 16, 16, Self::RENDERED, mapper);
+                } else {
+                    self.mark_chr(tile * 16, 16, Self::RENDERED, mapper);
+                }
+            }
+        }
+    }
+}
+
+/// How much of the ROM has been seen, e.g.
+/// `PRG: 1234 code, 567 data, 89 PCM, 30878 unused; CHR: 4096 drawn, 0 read, 4096 unused`
+impl fmt::Display for CodeDataLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |log: &[u8], flags: u8| log.iter().filter(|&&byte| byte & flags != 0).count();
+        let unused = |log: &[u8]| log.iter().filter(|&&byte| byte == 0).count();
+        write!(
+            f,
+            "PRG: {} code, {} data, {} PCM, {} unused; CHR: {} drawn, {} read, {} unused",
+            count(&self.prg, Self::CODE | Self::INDIRECT_CODE),
+            count(&self.prg, Self::DATA | Self::INDIRECT_DATA),
+            count(&self.prg, Self::PCM),
+            unused(&self.prg),
+            count(&self.chr, Self::RENDERED),
+            count(&self.chr, Self::READ),
+            unused(&self.chr)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::nes::nes::Nes;
+
+    #[test]
+    fn logs_a_frame() {
+        let program = asm!(
+            "
+            .org $C000
+    reset:  LDA #$00
+            STA $00
+            LDA #$C1
+            STA $01
+            LDY #1
+            LDA ($00),Y     ; indirect data at $C101
+            LDA $C100
+            LDX #1
+            LDA $C2FF,X     ; data at $C300, a dummy read of $C200 first
+            JSR sub
+            JMP ($C102)
+    target: LDA #$10
+            STA $2006
+            STA $2006
+            LDA $2007       ; CHR $1010
+            LDA #8          ; one sample byte at $C200
+            STA $4012
+            LDA #0
+            STA $4013
+            LDA #$10
+            STA $4015
+            LDA #$08        ; background on
+            STA $2001
+    idle:   JMP idle
+    sub:    RTS
+            .byte $EA       ; dummy read by the RTS
+            .org $C100
+            .byte 1, 2
+            .word target
+            .org $FFFA
+            .word reset, reset, reset
+            "
+        );
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        program.patch(&mut prg, 0xC000).unwrap();
+        rom.extend(prg);
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let mut nes = Nes::from_ines(&rom).unwrap();
+        nes.cpu.bus.cdl = Some(CodeDataLog::new(nes.cpu.bus.mapper.as_ref()));
+        nes.run_frame();
+        nes.run_frame();
+
+        let log = nes.cpu.bus.cdl.take().unwrap();
+        let window = 0x08;
+        assert_eq!(log.prg[0x0000], CodeDataLog::CODE | window);
+        assert_eq!(log.prg[0x0100], CodeDataLog::DATA | window);
+        assert_eq!(
+            log.prg[0x0101],
+            CodeDataLog::DATA | CodeDataLog::INDIRECT_DATA | window
+        );
+        assert_eq!(log.prg[0x0102], CodeDataLog::DATA | window);
+        assert_eq!(log.prg[0x0300], CodeDataLog::DATA | window);
+        assert_eq!(
+            log.prg[0x001A],
+            CodeDataLog::CODE | CodeDataLog::INDIRECT_CODE | window
+        );
+        assert_eq!(log.prg[0x0200], CodeDataLog::PCM | window);
+        assert_eq!(log.prg[0x0201], 0);
+        assert_eq!(log.prg[0x003C], CodeDataLog::CODE | window);
+        assert_eq!(log.prg[0x003D], 0);
+        assert_eq!(log.chr[0x1010], CodeDataLog::READ);
+        assert_eq!(log.chr[0x000F], CodeDataLog::RENDERED);
+        assert_eq!(log.chr[0x0010], 0);
+        assert_eq!(
+            log.to_string(),
+            "PRG: 61 code, 5 data, 1 PCM, 16317 unused; CHR: 16 drawn, 1 read, 8175 unused"
+        );
+
+        let mapper = nes.cpu.bus.mapper.as_ref();
+        assert_eq!(CodeDataLog::from_bytes(&log.to_bytes(), mapper), Ok(log));
+        assert!(CodeDataLog::from_bytes(&[0; 16], mapper).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+/// A standard joypad: a latch of the eight buttons shifted out one bit per read of $4016/$4017
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Controller {
+    /// the buttons held right now, `A` in bit 0 through `RIGHT` in bit 7
+    pub buttons: u8,
+    shift: u8,
+    /// while set the latch keeps reloading and reads keep returning `A`
+    strobe: bool,
+}
+
+impl Controller {
+    pub const A: u8 = 1 << 0;
+    pub const B: u8 = 1 << 1;
+    pub const SELECT: u8 = 1 << 2;
+    pub const START: u8 = 1 << 3;
+    pub const UP: u8 = 1 << 4;
+    pub const DOWN: u8 = 1 << 5;
+    pub const LEFT: u8 = 1 << 6;
+    pub const RIGHT: u8 = 1 << 7;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+    /// the next button in line, after all eight an official pad reads 1s
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            self.shift = self.shift >> 1 | 0x80;
+        }
+        bit
+    }
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons & 1
+        } else {
+            self.shift & 1
+        }
+    }
+    /// the latch as `(shift, strobe)`, for save states
+    pub fn latch(&self) -> (u8, bool) {
+        (self.shift, self.strobe)
+    }
+    pub fn set_latch(&mut self, shift: u8, strobe: bool) {
+        self.shift = shift;
+        self.strobe = strobe;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_out_buttons() {
+        let mut controller = Controller::new();
+        controller.buttons = Controller::A | Controller::START | Controller::RIGHT;
+        controller.write(1);
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+        controller.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+}
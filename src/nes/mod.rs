@@ -0,0 +1,5 @@
+pub mod bus;
+pub mod cdl;
+pub mod controller;
+#[allow(clippy::module_inception)]
+pub mod nes;
@@ -0,0 +1,307 @@
+use crate::cartridge::cartridge::{Cartridge, CartridgeError};
+use crate::cartridge::mapper;
+use crate::cpu::cpu::Cpu;
+use crate::cpu::flags::Flags;
+use crate::memory::memory::RamPattern;
+use crate::nes::bus::NesBus;
+use crate::ppu::ppu::Ppu;
+use crate::state::state::{self, StateError, StateReader, StateWriter};
+
+/// A whole console: the CPU with the PPU and cartridge hanging off its bus
+pub struct Nes {
+    pub cpu: Cpu<NesBus>,
+    /// CRC-32 of the loaded ROM, save states only load into the game that made them
+    pub rom_crc: u32,
+    /// MD5 of the loaded ROM, for movie files
+    pub rom_md5: [u8; 16],
+    /// what RAM holds after `power`
+    pub ram_pattern: RamPattern,
+}
+
+impl Nes {
+    /// the PPU register OAM DMA writes to
+    const OAM_DATA: u16 = 0x2004;
+
+    pub fn new(cartridge: Cartridge) -> Result<Self, CartridgeError> {
+        let rom_crc = cartridge.crc32();
+        let rom_md5 = cartridge.md5();
+        let bus = NesBus::new(mapper::from_cartridge(cartridge)?);
+        let mut cpu = Cpu::with_bus(bus);
+        cpu.reset();
+        Ok(Self {
+            cpu,
+            rom_crc,
+            rom_md5,
+            ram_pattern: RamPattern::default(),
+        })
+    }
+    pub fn from_ines(rom: &[u8]) -> Result<Self, CartridgeError> {
+        Self::new(Cartridge::from_ines(rom)?)
+    }
+    /// powers the console back on with RAM filled by `pattern`
+    pub fn with_ram_pattern(mut self, pattern: RamPattern) -> Self {
+        self.ram_pattern = pattern;
+        self.power();
+        self
+    }
+    /// presses the reset button
+    pub fn reset(&mut self) {
+        self.cpu.bus.ppu.reset();
+        self.cpu.reset();
+    }
+    /// switches the console off and on again, only the cartridge's memory survives
+    pub fn power(&mut self) {
+        let bus = &mut self.cpu.bus;
+        self.ram_pattern.fill(&mut bus.ram);
+        bus.io = [0; 0x18];
+        bus.dma_page = None;
+        bus.ppu = Ppu::new();
+        for controller in &mut bus.controllers {
+            controller.set_latch(0, false);
+        }
+        self.cpu.accumulator = 0;
+        self.cpu.idx = 0;
+        self.cpu.idy = 0;
+        self.cpu.flags = Flags::default();
+        self.cpu.jammed = false;
+        self.cpu.reset();
+    }
+
+    /// Snapshots the whole machine: a header with the format version and ROM checksum,
+    /// then the CPU, RAM, the values latched in the APU and IO registers, PPU and mapper.
+    /// The APU's channels aren't emulated, so their timers, length counters and sequencer
+    /// aren't in it
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes.extend_from_slice(&state::MAGIC);
+        state.u16(state::VERSION);
+        state.u32(self.rom_crc);
+        self.cpu.save_state(&mut state);
+        self.cpu.bus.save_state(&mut state);
+        state.bytes
+    }
+    /// Restores a snapshot from `save_state`, the machine is left as it was when it won't load
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = self.read_state(bytes);
+        if result.is_err() {
+            self.read_state(&backup)
+                .expect("the machine's own state loads back");
+        }
+        result
+    }
+    fn read_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(bytes);
+        for byte in state::MAGIC {
+            if state.u8().map_err(|_| StateError::BadMagic)? != byte {
+                return Err(StateError::BadMagic);
+            }
+        }
+        let version = state.u16()?;
+        if version != state::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_crc = state.u32()?;
+        if rom_crc != self.rom_crc {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_crc,
+                actual: rom_crc,
+            });
+        }
+        self.cpu.load_state(&mut state)?;
+        self.cpu.bus.load_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(StateError::Invalid("trailing"));
+        }
+        Ok(())
+    }
+
+    /// Runs one instruction, plus any NMI or DMA it caused, and returns the cycles that took.
+    /// The rest of the console keeps up with the CPU a cycle at a time through the bus, and
+    /// the CPU polls the PPU's NMI and the mapper's IRQ there
+    pub fn step(&mut self) -> u32 {
+        let pc = self.cpu.program_counter;
+        let logged = self
+            .cpu
+            .bus
+            .cdl
+            .is_some()
+            .then(|| self.cpu.decode(self.cpu.peek_memory(pc)));
+        if let (Some(cdl), Some(opcode)) = (&mut self.cpu.bus.cdl, logged) {
+            cdl.fetching(pc, opcode.size());
+        }
+        let mut cycles = self.cpu.step() as u32;
+        if let (Some(cdl), Some(opcode)) = (&mut self.cpu.bus.cdl, logged) {
+            let (operand, pc) = (self.cpu.address_bus, self.cpu.program_counter);
+            cdl.executed(opcode.mode, operand, pc, self.cpu.bus.mapper.as_ref());
+        }
+        if let Some(page) = self.cpu.bus.dma_page.take() {
+            cycles += self.oam_dma(page);
+        }
+        cycles
+    }
+    /// OAM DMA halts the CPU for a cycle, and one more when that's an odd cycle so the copy
+    /// starts on a read, then reads every byte of the page and writes it to OAMDATA. The halted
+    /// CPU keeps reading the program counter
+    fn oam_dma(&mut self, page: u8) -> u32 {
+        let halt = 1 + self.cpu.cycles % 2;
+        for _ in 0..halt {
+            self.cpu.dummy_read_memory(self.cpu.program_counter);
+        }
+        for offset in 0..=0xFF {
+            let value = self.cpu.read_memory(u16::from_le_bytes([offset, page]));
+            self.cpu.write_memory(Self::OAM_DATA, value);
+        }
+        self.cpu.cycles += halt + 512;
+        (halt + 512) as u32
+    }
+    /// Runs until the PPU starts its next frame, or the CPU jams
+    pub fn run_frame(&mut self) {
+        let frame = self.cpu.bus.ppu.frame;
+        while self.cpu.bus.ppu.frame == frame && !self.cpu.jammed {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::cartridge::tests::nrom;
+
+    /// an NROM image with CHR-RAM that counts frames into RAM, PRG-RAM, VRAM and CHR-RAM
+    fn counter_rom(seed: u8) -> Vec<u8> {
+        let program = asm!(&format!(
+            "
+            .org $8000
+    reset:  LDA #$80
+            STA $2000       ; NMI on vblank
+    idle:   JMP idle
+    nmi:    INC $00
+            LDA $00
+            EOR #{}
+            STA $6000
+            LDX #$20
+            STX $2006
+            STA $2006
+            STA $2007
+            LDX #$00
+            STX $2006
+            STX $2006
+            STA $2007
+            RTI
+            .org $FFFA
+            .word nmi, reset, reset
+            ",
+            seed
+        ));
+        nrom(&program)
+    }
+
+    fn snapshot(nes: &Nes) -> (u16, u8, [u8; 0x800], u8, u8, u64) {
+        let bus = &nes.cpu.bus;
+        (
+            nes.cpu.program_counter,
+            nes.cpu.accumulator,
+            bus.ram,
+            bus.mapper.peek_prg(0x6000),
+            bus.ppu.vram[0x10],
+            bus.ppu.frame,
+        )
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut nes = Nes::from_ines(&counter_rom(0x5A)).unwrap();
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        let state = nes.save_state();
+        for _ in 0..7 {
+            nes.run_frame();
+        }
+        let expected = snapshot(&nes);
+        let chr = nes.cpu.bus.mapper.read_chr(0x0000);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu.bus.ram[0], 5);
+        for _ in 0..7 {
+            nes.run_frame();
+        }
+        assert_eq!(snapshot(&nes), expected);
+        assert_eq!(nes.cpu.bus.mapper.read_chr(0x0000), chr);
+        assert_eq!(nes.cpu.bus.ram[0], 12);
+    }
+
+    #[test]
+    fn oam_dma_runs_cycle_by_cycle() {
+        let program = asm!(
+            "
+            .org $8000
+    reset:  LDA #$02
+            STA $4014
+            LDX $00
+            STA $4014
+    idle:   JMP idle
+            .org $FFFA
+            .word reset, reset, reset
+            "
+        );
+        let mut nes = Nes::from_ines(&nrom(&program)).unwrap();
+        for (index, byte) in nes.cpu.bus.ram[0x200..0x300].iter_mut().enumerate() {
+            *byte = index as u8 ^ 0xA5;
+        }
+        nes.step();
+        nes.cpu.record_accesses(true);
+        let mut dmas = Vec::new();
+        for _ in 0..3 {
+            let (cycles, dots) = (nes.cpu.cycles, nes.cpu.bus.ppu.dot as u64);
+            let taken = nes.step() as u64;
+            assert_eq!(nes.cpu.cycles, cycles + taken);
+            // the PPU was ticked for every cycle, DMA ones included
+            assert_eq!(nes.cpu.bus.ppu.dot as u64, (dots + taken * 3) % 341);
+            dmas.push(taken);
+        }
+        // whichever cycle the first STA ends on, the three cycle LDX flips the second's alignment
+        let halt = dmas[0] - 4 - 512;
+        assert!(halt == 1 || halt == 2);
+        assert_eq!(dmas, [4 + 512 + halt, 3, 4 + 512 + 3 - halt]);
+        assert_eq!(nes.cpu.bus.ppu.oam[0x00], 0xA5);
+        assert_eq!(nes.cpu.bus.ppu.oam[0xFF], 0x5A);
+        let accesses = nes.cpu.take_accesses();
+        assert_eq!(accesses.len() as u64, dmas.iter().sum::<u64>());
+        let copy = &accesses[4 + halt as usize..];
+        assert_eq!(copy[0].address, 0x0200);
+        assert_eq!((copy[1].address, copy[1].value), (0x2004, 0xA5));
+    }
+
+    #[test]
+    fn rejects_foreign_states() {
+        let mut nes = Nes::from_ines(&counter_rom(0x5A)).unwrap();
+        let mut state = nes.save_state();
+        let mut other = Nes::from_ines(&counter_rom(0xA5)).unwrap();
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::RomMismatch { .. })
+        ));
+        assert_eq!(nes.load_state(b"nope"), Err(StateError::BadMagic));
+        state[4..6].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        assert_eq!(
+            nes.load_state(&state),
+            Err(StateError::UnsupportedVersion(0xFFFF))
+        );
+        state[4..6].copy_from_slice(&state::VERSION.to_le_bytes());
+
+        // a state that breaks off halfway doesn't leave the machine half loaded
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let before = nes.save_state();
+        assert_eq!(
+            nes.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert!(nes.save_state() == before);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::cartridge::cartridge::Mirroring;
+use crate::cartridge::mapper::Mapper;
+use crate::state::state::{StateError, StateReader, StateWriter};
+
+/// The 2C02's registers and memories. It keeps NTSC time and raises NMIs, but doesn't render yet
+pub struct Ppu {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    pub oam_address: u8,
+    pub oam: [u8; 256],
+    /// the console's 2K of nametable RAM
+    pub vram: [u8; 0x800],
+    pub palette: [u8; 32],
+    /// loopy's current VRAM address
+    pub v: u16,
+    /// loopy's temporary VRAM address, the top left of the screen while rendering
+    pub t: u16,
+    /// fine X scroll
+    pub x: u8,
+    /// the write toggle shared by $2005 and $2006
+    pub w: bool,
+    /// $2007 reads lag one behind, except in palette space
+    pub read_buffer: u8,
+    /// last value written to a register, it shows up in the unused bits of reads
+    pub open_bus: u8,
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    nmi: bool,
+}
+
+impl Ppu {
+    pub const CTRL_INCREMENT: u8 = 1 << 2;
+    pub const CTRL_NMI: u8 = 1 << 7;
+    pub const MASK_BACKGROUND: u8 = 1 << 3;
+    pub const MASK_SPRITES: u8 = 1 << 4;
+    pub const MASK_RENDERING: u8 = Self::MASK_BACKGROUND | Self::MASK_SPRITES;
+    pub const STATUS_OVERFLOW: u8 = 1 << 5;
+    pub const STATUS_SPRITE_ZERO: u8 = 1 << 6;
+    pub const STATUS_VBLANK: u8 = 1 << 7;
+    pub const DOTS_PER_SCANLINE: u16 = 341;
+    pub const SCANLINES_PER_FRAME: u16 = 262;
+    pub const VBLANK_SCANLINE: u16 = 241;
+    pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+    pub fn new() -> Self {
+        Self {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_address: 0,
+            oam: [0; 256],
+            vram: [0; 0x800],
+            palette: [0; 32],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            open_bus: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nmi: false,
+        }
+    }
+    /// the reset line clears the control registers and the write toggle, memories survive it
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.w = false;
+        self.read_buffer = 0;
+    }
+
+    /// Advances the beam by `dots`, setting and clearing vblank on the way
+    pub fn tick(&mut self, dots: u32) {
+        for _ in 0..dots {
+            self.dot += 1;
+            // odd frames skip the last dot of the pre-render line while rendering
+            let skip = self.scanline == Self::PRE_RENDER_SCANLINE
+                && self.dot == Self::DOTS_PER_SCANLINE - 1
+                && self.frame % 2 == 1
+                && self.mask & Self::MASK_RENDERING != 0;
+            if self.dot == Self::DOTS_PER_SCANLINE || skip {
+                self.dot = 0;
+                self.scanline += 1;
+                if self.scanline == Self::SCANLINES_PER_FRAME {
+                    self.scanline = 0;
+                    self.frame += 1;
+                }
+            }
+            match (self.scanline, self.dot) {
+                (Self::VBLANK_SCANLINE, 1) => {
+                    self.status |= Self::STATUS_VBLANK;
+                    if self.ctrl & Self::CTRL_NMI != 0 {
+                        self.nmi = true;
+                    }
+                }
+                (Self::PRE_RENDER_SCANLINE, 1) => {
+                    self.status &=
+                        !(Self::STATUS_VBLANK | Self::STATUS_SPRITE_ZERO | Self::STATUS_OVERFLOW)
+                }
+                _ => {}
+            }
+        }
+    }
+    /// returns whether an NMI was raised since the last call, acknowledging it
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    /// CPU read of $2000-$3FFF, the registers are mirrored every 8 bytes
+    pub fn read_register(&mut self, address: u16, mapper: &dyn Mapper) -> u8 {
+        match address & 7 {
+            2 => {
+                let value = self.peek_register(address, mapper);
+                self.status &= !Self::STATUS_VBLANK;
+                self.w = false;
+                value
+            }
+            7 => {
+                let address = self.v & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    // palette reads are immediate, the buffer gets the nametable byte underneath
+                    self.read_buffer = self.read(address - 0x1000, mapper);
+                    (self.read(address, mapper) & 0x3F) | (self.open_bus & 0xC0)
+                } else {
+                    let value = self.read(address, mapper);
+                    std::mem::replace(&mut self.read_buffer, value)
+                };
+                self.increment_v();
+                value
+            }
+            _ => self.peek_register(address, mapper),
+        }
+    }
+    /// side effect free version of [`Ppu::read_register`]
+    pub fn peek_register(&self, address: u16, _mapper: &dyn Mapper) -> u8 {
+        match address & 7 {
+            2 => (self.status & 0xE0) | (self.open_bus & 0x1F),
+            4 => self.oam[self.oam_address as usize],
+            7 => self.read_buffer,
+            _ => self.open_bus,
+        }
+    }
+    /// CPU write of $2000-$3FFF
+    pub fn write_register(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        self.open_bus = value;
+        match address & 7 {
+            0 => {
+                let enabling_nmi = self.ctrl & Self::CTRL_NMI == 0 && value & Self::CTRL_NMI != 0;
+                if enabling_nmi && self.status & Self::STATUS_VBLANK != 0 {
+                    self.nmi = true;
+                }
+                self.ctrl = value;
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0b11) << 10);
+            }
+            1 => self.mask = value,
+            3 => self.oam_address = value,
+            4 => {
+                self.oam[self.oam_address as usize] = value;
+                self.oam_address = self.oam_address.wrapping_add(1);
+            }
+            5 => {
+                if self.w {
+                    self.t = (self.t & !0x73E0)
+                        | ((value as u16 & 0b111) << 12)
+                        | ((value as u16 & 0xF8) << 2);
+                } else {
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.x = value & 0b111;
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if self.w {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                } else {
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                }
+                self.w = !self.w;
+            }
+            7 => {
+                self.write(self.v & 0x3FFF, value, mapper);
+                self.increment_v();
+            }
+            _ => {}
+        }
+    }
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & Self::CTRL_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_address);
+        state.block(&self.oam);
+        state.block(&self.vram);
+        state.block(&self.palette);
+        state.u16(self.v);
+        state.u16(self.t);
+        state.u8(self.x);
+        state.bool(self.w);
+        state.u8(self.read_buffer);
+        state.u8(self.open_bus);
+        state.u16(self.scanline);
+        state.u16(self.dot);
+        state.u64(self.frame);
+        state.bool(self.nmi);
+    }
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_address = state.u8()?;
+        state.block_into(&mut self.oam, "OAM")?;
+        state.block_into(&mut self.vram, "VRAM")?;
+        state.block_into(&mut self.palette, "palette")?;
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.x = state.u8()?;
+        self.w = state.bool()?;
+        self.read_buffer = state.u8()?;
+        self.open_bus = state.u8()?;
+        self.scanline = state.u16()?;
+        self.dot = state.u16()?;
+        self.frame = state.u64()?;
+        self.nmi = state.bool()?;
+        Ok(())
+    }
+
+    /// reads the PPU's own address space, $0000-$3FFF
+    pub fn read(&self, address: u16, mapper: &dyn Mapper) -> u8 {
+        match address & 0x3FFF {
+            address @ 0x0000..=0x1FFF => mapper.read_chr(address),
+            address @ 0x2000..=0x3EFF => self.vram[Self::vram_index(address, mapper.mirroring())],
+            address => self.palette[Self::palette_index(address)],
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        match address & 0x3FFF {
+            address @ 0x0000..=0x1FFF => mapper.write_chr(address, value),
+            address @ 0x2000..=0x3EFF => {
+                self.vram[Self::vram_index(address, mapper.mirroring())] = value
+            }
+            address => self.palette[Self::palette_index(address)] = value,
+        }
+    }
+    /// folds the four logical nametables onto the 2K of physical RAM. Four screen boards carry
+    /// their own extra RAM which isn't emulated, so they get vertical mirroring for now
+    fn vram_index(address: u16, mirroring: Mirroring) -> usize {
+        let address = (address - 0x2000) % 0x1000;
+        let table = address / 0x400;
+        let page = match mirroring {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical | Mirroring::FourScreen => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+        (page * 0x400 + address % 0x400) as usize
+    }
+    /// $3F10/$3F14/$3F18/$3F1C are mirrors of the backdrop entries below them
+    fn palette_index(address: u16) -> usize {
+        let index = address as usize & 0x1F;
+        if index & 0x13 == 0x10 {
+            index & 0x0F
+        } else {
+            index
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::cartridge::Cartridge;
+    use crate::cartridge::mapper::Nrom;
+
+    fn chr_ram_mapper() -> Nrom {
+        let mut rom = b"NES\x1A\x01\x00\x01\x00".to_vec();
+        rom.resize(16 + 0x4000, 0);
+        Nrom::new(Cartridge::from_ines(&rom).unwrap())
+    }
+
+    #[test]
+    fn vblank_and_nmi() {
+        let mut mapper = chr_ram_mapper();
+        let mut ppu = Ppu::new();
+        ppu.write_register(0x2000, Ppu::CTRL_NMI, &mut mapper);
+        ppu.tick(241 * 341);
+        assert_eq!(ppu.status & Ppu::STATUS_VBLANK, 0);
+        ppu.tick(1);
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+
+        assert_ne!(ppu.read_register(0x2002, &mapper) & Ppu::STATUS_VBLANK, 0);
+        assert_eq!(ppu.read_register(0x2002, &mapper) & Ppu::STATUS_VBLANK, 0);
+
+        ppu.tick(21 * 341 - 1);
+        assert_eq!((ppu.frame, ppu.scanline, ppu.dot), (1, 0, 0));
+    }
+
+    #[test]
+    fn data_port_is_buffered() {
+        let mut mapper = chr_ram_mapper();
+        let mut ppu = Ppu::new();
+        for (address, value) in [(0x2400, 0x11), (0x2C00, 0x22), (0x3F10, 0x0F)] {
+            ppu.write_register(0x2006, (address >> 8) as u8, &mut mapper);
+            ppu.write_register(0x2006, address as u8, &mut mapper);
+            ppu.write_register(0x2007, value, &mut mapper);
+        }
+        // vertical mirroring folds $2C00 onto $2400
+        ppu.write_register(0x2006, 0x24, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.read_register(0x2007, &mapper);
+        assert_eq!(ppu.read_register(0x2007, &mapper), 0x22);
+        assert_eq!(ppu.palette[0], 0x0F);
+    }
+}
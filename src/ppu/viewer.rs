@@ -0,0 +1,309 @@
+use crate::cartridge::mapper::Mapper;
+use crate::ppu::ppu::Ppu;
+
+/// The colours the 2C02 outputs for each of its 64 palette entries, as RGB
+pub const SYSTEM_PALETTE: [[u8; 3]; 64] = [
+    [84, 84, 84],
+    [0, 30, 116],
+    [8, 16, 144],
+    [48, 0, 136],
+    [68, 0, 100],
+    [92, 0, 48],
+    [84, 4, 0],
+    [60, 24, 0],
+    [32, 42, 0],
+    [8, 58, 0],
+    [0, 64, 0],
+    [0, 60, 0],
+    [0, 50, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [152, 150, 152],
+    [8, 76, 196],
+    [48, 50, 236],
+    [92, 30, 228],
+    [136, 20, 176],
+    [160, 20, 100],
+    [152, 34, 32],
+    [120, 60, 0],
+    [84, 90, 0],
+    [40, 114, 0],
+    [8, 124, 0],
+    [0, 118, 40],
+    [0, 102, 120],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [76, 154, 236],
+    [120, 124, 236],
+    [176, 98, 236],
+    [228, 84, 236],
+    [236, 88, 180],
+    [236, 106, 100],
+    [212, 136, 32],
+    [160, 170, 0],
+    [116, 196, 0],
+    [76, 208, 32],
+    [56, 204, 108],
+    [56, 180, 204],
+    [60, 60, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [168, 204, 236],
+    [188, 188, 236],
+    [212, 178, 236],
+    [236, 174, 236],
+    [236, 174, 212],
+    [236, 180, 176],
+    [228, 196, 144],
+    [204, 210, 120],
+    [180, 222, 120],
+    [168, 226, 144],
+    [152, 226, 180],
+    [160, 214, 228],
+    [160, 162, 160],
+    [0, 0, 0],
+    [0, 0, 0],
+];
+
+/// the colour the scroll viewport is outlined in
+const VIEWPORT: [u8; 3] = [255, 0, 0];
+
+/// An RGBA picture, row by row from the top left
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// a fully transparent image
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let index = (y * self.width + x) * 4;
+        self.pixels[index..index + 4].try_into().unwrap()
+    }
+    pub fn set(&mut self, x: usize, y: usize, [r, g, b]: [u8; 3]) {
+        let index = (y * self.width + x) * 4;
+        self.pixels[index..index + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .expect("writing to memory can't fail");
+        writer
+            .write_image_data(&self.pixels)
+            .expect("the buffer is the size of the image");
+        writer.finish().expect("writing to memory can't fail");
+        bytes
+    }
+}
+
+/// Debug views of what the PPU holds, decoded the way the hardware would draw it
+impl Ppu {
+    pub const CTRL_SPRITE_TABLE: u8 = 1 << 3;
+    pub const CTRL_BACKGROUND_TABLE: u8 = 1 << 4;
+    pub const CTRL_TALL_SPRITES: u8 = 1 << 5;
+
+    /// the RGB colour of an entry in palette RAM, 0-15 for the background and 16-31 for sprites
+    pub fn colour(&self, entry: u8, mapper: &dyn Mapper) -> [u8; 3] {
+        let index = self.read(0x3F00 | (entry as u16 & 0x1F), mapper);
+        SYSTEM_PALETTE[index as usize & 0x3F]
+    }
+
+    /// the 2 bit pixel of a tile at `address` in pattern table space
+    fn tile_pixel(&self, address: u16, x: u16, y: u16, mapper: &dyn Mapper) -> u8 {
+        let low = self.read(address + y, mapper);
+        let high = self.read(address + y + 8, mapper);
+        let bit = 7 - x;
+        (low >> bit & 1) | (high >> bit & 1) << 1
+    }
+
+    /// Both pattern tables side by side, 256x128, coloured with palette 0-3 for the background
+    /// or 4-7 for sprites
+    pub fn pattern_tables(&self, palette: u8, mapper: &dyn Mapper) -> Image {
+        let mut image = Image::new(256, 128);
+        for y in 0..128 {
+            for x in 0..256 {
+                let tile = (x / 128 * 256 + y / 8 * 16 + x % 128 / 8) as u16;
+                let pixel = self.tile_pixel(tile * 16, x as u16 % 8, y as u16 % 8, mapper);
+                let entry = if pixel == 0 {
+                    0
+                } else {
+                    (palette & 7) * 4 + pixel
+                };
+                image.set(x, y, self.colour(entry, mapper));
+            }
+        }
+        image
+    }
+
+    /// All four nametables as a 512x480 grid with the mirroring applied, and the part the
+    /// scroll registers put on screen outlined
+    pub fn nametables(&self, mapper: &dyn Mapper) -> Image {
+        let mut image = Image::new(512, 480);
+        let table = if self.ctrl & Self::CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        for y in 0..480 {
+            for x in 0..512 {
+                let base = 0x2000 + (y / 240 * 2 + x / 256) as u16 * 0x400;
+                let (column, row) = ((x % 256 / 8) as u16, (y % 240 / 8) as u16);
+                let tile = self.read(base + row * 32 + column, mapper) as u16;
+                let attribute = self.read(base + 0x3C0 + row / 4 * 8 + column / 4, mapper);
+                let shift = (row % 4 / 2) * 4 + (column % 4 / 2) * 2;
+                let palette = attribute >> shift & 3;
+                let pixel = self.tile_pixel(table + tile * 16, x as u16 % 8, y as u16 % 8, mapper);
+                let entry = if pixel == 0 { 0 } else { palette * 4 + pixel };
+                image.set(x, y, self.colour(entry, mapper));
+            }
+        }
+        let (left, top) = self.scroll();
+        for offset in 0..256 {
+            image.set((left + offset) % 512, top, VIEWPORT);
+            image.set((left + offset) % 512, (top + 239) % 480, VIEWPORT);
+        }
+        for offset in 0..240 {
+            image.set(left, (top + offset) % 480, VIEWPORT);
+            image.set((left + 255) % 512, (top + offset) % 480, VIEWPORT);
+        }
+        image
+    }
+
+    /// where the top left of the screen is in the nametable grid, from loopy's t and fine x
+    pub fn scroll(&self) -> (usize, usize) {
+        let t = self.t as usize;
+        let x = (t & 0x1F) << 3 | self.x as usize;
+        let y = (t >> 5 & 0x1F) << 3 | (t >> 12 & 7);
+        (x + (t >> 10 & 1) * 256, y + (t >> 11 & 1) * 240)
+    }
+
+    /// The 64 sprites in OAM order, 8 to a row, each 8x8 or 8x16 as the control register says,
+    /// flipped as they'd be drawn. Transparent pixels stay transparent
+    pub fn sprites(&self, mapper: &dyn Mapper) -> Image {
+        let tall = self.ctrl & Self::CTRL_TALL_SPRITES != 0;
+        let height = if tall { 16 } else { 8 };
+        let mut image = Image::new(64, 8 * height);
+        for (index, sprite) in self.oam.chunks(4).enumerate() {
+            let (tile, attributes) = (sprite[1] as u16, sprite[2]);
+            let (left, top) = (index % 8 * 8, index / 8 * height);
+            for y in 0..height as u16 {
+                let row = if attributes & 0x80 != 0 {
+                    height as u16 - 1 - y
+                } else {
+                    y
+                };
+                let address = if tall {
+                    (tile & 1) * 0x1000 + ((tile & 0xFE) + row / 8) * 16
+                } else if self.ctrl & Self::CTRL_SPRITE_TABLE != 0 {
+                    0x1000 + tile * 16
+                } else {
+                    tile * 16
+                };
+                for x in 0..8 {
+                    let column = if attributes & 0x40 != 0 { 7 - x } else { x };
+                    let pixel = self.tile_pixel(address, column, row % 8, mapper);
+                    if pixel != 0 {
+                        let entry = 16 + (attributes & 3) * 4 + pixel;
+                        image.set(
+                            left + x as usize,
+                            top + y as usize,
+                            self.colour(entry, mapper),
+                        );
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Palette RAM as swatches of 8x8, the background palettes on the top row and the
+    /// sprite palettes below
+    pub fn palettes(&self, mapper: &dyn Mapper) -> Image {
+        let mut image = Image::new(128, 16);
+        for y in 0..16 {
+            for x in 0..128 {
+                let entry = (y / 8 * 16 + x / 8) as u8;
+                image.set(x, y, self.colour(entry, mapper));
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::cartridge::{Cartridge, Mirroring};
+    use crate::cartridge::mapper::Nrom;
+
+    fn mapper() -> Nrom {
+        Nrom::new(Cartridge {
+            prg_rom: vec![0; 0x4000],
+            chr_rom: Vec::new(),
+            mapper: 0,
+            mirroring: Mirroring::Vertical,
+            battery: false,
+        })
+    }
+
+    #[test]
+    fn renders_views() {
+        let mut mapper = mapper();
+        let mut ppu = Ppu::new();
+        // tile 1: a diagonal of colour 3, tile 2: a solid block of colour 1
+        for row in 0..8 {
+            ppu.write(16 + row, 0x80 >> row, &mut mapper);
+            ppu.write(16 + row + 8, 0x80 >> row, &mut mapper);
+            ppu.write(32 + row, 0xFF, &mut mapper);
+        }
+        ppu.write(0x3F00, 0x0F, &mut mapper);
+        ppu.write(0x3F03, 0x30, &mut mapper);
+        ppu.write(0x3F05, 0x16, &mut mapper);
+        ppu.write(0x3F11, 0x2A, &mut mapper);
+
+        let patterns = ppu.pattern_tables(0, &mapper);
+        assert_eq!(patterns.get(8, 0), [236, 238, 236, 255]);
+        assert_eq!(patterns.get(9, 0), [0, 0, 0, 255]);
+
+        // tile 2 in the top left of the right nametable, using palette 1
+        ppu.write(0x2400, 2, &mut mapper);
+        ppu.write(0x27C0, 0b01, &mut mapper);
+        ppu.t = 0x0400 | 1;
+        ppu.x = 3;
+        let nametables = ppu.nametables(&mapper);
+        assert_eq!(nametables.get(258, 2), [152, 34, 32, 255]);
+        // the viewport starts 11 pixels into the right nametable and wraps around to the left
+        assert_eq!(nametables.get(267, 0), [255, 0, 0, 255]);
+        assert_eq!(nametables.get(10, 100), [255, 0, 0, 255]);
+        assert_eq!(nametables.get(258, 0), [152, 34, 32, 255]);
+
+        ppu.oam[1] = 2;
+        ppu.oam[2] = 0x40;
+        let sprites = ppu.sprites(&mapper);
+        assert_eq!((sprites.width, sprites.height), (64, 64));
+        assert_eq!(sprites.get(0, 0), [76, 208, 32, 255]);
+        assert_eq!(sprites.get(8, 0), [0, 0, 0, 0]);
+
+        let palettes = ppu.palettes(&mapper);
+        assert_eq!(palettes.get(3 * 8, 0), [236, 238, 236, 255]);
+        assert_eq!(palettes.get(8, 8), [76, 208, 32, 255]);
+        assert_eq!(&palettes.to_png()[1..4], b"PNG");
+    }
+}
@@ -0,0 +1,3 @@
+pub mod rewind;
+#[allow(clippy::module_inception)]
+pub mod state;
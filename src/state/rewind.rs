@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+
+use crate::movie::movie::FrameInput;
+use crate::nes::nes::Nes;
+
+/// Rewinds gameplay frame by frame.
+/// A snapshot is taken every `interval` frames. The newest is kept whole and every older one as
+/// the run-length encoded XOR against the snapshot after it, so going back only ever undoes the
+/// newest delta. Frames between snapshots are re-simulated with the input recorded for them when
+/// rewinding so none are skipped
+pub struct Rewind {
+    /// frames between snapshots
+    pub interval: u64,
+    /// bytes the snapshots may take up, the oldest are dropped beyond it
+    pub budget: usize,
+    /// frames stepped back per `step_back`
+    pub speed: usize,
+    /// frames run since recording started
+    frame: u64,
+    /// the newest snapshot and the frame it was taken before
+    latest: Option<(u64, Vec<u8>)>,
+    /// older snapshots, oldest first, each a delta against the one after it
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    delta_bytes: usize,
+    /// the input of every frame since the oldest snapshot, the first one for `first_input`
+    inputs: VecDeque<FrameInput>,
+    first_input: u64,
+    /// re-simulated states between the snapshot being rewound through and the current frame
+    pending: Vec<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(interval: u64, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            speed: 1,
+            frame: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            inputs: VecDeque::new(),
+            first_input: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Call once before running every frame, with the input the frame runs with
+    pub fn record(&mut self, nes: &Nes, input: &FrameInput) {
+        self.pending.clear();
+        self.inputs
+            .truncate(self.frame.saturating_sub(self.first_input) as usize);
+        if self.frame.is_multiple_of(self.interval) {
+            if self
+                .latest
+                .as_ref()
+                .is_some_and(|(frame, _)| *frame == self.frame)
+            {
+                self.pop();
+            }
+            self.push(nes.save_state());
+        }
+        if self.inputs.is_empty() {
+            self.first_input = self.frame;
+        }
+        self.inputs.push_back(*input);
+        self.frame += 1;
+    }
+
+    /// Puts the machine back `speed` frames, false once there's nothing left to rewind to
+    pub fn step_back(&mut self, nes: &mut Nes) -> bool {
+        let mut state = None;
+        for _ in 0..self.speed {
+            match self.previous(nes) {
+                Some(previous) => state = Some(previous),
+                None => break,
+            }
+        }
+        match state {
+            Some(state) => {
+                nes.load_state(&state)
+                    .expect("rewind states come from the running machine");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the state before the frame preceding the current one
+    fn previous(&mut self, nes: &mut Nes) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            if self.latest.as_ref()?.0 == self.frame {
+                self.pop();
+            }
+            let (base, state) = self.latest.as_ref()?;
+            nes.load_state(state)
+                .expect("rewind states come from the running machine");
+            self.pending.push(state.clone());
+            for frame in *base..self.frame - 1 {
+                if let Some(input) = self.inputs.get((frame - self.first_input) as usize) {
+                    input.apply(nes);
+                }
+                nes.run_frame();
+                self.pending.push(nes.save_state());
+            }
+        }
+        self.frame -= 1;
+        self.pending.pop()
+    }
+
+    /// frames run since recording started, less the ones rewound
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+    /// the bytes held by the snapshots
+    pub fn size(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+    /// how many snapshots are held
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if let Some((frame, previous)) = self.latest.take() {
+            let delta = encode_delta(&state, &previous);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back((frame, delta));
+        }
+        self.latest = Some((self.frame, state));
+        while self.size() > self.budget {
+            match self.deltas.pop_front() {
+                Some((_, delta)) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+        let oldest = self.deltas.front().map_or(self.frame, |(frame, _)| *frame);
+        while self.first_input < oldest && !self.inputs.is_empty() {
+            self.inputs.pop_front();
+            self.first_input += 1;
+        }
+    }
+    fn pop(&mut self) {
+        let previous = self.deltas.pop_back().map(|(frame, delta)| {
+            self.delta_bytes -= delta.len();
+            let (_, latest) = self.latest.as_ref().unwrap();
+            (frame, decode_delta(latest, &delta))
+        });
+        self.latest = previous;
+    }
+}
+
+/// Encodes `target` as its XOR against `base`: the target's length, then runs of
+/// `zeros, literal length, literal bytes` with the counts as LEB128
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = target
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ base.get(index).copied().unwrap_or(0))
+        .collect();
+    let mut delta = Vec::new();
+    write_leb128(&mut delta, target.len());
+    let mut index = 0;
+    while index < xor.len() {
+        let zeros = xor[index..].iter().take_while(|byte| **byte == 0).count();
+        index += zeros;
+        // short zero runs cost more to encode than to copy
+        let literal = (index..xor.len())
+            .find(|&end| xor[end..].iter().take(4).all(|byte| *byte == 0))
+            .unwrap_or(xor.len())
+            - index;
+        write_leb128(&mut delta, zeros);
+        write_leb128(&mut delta, literal);
+        delta.extend_from_slice(&xor[index..index + literal]);
+        index += literal;
+    }
+    delta
+}
+
+/// Undoes `encode_delta`
+pub fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_leb128(delta, &mut position);
+    let mut target: Vec<u8> = (0..len)
+        .map(|index| base.get(index).copied().unwrap_or(0))
+        .collect();
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_leb128(delta, &mut position);
+        let literal = read_leb128(delta, &mut position);
+        for byte in &delta[position..position + literal] {
+            target[index] ^= byte;
+            index += 1;
+        }
+        position += literal;
+    }
+    target
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::cartridge::tests::nrom;
+    use crate::movie::movie::tests::joypad_nes;
+
+    /// counts NMIs in $00 and writes the count all over RAM and VRAM
+    fn nes() -> Nes {
+        let program = asm!(
+            "
+            .org $8000
+    reset:  LDA #$80
+            STA $2000
+    idle:   JMP idle
+    nmi:    INC $00
+            LDX $00
+            TXA
+            STA $0300,X
+            LDA #$21
+            STA $2006
+            STX $2006
+            STX $2007
+            RTI
+            .org $FFFA
+            .word nmi, reset, reset
+            "
+        );
+        Nes::from_ines(&nrom(&program)).unwrap()
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let target = [1, 2, 0, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+        let delta = encode_delta(&base, &target);
+        assert_eq!(decode_delta(&base, &delta), target);
+        assert_eq!(decode_delta(&target, &encode_delta(&target, &base)), base);
+        assert_eq!(encode_delta(&base, &base), [12, 12, 0]);
+    }
+
+    #[test]
+    fn rewinds_every_frame() {
+        let mut nes = nes();
+        let mut rewind = Rewind::new(8, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..30 {
+            states.push(nes.save_state());
+            rewind.record(&nes, &FrameInput::default());
+            nes.run_frame();
+        }
+        assert_eq!(rewind.len(), 4);
+        assert!(rewind.size() < 3 * states[0].len());
+
+        for frame in (20..30).rev() {
+            assert!(rewind.step_back(&mut nes));
+            assert!(nes.save_state() == states[frame], "frame {}", frame);
+            assert_eq!(rewind.frame(), frame as u64);
+        }
+        // playing on from a rewound frame records over the future
+        for state in &states[20..26] {
+            assert!(nes.save_state() == *state);
+            rewind.record(&nes, &FrameInput::default());
+            nes.run_frame();
+        }
+        rewind.speed = 3;
+        for frame in (0..24).rev().step_by(3) {
+            assert!(rewind.step_back(&mut nes));
+            assert!(nes.save_state() == states[frame], "frame {}", frame);
+        }
+        // only two frames were left, a partial step still counts
+        assert!(rewind.step_back(&mut nes));
+        assert!(nes.save_state() == states[0]);
+        assert!(!rewind.step_back(&mut nes));
+    }
+
+    #[test]
+    fn replays_input_between_snapshots() {
+        let mut nes = joypad_nes();
+        let mut rewind = Rewind::new(8, usize::MAX);
+        let mut states = Vec::new();
+        for frame in 0..30u8 {
+            let input = FrameInput {
+                pads: [frame.wrapping_mul(37), 0],
+                ..FrameInput::default()
+            };
+            states.push(nes.save_state());
+            rewind.record(&nes, &input);
+            input.apply(&mut nes);
+            nes.run_frame();
+        }
+        rewind.speed = 2;
+        for frame in (0..=28).rev().step_by(2) {
+            assert!(rewind.step_back(&mut nes));
+            assert!(nes.save_state() == states[frame], "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn budget_drops_oldest() {
+        let mut nes = nes();
+        let state_len = nes.save_state().len();
+        let mut rewind = Rewind::new(1, state_len + 200);
+        for _ in 0..50 {
+            rewind.record(&nes, &FrameInput::default());
+            nes.run_frame();
+        }
+        assert!(rewind.size() <= state_len + 200);
+        assert!(rewind.len() > 1 && rewind.len() < 50);
+    }
+}
@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// the data doesn't start with the save state magic
+    BadMagic,
+    /// saved by a version of the format this build can't read
+    UnsupportedVersion(u16),
+    /// saved while a different ROM was loaded
+    RomMismatch { expected: u32, actual: u32 },
+    /// the data ends before the state does
+    Truncated,
+    /// a section doesn't match the hardware it's being loaded into
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            Self::RomMismatch { expected, actual } => write!(
+                f,
+                "save state belongs to another ROM (CRC32 {:08X}, this one is {:08X})",
+                actual, expected
+            ),
+            Self::Truncated => write!(f, "save state is truncated"),
+            Self::Invalid(section) => write!(f, "save state has a bad {} section", section),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub const MAGIC: [u8; 4] = *b"ICST";
+/// bumped whenever the layout of any section changes
+pub const VERSION: u16 = 4;
+
+/// Builds a save state, multi-byte values are little endian
+#[derive(Default)]
+pub struct StateWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    /// a length prefixed block of bytes
+    pub fn block(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// Reads a save state back in the order `StateWriter` wrote it
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or(StateError::Truncated)?;
+        self.position += len;
+        Ok(bytes)
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn block(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+    /// reads a block into `target`, which must be exactly as long as the saved one
+    pub fn block_into(
+        &mut self,
+        target: &mut [u8],
+        section: &'static str,
+    ) -> Result<(), StateError> {
+        let block = self.block()?;
+        if block.len() != target.len() {
+            return Err(StateError::Invalid(section));
+        }
+        target.copy_from_slice(block);
+        Ok(())
+    }
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u64(u64::MAX - 1);
+        writer.block(&[1, 2, 3]);
+
+        let mut reader = StateReader::new(&writer.bytes);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        let mut block = [0; 2];
+        assert_eq!(
+            reader.block_into(&mut block, "test"),
+            Err(StateError::Invalid("test"))
+        );
+        assert!(reader.is_empty());
+        assert_eq!(reader.u8(), Err(StateError::Truncated));
+    }
+}
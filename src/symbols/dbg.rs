@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use crate::symbols::symbols::{SymbolError, SymbolTable};
+
+/// The iNES header in front of PRG-ROM in a `.nes` file, segment offsets count it
+const INES_HEADER_LEN: usize = 16;
+/// the `line` record type of lines inside macro expansions, which point into the macro
+const MACRO_LINE: &str = "2";
+
+/// A segment of the linked program
+struct Segment {
+    start: u32,
+    /// where it starts in PRG-ROM, for segments written to ROM
+    offset: Option<usize>,
+}
+
+/// Splits `key=value,key="quoted, value"` into its pairs, quotes removed
+fn fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, c) in text.char_indices().chain([(text.len(), ',')]) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = text[start..index].split_once('=') {
+                    fields.insert(key, value.trim_matches('"'));
+                }
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// decimal, or hexadecimal with `0x`
+fn number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Reads the debug info ld65 writes with `--dbgfile`: the labels from its `sym` records and
+/// the source lines of its `line` records, tied to PRG-ROM offsets through the segments'
+/// offsets in the output file. Segments past `prg_len`, CHR-ROM, are left out
+pub fn parse(text: &str, prg_len: usize) -> Result<SymbolTable, SymbolError> {
+    let mut files = HashMap::new();
+    let mut segments = HashMap::new();
+    let mut spans = HashMap::new();
+    let mut names = HashMap::new();
+    // lines and symbols come before the segments they refer to
+    let mut lines = Vec::new();
+    let mut labels = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let Some((kind, rest)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let fields = fields(rest.trim());
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|value| number(value))
+                .ok_or_else(|| SymbolError {
+                    line: index + 1,
+                    message: format!("`{}` record without a valid `{}`", kind, key),
+                })
+        };
+        match kind {
+            "file" => {
+                files.insert(field("id")?, fields.get("name").copied().unwrap_or(""));
+            }
+            "seg" => {
+                let start = field("start")?;
+                let in_nes = fields
+                    .get("oname")
+                    .is_some_and(|name| name.ends_with(".nes"));
+                let offset = fields
+                    .get("ooffs")
+                    .and_then(|offset| number(offset))
+                    .and_then(|offset| {
+                        let header = if in_nes { INES_HEADER_LEN } else { 0 };
+                        (offset as usize).checked_sub(header)
+                    })
+                    .filter(|&offset| offset < prg_len);
+                segments.insert(field("id")?, Segment { start, offset });
+            }
+            "span" => {
+                spans.insert(field("id")?, (field("seg")?, field("start")?));
+            }
+            "line" if fields.get("type") != Some(&MACRO_LINE) => {
+                if let Some(span) = fields.get("span") {
+                    lines.push((field("file")?, field("line")?, span.to_string()));
+                }
+            }
+            "sym" => {
+                let name = fields.get("name").copied().unwrap_or("");
+                names.insert(field("id")?, name);
+                if fields.get("type") == Some(&"lab") {
+                    let parent = fields.get("parent").and_then(|parent| number(parent));
+                    let segment = fields.get("seg").and_then(|segment| number(segment));
+                    labels.push((name, parent, field("val")?, segment));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut symbols = SymbolTable::new();
+    // where a segment relative address ends up in PRG-ROM
+    let offset_of = |segment: Option<u32>, address: u32| {
+        let segment: &Segment = segments.get(&segment?)?;
+        Some(segment.offset? + address.checked_sub(segment.start)? as usize)
+    };
+    for (name, parent, value, segment) in labels {
+        // cheap locals like `@loop` are named after the label they belong to, `Main@loop`
+        let name = match parent.and_then(|parent| names.get(&parent)) {
+            Some(parent) => format!("{}{}", parent, name),
+            None => name.to_string(),
+        };
+        match offset_of(segment, value) {
+            Some(offset) => symbols.insert_banked(offset, value as u16, &name),
+            None => symbols.insert(value as u16, &name),
+        }
+    }
+    for (file, line, span_list) in lines {
+        let file = symbols.add_file(files.get(&file).copied().unwrap_or("?"));
+        for span in span_list.split('+').filter_map(number) {
+            let Some(&(segment, start)) = spans.get(&span) else {
+                continue;
+            };
+            let Some(base) = segments.get(&segment).map(|segment| segment.start) else {
+                continue;
+            };
+            let address = base + start;
+            let offset = offset_of(Some(segment), address);
+            symbols.add_line(address as u16, offset, file, line);
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::bus::Bus;
+    use crate::memory::memory::Memory;
+
+    const DBG: &str = r#"version	major=2,minor=0
+info	csym=0,file=2,lib=0,line=4,mod=1,scope=1,seg=4,span=4,sym=5,type=2
+file	id=0,name="src/main.s",size=410,mtime=0x66000000,mod=0
+file	id=1,name="src/bank, one.s",size=80,mtime=0x66000000,mod=0
+line	id=0,file=0,line=12,span=0
+line	id=1,file=0,line=14,span=1
+line	id=2,file=1,line=3,span=2
+line	id=3,file=0,line=40,type=2,span=3
+mod	id=0,name="main.o",file=0
+seg	id=0,name="HEADER",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=0
+seg	id=1,name="BANK0",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+seg	id=2,name="FIXED",start=0x00C000,size=0x4000,addrsize=absolute,type=ro,oname="game.nes",ooffs=16400
+seg	id=3,name="BSS",start=0x000300,size=0x0010,addrsize=absolute,type=rw
+span	id=0,seg=2,start=0,size=3
+span	id=1,seg=2,start=3,size=2
+span	id=2,seg=1,start=16,size=1
+span	id=3,seg=2,start=5,size=1
+scope	id=0,name="",mod=0,size=0
+sym	id=0,name="Reset",addrsize=absolute,scope=0,def=12,val=0xC000,seg=2,type=lab
+sym	id=1,name="@loop",addrsize=absolute,scope=0,parent=0,def=14,val=0xC003,seg=2,type=lab
+sym	id=2,name="Table",addrsize=absolute,scope=0,def=3,val=0x8010,seg=1,type=lab
+sym	id=3,name="score",addrsize=absolute,scope=0,def=5,val=0x300,seg=3,type=lab
+sym	id=4,name="LIVES",addrsize=zeropage,scope=0,def=6,val=0x3,type=equ
+"#;
+
+    /// memory with bank 0 of a 32K ROM at $8000 and bank 1 at $C000
+    struct Banked(Memory);
+
+    impl Bus for Banked {
+        fn read(&mut self, address: u16) -> u8 {
+            self.0.read(address)
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.0.write(address, value)
+        }
+        fn peek(&self, address: u16) -> u8 {
+            self.0.peek(address)
+        }
+        fn prg_offset(&self, address: u16) -> Option<usize> {
+            address.checked_sub(0x8000).map(usize::from)
+        }
+    }
+
+    #[test]
+    fn parses_ld65_debug_info() {
+        let symbols = parse(DBG, 0x8000).unwrap();
+        assert_eq!(symbols.address("Reset"), Some(0xC000));
+        assert_eq!(symbols.address("Reset@loop"), Some(0xC003));
+        assert_eq!(symbols.label(0x0300), Some("score"));
+        assert_eq!(symbols.address("LIVES"), None);
+
+        let bus = Banked(Memory::new());
+        assert_eq!(symbols.label_on(&bus, 0x8010), Some("Table"));
+        assert_eq!(symbols.label_on(&bus, 0xC003), Some("Reset@loop"));
+        assert_eq!(symbols.source_on(&bus, 0xC000), Some(("src/main.s", 12)));
+        assert_eq!(
+            symbols.source_on(&bus, 0x8010),
+            Some(("src/bank, one.s", 3))
+        );
+        assert_eq!(symbols.source_on(&bus, 0xC005), None);
+
+        let error = parse("seg\tid=0,start=nope", 0x8000).unwrap_err();
+        assert_eq!(error.line, 1);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod dbg;
+#[allow(clippy::module_inception)]
+pub mod symbols;
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::memory::bus::Bus;
+use crate::symbols::dbg;
+
+/// A line of a symbol file that couldn't be understood
+#[derive(Debug, PartialEq, Eq)]
+pub struct SymbolError {
+    /// 1-based line number, 0 when the file couldn't be read at all
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SymbolError {}
+
+/// A line of a source file, by its index in the table's file list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SourceLine {
+    file: usize,
+    line: u32,
+}
+
+/// Names for addresses, shared by the disassembler, tracer and debugger. Labels and source
+/// lines in PRG-ROM can be tied to a ROM offset as well, so every bank of a banked game has
+/// its own and the ones shown are those of the bank that's mapped in
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    /// every label by CPU address, whatever bank it's in
+    labels: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+    /// labels in PRG-ROM by offset
+    banked: HashMap<usize, String>,
+    files: Vec<String>,
+    lines: HashMap<u16, SourceLine>,
+    banked_lines: HashMap<usize, SourceLine>,
+}
+
+impl SymbolTable {
+    /// the size of the PRG-ROM banks `.nl` files are split into
+    const NL_BANK_LEN: usize = 0x4000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// names `address`, the first name given to an address is the one it's displayed with
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+    /// names the byte at `offset` in PRG-ROM, which the CPU sees at `address` when its bank is in
+    pub fn insert_banked(&mut self, offset: usize, address: u16, name: &str) {
+        self.banked
+            .entry(offset)
+            .or_insert_with(|| name.to_string());
+        self.insert(address, name);
+    }
+    /// the label of an address, from whichever bank named it first
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+    /// the label of an address with the banks as they're mapped on `bus`
+    pub fn label_on(&self, bus: &impl Bus, address: u16) -> Option<&str> {
+        match bus.prg_offset(address) {
+            // once any ROM offsets are known, ROM is only named through them
+            Some(offset) if !self.banked.is_empty() => self.banked.get(&offset).map(String::as_str),
+            _ => self.label(address),
+        }
+    }
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+    /// every name with its address, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.addresses
+            .iter()
+            .map(|(name, address)| (name.as_str(), *address))
+    }
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// the index of a source file for `add_line`, adding it if it's new
+    pub fn add_file(&mut self, name: &str) -> usize {
+        match self.files.iter().position(|file| file == name) {
+            Some(index) => index,
+            None => {
+                self.files.push(name.to_string());
+                self.files.len() - 1
+            }
+        }
+    }
+    /// records that the code or data at `address`, and `offset` in PRG-ROM when it's there,
+    /// came from a line of a source file
+    pub fn add_line(&mut self, address: u16, offset: Option<usize>, file: usize, line: u32) {
+        let source = SourceLine { file, line };
+        self.lines.entry(address).or_insert(source);
+        if let Some(offset) = offset {
+            self.banked_lines.entry(offset).or_insert(source);
+        }
+    }
+    /// the file and line an address was assembled from, with the banks as they're on `bus`
+    pub fn source_on(&self, bus: &impl Bus, address: u16) -> Option<(&str, u32)> {
+        let source = match bus.prg_offset(address) {
+            Some(offset) if !self.banked_lines.is_empty() => self.banked_lines.get(&offset),
+            _ => self.lines.get(&address),
+        }?;
+        Some((self.files[source.file].as_str(), source.line))
+    }
+
+    /// adds everything in `other`, names already taken here win
+    pub fn merge(&mut self, other: Self) {
+        for (address, name) in other.labels {
+            self.labels.entry(address).or_insert(name);
+        }
+        for (name, address) in other.addresses {
+            self.addresses.entry(name).or_insert(address);
+        }
+        for (offset, name) in other.banked {
+            self.banked.entry(offset).or_insert(name);
+        }
+        let files: Vec<usize> = other.files.iter().map(|file| self.add_file(file)).collect();
+        let remap = |source: SourceLine| SourceLine {
+            file: files[source.file],
+            line: source.line,
+        };
+        for (address, source) in other.lines {
+            self.lines.entry(address).or_insert(remap(source));
+        }
+        for (offset, source) in other.banked_lines {
+            self.banked_lines.entry(offset).or_insert(remap(source));
+        }
+    }
+
+    /// Parses a VICE label file, which is what `ld65 -Ln` writes: `al 00C000 .Reset`
+    pub fn parse_vice(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| SymbolError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                None => continue,
+                Some("al") => {}
+                Some(_) => return Err(error("expected an `al` directive")),
+            }
+            let address = fields.next().ok_or_else(|| error("missing address"))?;
+            // VICE prefixes the address with a memory space, `C:`
+            let address = address.rsplit(':').next().unwrap_or(address);
+            let address =
+                u32::from_str_radix(address, 16).map_err(|_| error("address isn't hexadecimal"))?;
+            let name = fields.next().ok_or_else(|| error("missing name"))?;
+            symbols.insert(address as u16, name.trim_start_matches('.'));
+        }
+        Ok(symbols)
+    }
+
+    /// Parses an FCEUX `.nl` file: `$C000#Reset#comment`, with an optional `/size` after the
+    /// address. FCEUX keeps RAM in `game.nes.ram.nl` and each 16K of PRG-ROM in
+    /// `game.nes.<bank>.nl`, `bank` is that number for the ROM files
+    pub fn parse_nl(text: &str, bank: Option<usize>) -> Result<Self, SymbolError> {
+        let mut symbols = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            // lines that don't start with an address carry on a multi-line comment
+            let Some(line) = line.trim().strip_prefix('$') else {
+                continue;
+            };
+            let mut fields = line.split('#');
+            let address = fields.next().unwrap().split('/').next().unwrap();
+            let address = u16::from_str_radix(address, 16).map_err(|_| SymbolError {
+                line: index + 1,
+                message: "address isn't hexadecimal".to_string(),
+            })?;
+            let name = fields.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            match bank {
+                Some(bank) if address >= 0x8000 => {
+                    let offset = bank * Self::NL_BANK_LEN + address as usize % Self::NL_BANK_LEN;
+                    symbols.insert_banked(offset, address, name);
+                }
+                _ => symbols.insert(address, name),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Parses a Mesen `.mlb` file: `P:1234:Name:comment`, with a memory type, an offset into
+    /// it or a range of them, a name and a comment. Both Mesen's one letter types and Mesen 2's
+    /// long ones are understood, types other than ROM, RAM and registers are skipped
+    pub fn parse_mlb(text: &str, prg_len: usize) -> Result<Self, SymbolError> {
+        let mut symbols = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| SymbolError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(4, ':');
+            let kind = fields.next().unwrap();
+            let offset = fields.next().ok_or_else(|| error("missing address"))?;
+            let offset = offset.split('-').next().unwrap();
+            let offset = usize::from_str_radix(offset, 16)
+                .map_err(|_| error("address isn't hexadecimal"))?;
+            let name = fields.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            match kind {
+                "P" | "NesPrgRom" => {
+                    symbols.insert_banked(offset, Self::rom_address(offset, prg_len), name)
+                }
+                "R" | "NesInternalRam" => symbols.insert(offset as u16 & 0x7FF, name),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => {
+                    symbols.insert(0x6000 | (offset as u16 & 0x1FFF), name)
+                }
+                "G" | "NesMemory" | "Register" => symbols.insert(offset as u16, name),
+                _ => {}
+            }
+        }
+        Ok(symbols)
+    }
+    /// A guess at where the CPU sees a PRG-ROM offset: the last 16K is where fixed banks and
+    /// the vectors are, anything before it is taken to be switched in at $8000
+    fn rom_address(offset: usize, prg_len: usize) -> u16 {
+        let window = if offset + Self::NL_BANK_LEN >= prg_len {
+            0xC000
+        } else {
+            0x8000
+        };
+        window | (offset % Self::NL_BANK_LEN) as u16
+    }
+
+    /// Loads a symbol file by its extension: ld65's `.dbg`, Mesen's `.mlb`, FCEUX's `.nl` or
+    /// anything else as a VICE label file. `prg_len` is the size of the game's PRG-ROM
+    pub fn load(path: &Path, prg_len: usize) -> Result<Self, SymbolError> {
+        let text = fs::read_to_string(path).map_err(|error| SymbolError {
+            line: 0,
+            message: error.to_string(),
+        })?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dbg") => dbg::parse(&text, prg_len),
+            Some("mlb") => Self::parse_mlb(&text, prg_len),
+            Some("nl") => {
+                // `game.nes.ram.nl` or `game.nes.3.nl`
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let bank = stem.rsplit('.').next().and_then(|bank| bank.parse().ok());
+                Self::parse_nl(&text, bank)
+            }
+            _ => Self::parse_vice(&text),
+        }
+    }
+    /// Loads every symbol file sitting next to a ROM: `game.dbg`, `game.mlb`, and FCEUX's
+    /// `game.nes.ram.nl` and `game.nes.<bank>.nl`
+    pub fn for_rom(rom: &Path, prg_len: usize) -> Result<Self, SymbolError> {
+        let mut paths = vec![rom.with_extension("dbg"), rom.with_extension("mlb")];
+        let nl = |suffix: String| {
+            let mut path = rom.as_os_str().to_owned();
+            path.push(suffix);
+            std::path::PathBuf::from(path)
+        };
+        paths.push(nl(".ram.nl".to_string()));
+        paths.extend(
+            (0..prg_len.div_ceil(Self::NL_BANK_LEN)).map(|bank| nl(format!(".{}.nl", bank))),
+        );
+        let mut symbols = Self::new();
+        for path in paths.iter().filter(|path| path.exists()) {
+            let loaded = Self::load(path, prg_len).map_err(|error| SymbolError {
+                line: error.line,
+                message: format!("{}: {}", path.display(), error.message),
+            })?;
+            symbols.merge(loaded);
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ld65_labels() {
+        let symbols =
+            SymbolTable::parse_vice("al 00C000 .Reset\nal C:C0F2 .NmiHandler\n\n").unwrap();
+        assert_eq!(symbols.label(0xC000), Some("Reset"));
+        assert_eq!(symbols.address("NmiHandler"), Some(0xC0F2));
+        assert_eq!(symbols.len(), 2);
+
+        let error = SymbolTable::parse_vice("al 00C000 .Reset\nal nope .Bad").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn parses_fceux_and_mesen_labels() {
+        let ram = SymbolTable::parse_nl("$0010#frame#counts up\n$0200/100#oam#\n\\more\n", None);
+        let bank = SymbolTable::parse_nl("$C0F2#NmiHandler#\n$C100##just a comment\n", Some(3));
+        let mut symbols = ram.unwrap();
+        symbols.merge(bank.unwrap());
+        assert_eq!(symbols.label(0x0200), Some("oam"));
+        assert_eq!(symbols.address("NmiHandler"), Some(0xC0F2));
+        assert_eq!(
+            symbols.banked.get(&(3 * 0x4000 + 0xF2)).unwrap(),
+            "NmiHandler"
+        );
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(SymbolTable::parse_nl("$zz#bad#", None).unwrap_err().line, 1);
+
+        let mesen = "P:1F000:Reset:entry\nR:0010-0011:pointer\nW:0100:save\nNesPrgRom:0040:Table\nS:0000::\n";
+        let symbols = SymbolTable::parse_mlb(mesen, 0x20000).unwrap();
+        assert_eq!(symbols.address("Reset"), Some(0xF000));
+        assert_eq!(symbols.address("Table"), Some(0x8040));
+        assert_eq!(symbols.label(0x0010), Some("pointer"));
+        assert_eq!(symbols.label(0x6100), Some("save"));
+        assert_eq!(symbols.len(), 4);
+    }
+}